@@ -5,10 +5,10 @@ use embedded_graphics::{
     prelude::*,
     primitives::{Circle, Line, PrimitiveStyle, Rectangle, StyledDrawable},
 };
-use waveshare_epd::epd5in79::Epd5in79Impl;
+use waveshare_epd::epd5in79::Epd5in79Linux;
 
 fn main() {
-    let mut epd_impl = Epd5in79Impl::default();
+    let mut epd_impl = Epd5in79Linux::default();
     {
         // binary color EPD.
         let mut epd_bin = epd_impl.as_binary();
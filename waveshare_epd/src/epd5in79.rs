@@ -1,12 +1,27 @@
 //! Implement the driver for **epd5in79**.
 //!
 //! This screen supports two colors ([`BinaryColor`], [`Gray2`]).
-//! This driver supports automatic color conversion.
+//! This driver supports automatic color conversion, [`Epd5in79Impl::as_binary_dithered`] and
+//! [`Epd5in79Impl::as_gray2_smoothed`] look at neighbouring pixels while converting.
+//!
+//! [`Epd5in79Impl`] works with any [embedded-hal](https://docs.rs/embedded-hal/latest/embedded_hal/)
+//! implementation, the `linux` feature provides [`Epd5in79Linux`] on top of `spidev` and `gpio-cdev`.
+//! [`Epd5in79Impl`] implements [`Epd`] for both colors.
+//! Use [`Epd5in79Builder`] to configure SPI speed, chunk size, timeouts and GPIO options.
+//! [`Epd5in79Impl::set_orientation`] maps the drawing to panels mounted in portrait,
+//! upside down or mirrored.
+//! [`Epd5in79Impl::read_temperature`] reads the sensor of the controller, see [`Temperature`],
+//! the temperature selects the waveforms from the [`WaveformBands`] of each [`DisplayMode`].
+//! [`Epd5in79Impl::load_custom_waveform`] loads a [`Waveform`] of your own.
+//! With the `async` feature, [`asynch::Epd5in79AsyncImpl`] provides the same driver on top of
+//! [embedded-hal-async](https://docs.rs/embedded-hal-async/latest/embedded_hal_async/).
+//! With the `virtual` feature, [`virtual_panel::VirtualEpd5in79`] runs the driver without hardware.
 //!
 //! # Examples
 //! ```no_run
-//! # use waveshare_epd::epd5in79::Epd5in79Impl;
-//! let mut epd_impl = Epd5in79Impl::default();
+//! # #[cfg(feature = "linux")] {
+//! # use waveshare_epd::epd5in79::Epd5in79Linux;
+//! let mut epd_impl = Epd5in79Linux::default();
 //! let mut epd_gray = epd_impl.as_gray2();
 //! // Draw some pixels...
 //! epd_gray.display_gray2().unwrap();
@@ -26,52 +41,99 @@
 //! // When `epd_impl` goes out of scope, it will automatically enter deep sleep mode,
 //! // at this point, any errors will be ignored,
 //! // and you can explicitly call `deepsleep()` to enter deep sleep mode.
+//! # }
 //! ```
 
+#[cfg(feature = "async")]
+pub mod asynch;
+mod builder;
+mod driver;
+mod state_file;
+mod temperature;
+#[cfg(feature = "virtual")]
+pub mod virtual_panel;
+mod waveform;
+
+pub use builder::{Epd5in79Builder, MAX_SPI_SPEED_HZ};
+pub use temperature::{
+    Temperature, TemperatureSource, WaveformBands, WaveformSource, RATED_TEMPERATURE,
+    REGISTER_RANGE, SENSOR_RANGE,
+};
+pub use waveform::{Group, Lut, Phase, Voltage, Voltages, Waveform, GROUPS, LUT_LEN, WAVEFORM_LEN};
+
 use std::{
+    borrow::Cow,
     convert::Infallible,
     fmt::Debug,
+    io,
     marker::PhantomData,
-    path::Path,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
+use self::driver::{block_on, Driver};
+#[cfg(feature = "linux")]
+use crate::linux::{BusyWait, CdevBusyPin};
 use embedded_graphics_core::{
     image::GetPixel,
     pixelcolor::{BinaryColor, Gray2},
     prelude::*,
+    primitives::Rectangle,
+};
+use embedded_hal::{
+    delay::DelayNs,
+    digital::{InputPin, OutputPin},
+    spi::SpiDevice,
 };
-use linux_embedded_hal::{
-    gpio_cdev::{Chip, LineRequestFlags},
-    spidev::{SpiModeFlags, SpidevOptions},
-    CdevPin, Delay, SpidevDevice,
+#[cfg(feature = "linux")]
+use linux_embedded_hal::{CdevPin, Delay, SpidevDevice};
+#[cfg(feature = "linux")]
+use waveshare_epd_core::spi_interface::PinDefinition;
+use waveshare_epd_core::{
+    bit_plane::PlaneColor,
+    cascade::{Cascade, CascadeBuffer, ControllerRegion},
+    dither::{dither, Dither, ErrorDiffusionKernel},
+    epd::{Epd, RefreshMode},
+    error::{ConfigError, Error},
+    export::{self, ExportFormat},
+    orientation::{Orientation, Rotation},
+    refresh::{RefreshPolicy, RefreshStats},
+    spi_interface::{DelayStep, SpiInterface, SpiInterfaceError},
 };
-use waveshare_epd_core::spi_interface::{DelayStep, PinDefinition, SpiInterface};
 
-// TODO: use specialised error types.
-type Spi = SpiInterface<SpidevDevice, CdevPin, CdevPin, Delay, anyhow::Error>;
+/// [`Epd5in79Impl`] on top of `spidev` and `gpio-cdev`.
+#[cfg(feature = "linux")]
+pub type Epd5in79Linux = Epd5in79Impl<SpidevDevice, CdevBusyPin, CdevPin, Delay>;
 
 pub const WIDTH: u32 = 792;
 pub const HIGH: u32 = 272;
 
-pub struct Epd5in79Impl {
-    spi_interface: Spi,
-    buffer0: Box<[u8; 13600]>, // master bw 0x24
-    buffer1: Box<[u8; 13600]>, // slave bw 0xa4
-    buffer2: Box<[u8; 13600]>, // master r 0x26
-    buffer3: Box<[u8; 13600]>, // slave r 0xa6
-    state: Epd5in79State,
+pub struct Epd5in79Impl<SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    driver: Driver<SpiInterface<SPI, BUSY, OUT, DELAY>>,
 }
 
-impl Debug for Epd5in79Impl {
+impl<SPI, BUSY, OUT, DELAY> Debug for Epd5in79Impl<SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Epd5in79Impl")
-            .field("state", &self.state)
+            .field("state", &self.driver.state)
             .finish_non_exhaustive()
     }
 }
 
-impl Default for Epd5in79Impl {
+#[cfg(feature = "linux")]
+impl Default for Epd5in79Linux {
     /// Use default [`PinDefinition`] and `/dev/spidev0.0` `/dev/gpiochip0`.
     fn default() -> Self {
         Self::new_with_pindefinition(PinDefinition::DEFAULT, "/dev/spidev0.0", "/dev/gpiochip0")
@@ -79,574 +141,1120 @@ impl Default for Epd5in79Impl {
     }
 }
 
-impl Epd5in79Impl {
-    pub fn new(
-        spi: SpidevDevice,
-        rst_pin: CdevPin,
-        dc_pin: CdevPin,
-        cs_pin: Option<CdevPin>,
-        busy_pin: CdevPin,
-        pwr_pin: CdevPin,
-        delay: Delay,
-    ) -> Self {
-        let buf = Box::new([!0; 13600]);
-        Self {
-            spi_interface: SpiInterface::new(
-                spi, rst_pin, dc_pin, cs_pin, busy_pin, pwr_pin, delay,
-            ),
-            buffer0: buf.clone(),
-            buffer1: buf.clone(),
-            buffer2: buf.clone(),
-            buffer3: buf,
-            state: Epd5in79State {
-                power_on: None,
-                color_in_buf: ColorInBuf::Binary,
-                init_for: None,
-            },
-        }
+#[cfg(feature = "linux")]
+impl Epd5in79Linux {
+    pub fn new_with_pindefinition(
+        pindefinition: PinDefinition,
+        spi_path: impl AsRef<Path>,
+        gpio_path: impl AsRef<Path>,
+    ) -> Result<Self, SpiInterfaceError<SpidevDevice, CdevBusyPin, CdevPin>> {
+        Self::new_with_busy_wait(pindefinition, spi_path, gpio_path, BusyWait::default())
     }
 
-    pub fn new_with_pindefinition(
+    /// Like [`Self::new_with_pindefinition`], but choose how to wait for the busy pin.
+    pub fn new_with_busy_wait(
         pindefinition: PinDefinition,
         spi_path: impl AsRef<Path>,
         gpio_path: impl AsRef<Path>,
-    ) -> Result<Self, anyhow::Error> {
-        let mut spi = SpidevDevice::open(spi_path)?;
-        spi.0.configure(
-            &SpidevOptions::new()
-                .max_speed_hz(4_000_000)
-                .mode(SpiModeFlags::SPI_MODE_0)
-                .build(),
-        )?;
-        let mut chip = Chip::new(gpio_path)?;
-        let rst_pin = CdevPin::new(chip.get_line(pindefinition.rst_pin)?.request(
-            LineRequestFlags::OUTPUT,
-            0,
-            "epd5in79_rst_pin",
-        )?)?;
-        let dc_pin = CdevPin::new(chip.get_line(pindefinition.dc_pin)?.request(
-            LineRequestFlags::OUTPUT,
-            0,
-            "epd5in79_dc_pin",
-        )?)?;
-        let pwr_pin = CdevPin::new(chip.get_line(pindefinition.pwr_pin)?.request(
-            LineRequestFlags::OUTPUT,
-            0,
-            "epd5in79_pwr_pin",
-        )?)?;
-        let busy_pin = CdevPin::new(chip.get_line(pindefinition.busy_pin)?.request(
-            LineRequestFlags::INPUT | LineRequestFlags::from_bits_retain(1 << 6),
-            0,
-            "epd5in79_busy_pin",
-        )?)?;
-        let cs_pin = if let Some(cs_pin_n) = pindefinition.cs_pin {
-            Some(CdevPin::new(chip.get_line(cs_pin_n)?.request(
-                LineRequestFlags::OUTPUT | LineRequestFlags::ACTIVE_LOW,
-                0,
-                "epd5in79_cs_pin",
-            )?)?)
-        } else {
-            None
-        };
-        Ok(Self::new(
-            spi, rst_pin, dc_pin, cs_pin, busy_pin, pwr_pin, Delay,
-        ))
+        busy_wait: BusyWait,
+    ) -> Result<Self, SpiInterfaceError<SpidevDevice, CdevBusyPin, CdevPin>> {
+        Epd5in79Builder::new()
+            .pins(pindefinition)
+            .busy_wait(busy_wait)
+            .open(spi_path, gpio_path)
+    }
+}
+
+impl<SPI, BUSY, OUT, DELAY> Epd5in79Impl<SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    pub fn new(
+        spi: SPI,
+        rst_pin: OUT,
+        dc_pin: OUT,
+        cs_pin: Option<OUT>,
+        busy_pin: BUSY,
+        pwr_pin: OUT,
+        delay: DELAY,
+    ) -> Self {
+        Self {
+            driver: Driver::new(SpiInterface::new(
+                spi, rst_pin, dc_pin, cs_pin, busy_pin, pwr_pin, delay,
+            )),
+        }
     }
 
-    pub fn as_binary(&mut self) -> Epd5in79<'_, BinaryColor> {
+    pub fn as_binary(&mut self) -> Epd5in79<'_, BinaryColor, SPI, BUSY, OUT, DELAY> {
         self.as_binary_with(BinaryColor::from)
     }
 
     pub fn as_binary_with(
         &mut self,
         f: impl Fn(Gray2) -> BinaryColor,
-    ) -> Epd5in79<'_, BinaryColor> {
-        self.mapping_to_binary(f);
+    ) -> Epd5in79<'_, BinaryColor, SPI, BUSY, OUT, DELAY> {
+        self.driver.mapping_to_binary(f);
         Epd5in79 {
             inner: self,
             color: PhantomData,
         }
     }
 
-    pub fn as_gray2(&mut self) -> Epd5in79<'_, Gray2> {
+    pub fn as_gray2(&mut self) -> Epd5in79<'_, Gray2, SPI, BUSY, OUT, DELAY> {
         self.as_gray2_with(Gray2::from)
     }
 
-    pub fn as_gray2_with(&mut self, f: impl Fn(BinaryColor) -> Gray2) -> Epd5in79<'_, Gray2> {
-        self.mapping_to_gray2(f);
+    pub fn as_gray2_with(
+        &mut self,
+        f: impl Fn(BinaryColor) -> Gray2,
+    ) -> Epd5in79<'_, Gray2, SPI, BUSY, OUT, DELAY> {
+        self.driver.mapping_to_gray2(f);
         Epd5in79 {
             inner: self,
             color: PhantomData,
         }
     }
 
-    fn mapping_to_binary(&mut self, f: impl Fn(Gray2) -> BinaryColor) {
-        if matches!(self.state.color_in_buf, ColorInBuf::Binary) {
-            return;
-        }
-        for x in 0..792 {
-            for y in 0..272 {
-                let point = Point::new(x, y);
-                // get color
-                let Some(color) = self.get_gray(point) else {
-                    continue;
-                };
-                // set pixel
-                self.set_binary(Pixel(point, f(color)));
-            }
+    /// Like [`as_binary`](Self::as_binary), but grays are dithered with `kernel`
+    /// instead of thresholded, so gray artwork survives the switch.
+    pub fn as_binary_dithered(
+        &mut self,
+        kernel: ErrorDiffusionKernel,
+    ) -> Epd5in79<'_, BinaryColor, SPI, BUSY, OUT, DELAY> {
+        self.driver.dither_to_binary(kernel);
+        Epd5in79 {
+            inner: self,
+            color: PhantomData,
         }
-        self.state.color_in_buf = ColorInBuf::Binary;
     }
 
-    fn mapping_to_gray2(&mut self, f: impl Fn(BinaryColor) -> Gray2) {
-        if matches!(self.state.color_in_buf, ColorInBuf::Gray) {
-            return;
-        }
-        for x in 0..792 {
-            for y in 0..272 {
-                let point = Point::new(x, y);
-                // get color
-                let Some(color) = self.get_binary(point) else {
-                    continue;
-                };
-                // set pixel
-                self.set_gray(Pixel(point, f(color)));
-            }
+    /// Like [`as_gray2`](Self::as_gray2), but every pixel becomes the mean of its 3x3
+    /// neighbourhood, so dithered areas turn back into grays, sharp edges get soft.
+    pub fn as_gray2_smoothed(&mut self) -> Epd5in79<'_, Gray2, SPI, BUSY, OUT, DELAY> {
+        self.driver.smooth_to_gray2();
+        Epd5in79 {
+            inner: self,
+            color: PhantomData,
         }
-        self.state.color_in_buf = ColorInBuf::Gray;
     }
 
-    fn send_buf(&mut self, cmd: u8) -> Result<(), anyhow::Error> {
-        let buf = match cmd {
-            0x24 => self.buffer0.as_slice(),
-            0xa4 => self.buffer1.as_slice(),
-            0x26 => self.buffer2.as_slice(),
-            0xa6 => self.buffer3.as_slice(),
-            _ => unreachable!(),
-        };
-        self.spi_interface.command_data(cmd, buf, 4096)?;
-        Ok(())
+    /// Enter the [`DeepSleepMode`] set by [`set_deep_sleep_mode`](Self::set_deep_sleep_mode),
+    /// and save the state file if one is set.
+    pub fn deep_sleep(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        let mode = self.driver.config.deep_sleep_mode;
+        block_on(self.driver.enter_deep_sleep(mode))?;
+        self.driver.save_state_file()
     }
 
-    fn send_bufs(&mut self, cmds: impl IntoIterator<Item = u8>) -> Result<(), anyhow::Error> {
-        for cmd in cmds {
-            self.send_buf(cmd)?;
-        }
-        Ok(())
+    /// Fill the screen and the framebuffer with `color` and show it with `mode`.
+    ///
+    /// The controllers fill their RAM with the auto-write pattern commands (0x46/0x47),
+    /// so a few bytes are sent instead of the planes.
+    /// [`Epd::clear`] only fills the framebuffer.
+    /// On error the framebuffer and its color are left as they were.
+    pub fn clear_screen(
+        &mut self,
+        color: BinaryColor,
+        mode: RefreshMode,
+    ) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        block_on(self.driver.clear_screen(color, mode))
     }
 
-    fn send_bufs_all(&mut self) -> Result<(), anyhow::Error> {
-        self.send_bufs([0x24, 0x26, 0xa4, 0xa6])
+    /// Read the built-in temperature sensor of the controller, waking the panel if it sleeps.
+    ///
+    /// The SPI device must be able to read the data line: a 3-wire (half-duplex) bus, or MISO
+    /// wired to the data line through a resistor, see
+    /// [`Epd5in79Builder::three_wire`](Epd5in79Builder::three_wire). On a write-only bus this
+    /// returns garbage, supply a reading with [`set_temperature`](Self::set_temperature) instead.
+    /// Readings outside of [`SENSOR_RANGE`] are rejected with
+    /// [`Error::ImplausibleTemperature`] and not stored.
+    pub fn read_temperature(&mut self) -> Result<Temperature, SpiInterfaceError<SPI, BUSY, OUT>> {
+        block_on(self.driver.read_temperature())
     }
 
-    fn command_data(&mut self, cmd: u8, data: impl AsRef<[u8]>) -> Result<(), anyhow::Error> {
-        self.spi_interface.command_data(cmd, data, 4096)?;
-        Ok(())
+    /// Supply the temperature of the panel in °C from an external sensor,
+    /// `None` forgets the last reading.
+    ///
+    /// The OTP waveforms then use it instead of the built-in sensor, which the controller
+    /// reads by every display update otherwise.
+    /// Temperatures outside of [`SENSOR_RANGE`] are rejected with
+    /// [`Error::ImplausibleTemperature`] and the last one is kept.
+    pub fn set_temperature(
+        &mut self,
+        celsius: Option<f32>,
+    ) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.driver.set_temperature(celsius)
     }
 
-    pub fn deep_sleep(&mut self) -> Result<(), anyhow::Error> {
-        if !self.state.is_deepsleep() {
-            self.spi_interface.command_data(0x10, [0x03], 4096)?;
-            self.state.power_on = None;
-            self.spi_interface.set_power(false)?;
-            self.spi_interface.set_rst_pin(false)?;
-        }
-        Ok(())
+    /// The last temperature read or supplied.
+    ///
+    /// Check [`Temperature::is_below_rated`] to warn about a panel that is too cold.
+    pub fn temperature(&self) -> Option<Temperature> {
+        self.driver.state.temperature
+    }
+
+    /// The waveforms of `mode` by temperature.
+    pub fn waveform_bands(&self, mode: DisplayMode) -> &WaveformBands {
+        &self.driver.waveforms[mode.index()]
+    }
+
+    /// Replace the waveforms of `mode`, they are loaded by the next display update.
+    pub fn set_waveform_bands(&mut self, mode: DisplayMode, bands: WaveformBands) {
+        self.driver.set_waveform_bands(mode, bands);
+    }
+
+    /// Use `waveform` for `mode` below `below` °C, see [`WaveformBands::insert`].
+    pub fn add_waveform_band(&mut self, mode: DisplayMode, below: f32, waveform: WaveformSource) {
+        self.driver.add_waveform_band(mode, below, waveform);
+    }
+
+    /// Validate `waveform` and use it for `mode` at all temperatures,
+    /// it is loaded by the next display update.
+    ///
+    /// Tunes the gray levels of [`DisplayMode::Gray2`], or the speed and ghosting of the
+    /// binary refreshes. The pixels are driven by the [`Lut`] of their red and bw RAM bits.
+    pub fn load_custom_waveform(
+        &mut self,
+        mode: DisplayMode,
+        waveform: Waveform,
+    ) -> Result<(), ConfigError> {
+        self.driver.load_custom_waveform(mode, waveform)
+    }
+
+    /// Clear the screen to white with a full refresh and power off the panel,
+    /// as recommended before storing it for a long time, and save the state file if one is set.
+    pub fn clear_for_storage(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        block_on(self.driver.clear_for_storage())?;
+        self.driver.save_state_file()
+    }
+
+    /// Save the framebuffer and refresh counters to `path` on [`deep_sleep`](Self::deep_sleep)
+    /// and [`Drop`], `None` disables it.
+    ///
+    /// See also [`Epd5in79Builder::state_file`], which restores the state on construction.
+    pub fn set_state_file(&mut self, path: Option<PathBuf>) {
+        self.driver.state_file = path;
+    }
+
+    /// Encode the framebuffer in its current color and [`Orientation`],
+    /// without a panel attached.
+    pub fn export_bytes(&self, format: ExportFormat) -> io::Result<Vec<u8>> {
+        self.driver.export_bytes(format)
+    }
+
+    /// Write the framebuffer in its current color to `path`.
+    pub fn export(&self, path: impl AsRef<Path>, format: ExportFormat) -> io::Result<()> {
+        std::fs::write(path, self.export_bytes(format)?)
+    }
+
+    pub fn save_state(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.driver.save_state(path.as_ref())
+    }
+
+    /// Restore the framebuffer and refresh counters saved by [`save_state`](Self::save_state),
+    /// returns `false` if `path` does not exist.
+    pub fn restore_state(&mut self, path: impl AsRef<Path>) -> io::Result<bool> {
+        self.driver.restore_state(path.as_ref())
     }
 
     pub fn power_on_dur(&self) -> Option<Duration> {
-        self.state.power_on.map(|i| i.elapsed())
+        self.driver.state.power_on.map(|i| i.elapsed())
     }
 
-    fn set_binary(&mut self, Pixel(point, color): Pixel<BinaryColor>) {
-        if !is_point_in_screen(point) {
-            return;
-        }
+    /// Bounding box of the pixels changed since the last refresh, `None` if nothing changed.
+    ///
+    /// Every `display_*` function resets it for the area it sent, a partial region only
+    /// shrinks it when the region covers a whole edge of the box.
+    /// It is in the coordinates of the [`Orientation`].
+    pub fn dirty_area(&self) -> Option<Rectangle> {
+        self.driver.dirty_area()
+    }
 
-        if point.x < 50 * 8 {
-            // master
-            let buf_index = 50 * point.y + point.x / 8;
-            let offset = 7 - (point.x % 8) as u8;
-            let value = self.buffer0.get_mut(buf_index as usize).unwrap();
-            set_binary_value(color, offset, value);
-        }
+    /// Forget the changed pixels, e.g. after an external refresh.
+    pub fn reset_dirty(&mut self) {
+        self.driver.frame.dirty = None;
+    }
 
-        if point.x >= 49 * 8 {
-            // slave
-            let buf_index = 50 * point.y + (point.x - 49 * 8) / 8;
-            let offset = 7 - ((point.x - 49 * 8) % 8) as u8;
-            let value = self.buffer1.get_mut(buf_index as usize).unwrap();
-            set_binary_value(color, offset, value);
-        }
+    pub fn refresh_policy(&self) -> RefreshPolicy {
+        self.driver.config.refresh_policy
     }
 
-    fn get_binary(&self, Point { x, y }: Point) -> Option<BinaryColor> {
-        if !is_point_in_screen(Point::new(x, y)) {
-            return None;
-        }
-        if x < 49 * 8 {
-            // master
-            let buf_index = 50 * y + x / 8;
-            let offset = 7 - (x % 8) as u8;
-            let value = self.buffer0[buf_index as usize];
-            Some(get_binary_from_value(offset, value))
-        } else {
-            // slave
-            let buf_index = 50 * y + (x - 49 * 8) / 8;
-            let offset = 7 - ((x - 49 * 8) % 8) as u8;
-            let value = self.buffer1[buf_index as usize];
-            Some(get_binary_from_value(offset, value))
-        }
+    /// The policy used by [`Epd5in79::refresh`].
+    pub fn set_refresh_policy(&mut self, policy: RefreshPolicy) {
+        self.driver.config.refresh_policy = policy;
     }
 
-    fn set_gray(&mut self, Pixel(point, color): Pixel<Gray2>) {
-        if !is_point_in_screen(point) {
-            return;
-        }
+    pub fn refresh_stats(&self) -> RefreshStats {
+        self.driver.state.stats
+    }
 
-        if point.x < 50 * 8 {
-            // master
-            let buf_index = 50 * point.y + point.x / 8;
-            let offset = 7 - (point.x % 8) as u8;
-            let bw_value = self.buffer0.get_mut(buf_index as usize).unwrap();
-            let r_value = self.buffer2.get_mut(buf_index as usize).unwrap();
-            set_gray_value(color, offset, bw_value, r_value);
-        }
+    pub fn deep_sleep_mode(&self) -> DeepSleepMode {
+        self.driver.config.deep_sleep_mode
+    }
 
-        if point.x >= 49 * 8 {
-            // slave
-            let buf_index = 50 * point.y + (point.x - 49 * 8) / 8;
-            let offset = 7 - ((point.x - 49 * 8) % 8) as u8;
-            let bw_value = self.buffer1.get_mut(buf_index as usize).unwrap();
-            let r_value = self.buffer3.get_mut(buf_index as usize).unwrap();
-            set_gray_value(color, offset, bw_value, r_value);
-        }
+    /// The mode entered by [`deep_sleep`](Self::deep_sleep) and [`Drop`].
+    pub fn set_deep_sleep_mode(&mut self, mode: DeepSleepMode) {
+        self.driver.config.deep_sleep_mode = mode;
     }
 
-    fn get_gray(&self, Point { x, y }: Point) -> Option<Gray2> {
-        if !is_point_in_screen(Point::new(x, y)) {
-            return None;
-        }
+    /// The mode the panel sleeps in, `None` while it is awake.
+    ///
+    /// Before the first refresh the panel is treated as powered off.
+    pub fn sleeping_in(&self) -> Option<DeepSleepMode> {
+        self.driver.state.sleeping_in()
+    }
 
-        if x < 49 * 8 {
-            // master
-            let buf_index = 50 * y + x / 8;
-            let offset = 7 - (x % 8) as u8;
-            let bw_value = self.buffer0[buf_index as usize];
-            let r_value = self.buffer2[buf_index as usize];
-            Some(get_gray_from_values(offset, bw_value, r_value))
-        } else {
-            // slave
-            let buf_index = 50 * y + (x - 49 * 8) / 8;
-            let offset = 7 - ((x - 49 * 8) % 8) as u8;
-            let bw_value = self.buffer1[buf_index as usize];
-            let r_value = self.buffer3[buf_index as usize];
-            Some(get_gray_from_values(offset, bw_value, r_value))
-        }
+    pub fn orientation(&self) -> Orientation {
+        self.driver.config.orientation
+    }
+
+    /// Map everything drawn, read, the size, the dirty area and the area of
+    /// [`Epd5in79::display_binary_partial_region`] through `orientation`.
+    ///
+    /// Mirroring and 180° are done by the controllers, which write the RAM in the opposite
+    /// direction, only 90° and 270° are remapped while drawing.
+    ///
+    /// The framebuffer keeps the image of the panel, so the "old data" of partial refresh
+    /// stays valid, but its content is rotated and mirrored with the panel: read in the new
+    /// orientation, it is the old image turned around. Redraw it after changing the orientation.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.driver.reorient(orientation);
+    }
+
+    /// See [`set_orientation`](Self::set_orientation).
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.set_orientation(Orientation {
+            rotation,
+            ..self.driver.config.orientation
+        });
+    }
+
+    /// Mirror the image left to right (`x`) and top to bottom (`y`),
+    /// see [`set_orientation`](Self::set_orientation).
+    pub fn set_mirror(&mut self, x: bool, y: bool) {
+        self.set_orientation(Orientation {
+            mirror_x: x,
+            mirror_y: y,
+            ..self.driver.config.orientation
+        });
     }
 }
 
-impl Drop for Epd5in79Impl {
+impl<SPI, BUSY, OUT, DELAY> Drop for Epd5in79Impl<SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
     fn drop(&mut self) {
         let _ = self.deep_sleep();
     }
 }
 
-#[derive(Debug)]
-pub struct Epd5in79<'a, C> {
-    inner: &'a mut Epd5in79Impl,
+pub struct Epd5in79<'a, C, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    inner: &'a mut Epd5in79Impl<SPI, BUSY, OUT, DELAY>,
     color: PhantomData<C>,
 }
 
-impl<'a> GetPixel for Epd5in79<'a, BinaryColor> {
+impl<'a, C, SPI, BUSY, OUT, DELAY> Debug for Epd5in79<'a, C, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Epd5in79")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, SPI, BUSY, OUT, DELAY> GetPixel for Epd5in79<'a, BinaryColor, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
     type Color = BinaryColor;
     fn pixel(&self, p: Point) -> Option<Self::Color> {
-        debug_assert!(matches!(self.inner.state.color_in_buf, ColorInBuf::Binary));
-        self.inner.get_binary(p)
+        self.inner.driver.pixel_binary(p)
     }
 }
 
-impl<'a> GetPixel for Epd5in79<'a, Gray2> {
+impl<'a, SPI, BUSY, OUT, DELAY> GetPixel for Epd5in79<'a, Gray2, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
     type Color = Gray2;
     fn pixel(&self, p: Point) -> Option<Self::Color> {
-        debug_assert!(matches!(self.inner.state.color_in_buf, ColorInBuf::Gray));
-        self.inner.get_gray(p)
+        self.inner.driver.pixel_gray(p)
     }
 }
 
-impl<'a, C> std::ops::Deref for Epd5in79<'a, C> {
-    type Target = Epd5in79Impl;
+impl<'a, C, SPI, BUSY, OUT, DELAY> std::ops::Deref for Epd5in79<'a, C, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    type Target = Epd5in79Impl<SPI, BUSY, OUT, DELAY>;
 
     fn deref(&self) -> &Self::Target {
         self.inner
     }
 }
 
-impl<'a, C> std::ops::DerefMut for Epd5in79<'a, C> {
+impl<'a, C, SPI, BUSY, OUT, DELAY> std::ops::DerefMut for Epd5in79<'a, C, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
     fn deref_mut(&mut self) -> &mut Self::Target {
         self.inner
     }
 }
 
-impl<'a, C> Epd5in79<'a, C> {
-    fn set_address(&mut self) -> Result<(), anyhow::Error> {
-        self.inner.command_data(0x11, [0x01])?;
+impl<'a, C, SPI, BUSY, OUT, DELAY> Epd5in79<'a, C, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    pub fn wait_busy(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        block_on(self.inner.driver.wait_busy())
+    }
+}
 
-        self.inner.command_data(0x44, [0x00, 0x31])?;
-        self.inner.command_data(0x45, [0x0f, 0x01, 0x00, 0x00])?;
+impl<'a, SPI, BUSY, OUT, DELAY> Epd5in79<'a, Gray2, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    pub fn display_gray2(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        block_on(self.inner.driver.display_gray2())
+    }
+}
 
-        self.inner.command_data(0x4e, [0x00])?;
-        self.inner.command_data(0x4f, [0x0f, 0x01])?;
+impl<'a, SPI, BUSY, OUT, DELAY> Epd5in79<'a, BinaryColor, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    pub fn display_binary_full(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        block_on(self.inner.driver.display_binary_full())
+    }
 
-        self.inner.command_data(0x91, [0x00])?;
-        self.inner.command_data(0xc4, [0x31, 0x00])?;
-        self.inner.command_data(0xc5, [0x0f, 0x01, 0x00, 0x00])?;
+    pub fn display_binary_fast(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        block_on(self.inner.driver.display_binary_fast())
+    }
 
-        self.inner.command_data(0xce, [0x31])?;
-        self.inner.command_data(0xcf, [0x0f, 0x01])?;
-        Ok(())
+    pub fn display_binary_partial(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        block_on(self.inner.driver.display_binary_partial())
     }
 
-    fn check_deepsleep(&self) -> Result<(), anyhow::Error> {
-        self.inner.state.check_deepsleep()
+    /// Like [`display_binary_partial`](Self::display_binary_partial),
+    /// but only sends the bytes covering `area`.
+    ///
+    /// `area` is in the coordinates of the [`Orientation`], it is clipped to the screen
+    /// and widened to whole bytes, nothing is done if it is empty.
+    pub fn display_binary_partial_region(
+        &mut self,
+        area: Rectangle,
+    ) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        let area = self.inner.driver.config.rect_to_frame(&area);
+        block_on(self.inner.driver.display_binary_partial_native(area))
     }
 
-    fn hw_reset(&mut self) -> Result<(), anyhow::Error> {
-        self.inner.spi_interface.set_rst_pin(true)?;
-        self.inner.spi_interface.delay(DelayStep::Us(200));
-        self.inner.spi_interface.set_rst_pin(false)?;
-        self.inner.spi_interface.delay(DelayStep::Us(200));
-        self.inner.spi_interface.set_rst_pin(true)?;
-        self.inner.spi_interface.delay(DelayStep::Us(200));
-        self.wait_busy_without_check()?;
-        self.inner.state.power_on = Some(Instant::now());
-        Ok(())
+    /// Show the framebuffer with the refresh mode chosen by the [`RefreshPolicy`].
+    ///
+    /// Partial refreshes only send the dirty area, nothing is done if nothing changed
+    /// and no full refresh is due. Below the [`RATED_TEMPERATURE`] only full refresh is used.
+    /// Returns the refresh mode used.
+    pub fn refresh(&mut self) -> Result<Option<RefreshMode>, SpiInterfaceError<SPI, BUSY, OUT>> {
+        block_on(self.inner.driver.refresh())
+    }
+}
+
+impl<'a, C, SPI, BUSY, OUT, DELAY> OriginDimensions for Epd5in79<'a, C, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    fn size(&self) -> Size {
+        self.inner.driver.config.size()
     }
+}
+
+impl<'a, SPI, BUSY, OUT, DELAY> DrawTarget for Epd5in79<'a, BinaryColor, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    type Color = BinaryColor;
+    type Error = Infallible;
 
-    fn sw_reset(&mut self) -> Result<(), anyhow::Error> {
-        self.inner.spi_interface.command(0x12)?;
-        self.wait_busy_without_check()?;
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.inner.driver.draw_binary(pixels);
         Ok(())
     }
 
-    fn power_on(&mut self) -> Result<(), anyhow::Error> {
-        if self.inner.state.is_deepsleep() {
-            self.inner.spi_interface.set_power(true)?;
-            self.hw_reset()?;
-        }
-        self.sw_reset()?;
-        self.inner.state.init_for = None;
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.inner.driver.fill_solid(area, color);
         Ok(())
     }
+}
 
-    fn wait_busy_without_check(&mut self) -> Result<(), anyhow::Error> {
-        self.inner
-            .spi_interface
-            .wait_busy_timeout(DelayStep::Us(200), Duration::from_secs(5))?;
+impl<'a, SPI, BUSY, OUT, DELAY> DrawTarget for Epd5in79<'a, Gray2, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    type Color = Gray2;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.inner.driver.draw_gray(pixels);
         Ok(())
     }
 
-    pub fn wait_busy(&mut self) -> Result<(), anyhow::Error> {
-        self.check_deepsleep()?;
-        self.wait_busy_without_check()?;
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.inner.driver.fill_solid(area, color);
         Ok(())
     }
 }
 
-impl<'a> Epd5in79<'a, Gray2> {
-    fn init_gray2(&mut self) -> Result<(), anyhow::Error> {
-        self.power_on()?;
-        self.inner.command_data(0x0c, [0x8b, 0x9c, 0xa6, 0x0f])?;
-        self.inner.command_data(0x3c, [0x81])?;
-        self.set_address()?;
-        self.load_lut()?;
-        self.inner.state.init_for = Some(DisplayMode::Gray2);
-        Ok(())
+impl<SPI, BUSY, OUT, DELAY> Epd<BinaryColor> for Epd5in79Impl<SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    type Error = SpiInterfaceError<SPI, BUSY, OUT>;
+    type Frame<'a>
+        = Epd5in79<'a, BinaryColor, SPI, BUSY, OUT, DELAY>
+    where
+        Self: 'a;
+
+    fn size(&self) -> Size {
+        self.driver.config.size()
+    }
+
+    fn refresh_modes(&self) -> &'static [RefreshMode] {
+        &[RefreshMode::Full, RefreshMode::Fast, RefreshMode::Partial]
+    }
+
+    fn frame(&mut self) -> Self::Frame<'_> {
+        self.as_binary()
     }
 
-    fn ensure_inited_gray2(&mut self) -> Result<(), anyhow::Error> {
-        if !self.inner.state.is_ready_for(DisplayMode::Gray2) {
-            self.init_gray2()?;
+    fn display(&mut self, mode: RefreshMode) -> Result<(), Self::Error> {
+        let mut epd = self.as_binary();
+        match mode {
+            RefreshMode::Full => epd.display_binary_full(),
+            RefreshMode::Fast => epd.display_binary_fast(),
+            RefreshMode::Partial => epd.display_binary_partial(),
         }
-        Ok(())
     }
 
-    fn load_lut(&mut self) -> Result<(), anyhow::Error> {
-        self.inner.command_data(0x32, LUT_DATA)?;
-        self.inner.command_data(0x3f, [0x22])?;
-        self.inner.command_data(0x03, [0x17])?;
-        self.inner.command_data(0x04, [0x41, 0xa8, 0x32])?;
-        self.inner.command_data(0x2c, [0x40])?;
-        Ok(())
+    fn sleep(&mut self) -> Result<(), Self::Error> {
+        self.deep_sleep()
     }
 
-    pub fn display_gray2(&mut self) -> Result<(), anyhow::Error> {
-        self.ensure_inited_gray2()?;
-        debug_assert!(matches!(self.inner.state.color_in_buf, ColorInBuf::Gray));
+    fn wake(&mut self) -> Result<(), Self::Error> {
+        block_on(self.driver.power_on())
+    }
 
-        // send data
-        self.inner.send_bufs_all()?;
-        // turn on display
-        self.inner.command_data(0x22, [0xcf])?;
-        self.inner.spi_interface.command(0x20)?;
+    fn is_sleeping(&self) -> bool {
+        self.driver.state.is_deepsleep()
+    }
+}
 
-        self.inner.spi_interface.delay(DelayStep::Us(200));
-        self.wait_busy()?;
-        Ok(())
+impl<SPI, BUSY, OUT, DELAY> Epd<Gray2> for Epd5in79Impl<SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    type Error = SpiInterfaceError<SPI, BUSY, OUT>;
+    type Frame<'a>
+        = Epd5in79<'a, Gray2, SPI, BUSY, OUT, DELAY>
+    where
+        Self: 'a;
+
+    fn size(&self) -> Size {
+        self.driver.config.size()
+    }
+
+    fn refresh_modes(&self) -> &'static [RefreshMode] {
+        &[RefreshMode::Full]
+    }
+
+    fn frame(&mut self) -> Self::Frame<'_> {
+        self.as_gray2()
+    }
+
+    fn display(&mut self, mode: RefreshMode) -> Result<(), Self::Error> {
+        match mode {
+            RefreshMode::Full => self.as_gray2().display_gray2(),
+            mode => Err(Error::UnsupportedMode(mode)),
+        }
+    }
+
+    fn sleep(&mut self) -> Result<(), Self::Error> {
+        self.deep_sleep()
+    }
+
+    fn wake(&mut self) -> Result<(), Self::Error> {
+        block_on(self.driver.power_on())
+    }
+
+    fn is_sleeping(&self) -> bool {
+        self.driver.state.is_deepsleep()
     }
 }
 
-impl<'a> Epd5in79<'a, BinaryColor> {
-    fn init_binary_full(&mut self) -> Result<(), anyhow::Error> {
-        self.power_on()?;
-        self.set_address()?;
-        self.inner.state.init_for = Some(DisplayMode::Full);
-        Ok(())
+/// What [`Epd5in79Impl::deep_sleep`] does with the panel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeepSleepMode {
+    /// Deep sleep mode 1, the panel stays powered and the controllers keep their RAM,
+    /// so a partial refresh right after waking does not resend the "old data".
+    RetainRam,
+    /// Deep sleep mode 2, then the panel is powered off and the RAM is lost.
+    #[default]
+    PowerOff,
+}
+
+impl DeepSleepMode {
+    /// The parameter of the deep sleep command (0x10).
+    fn command_data(self) -> u8 {
+        match self {
+            Self::RetainRam => 0x01,
+            Self::PowerOff => 0x03,
+        }
+    }
+}
+
+/// Settings that do not depend on the HAL, see [`Epd5in79Builder`].
+#[derive(Debug, Clone, Copy)]
+struct Config {
+    chunk_size: usize,
+    busy_timeout: Duration,
+    busy_poll_step: DelayStep,
+    refresh_policy: RefreshPolicy,
+    deep_sleep_mode: DeepSleepMode,
+    orientation: Orientation,
+    /// The part of `orientation` done by the CPU while drawing.
+    frame_orientation: Orientation,
+    /// The part of `orientation` done by the address counters of the controllers.
+    ram_mirror: Orientation,
+}
+
+impl Config {
+    const DEFAULT: Config = Config {
+        chunk_size: 4096,
+        busy_timeout: Duration::from_secs(5),
+        busy_poll_step: DelayStep::Us(200),
+        refresh_policy: RefreshPolicy::DEFAULT,
+        deep_sleep_mode: DeepSleepMode::PowerOff,
+        orientation: Orientation::DEFAULT,
+        frame_orientation: Orientation::DEFAULT,
+        ram_mirror: Orientation::DEFAULT,
+    };
+
+    /// Split `orientation` into a rotation of the framebuffer by 0° or 90°
+    /// and mirrors done by the controllers.
+    fn with_orientation(mut self, orientation: Orientation) -> Self {
+        let Orientation {
+            rotation,
+            mirror_x,
+            mirror_y,
+        } = orientation;
+        // 180° mirrors both axes, 270° is 90° mirrored on both axes,
+        // after 90° the mirrors of the image are swapped on the panel
+        let (frame_rotation, ram_x, ram_y) = match rotation {
+            Rotation::Deg0 => (Rotation::Deg0, mirror_x, mirror_y),
+            Rotation::Deg180 => (Rotation::Deg0, !mirror_x, !mirror_y),
+            Rotation::Deg90 => (Rotation::Deg90, mirror_y, mirror_x),
+            Rotation::Deg270 => (Rotation::Deg90, !mirror_y, !mirror_x),
+        };
+        self.orientation = orientation;
+        self.frame_orientation = Orientation {
+            rotation: frame_rotation,
+            ..Orientation::DEFAULT
+        };
+        self.ram_mirror = Orientation {
+            rotation: Rotation::Deg0,
+            mirror_x: ram_x,
+            mirror_y: ram_y,
+        };
+        self
+    }
+
+    /// The size in the coordinates of the orientation.
+    fn size(self) -> Size {
+        self.orientation.size(NATIVE_SIZE)
+    }
+
+    fn to_frame(self, point: Point) -> Point {
+        self.frame_orientation.to_native(point, NATIVE_SIZE)
     }
 
-    fn ensure_inited_binary_full(&mut self) -> Result<(), anyhow::Error> {
-        if !self.inner.state.is_ready_for(DisplayMode::Full) {
-            self.init_binary_full()?;
+    fn rect_to_frame(self, area: &Rectangle) -> Rectangle {
+        self.frame_orientation.rect_to_native(area, NATIVE_SIZE)
+    }
+
+    fn rect_from_frame(self, area: &Rectangle) -> Rectangle {
+        self.frame_orientation.rect_from_native(area, NATIVE_SIZE)
+    }
+}
+
+/// The size of the framebuffer, which is not rotated.
+const NATIVE_SIZE: Size = Size::new(WIDTH, HIGH);
+
+/// The master drives columns 0..400, the slave 392..792, columns 392..400 are driven by both.
+const CASCADE: Cascade = Cascade::new(
+    HIGH,
+    &[
+        ControllerRegion::new(0, 400, 0x00),
+        ControllerRegion::new(49 * 8, 400, 0x80),
+    ],
+);
+
+/// Framebuffers of both controllers, shared by the blocking and the async driver.
+///
+/// Plane 0 of each buffer is the bw RAM (0x24/0xa4), plane 1 is the red RAM (0x26/0xa6).
+/// Binary colors only use plane 0, plane 1 holds the "old data" then.
+///
+/// With the mirrors of [`Config::ram_mirror`] the image is mirrored on the panel,
+/// the buffer of the master region is then shown by the slave and the other way round.
+#[derive(Clone)]
+struct Frame {
+    buf: CascadeBuffer<Gray2>,
+    /// Bounding box of the pixels changed since the last refresh.
+    dirty: Option<Rectangle>,
+}
+
+impl Frame {
+    fn new() -> Self {
+        Self {
+            buf: CascadeBuffer::new(CASCADE, Gray2::WHITE),
+            dirty: None,
         }
-        debug_assert!(self.inner.state.is_ready_for(DisplayMode::Full));
-        Ok(())
     }
 
-    pub fn display_binary_full(&mut self) -> Result<(), anyhow::Error> {
-        self.ensure_inited_binary_full()?;
+    fn plane(&self, cmd: u8) -> &[u8] {
+        let region = (cmd & 0x80 != 0) as usize;
+        match cmd & !0x80 {
+            0x24 => self.buf.buffer(region).plane(0),
+            0x26 => self.buf.buffer(region).plane(1),
+            _ => unreachable!(),
+        }
+    }
 
-        // send data
-        self.inner.buffer2.fill(0);
-        self.inner.buffer3.fill(0);
-        self.inner.send_bufs_all()?;
-        // turn on display
-        self.inner.command_data(0x22, [0xf7])?;
-        self.inner.spi_interface.command(0x20)?;
+    fn plane_mut(&mut self, cmd: u8) -> &mut [u8] {
+        let region = (cmd & 0x80 != 0) as usize;
+        match cmd & !0x80 {
+            0x24 => self.buf.buffer_mut(region).plane_mut(0),
+            0x26 => self.buf.buffer_mut(region).plane_mut(1),
+            _ => unreachable!(),
+        }
+    }
 
-        self.inner.spi_interface.delay(DelayStep::Us(200));
-        self.wait_busy()?;
-        Ok(())
+    /// The data of the RAM `cmd` of a controller.
+    ///
+    /// The controllers cannot reverse the pixels of a byte,
+    /// so they are reversed here when mirrored left to right.
+    fn ram_plane(&self, cmd: u8, ram_mirror: Orientation) -> Cow<'_, [u8]> {
+        if ram_mirror.mirror_x {
+            let plane = self.plane(cmd ^ 0x80);
+            Cow::Owned(plane.iter().map(|byte| byte.reverse_bits()).collect())
+        } else {
+            Cow::Borrowed(self.plane(cmd))
+        }
     }
 
-    fn init_binary_fast(&mut self) -> Result<(), anyhow::Error> {
-        self.power_on()?;
+    /// Mirror the image left to right (`x`) and top to bottom (`y`).
+    fn mirror(&mut self, x: bool, y: bool) {
+        if !x && !y {
+            return;
+        }
+        let row_bytes = self.buf.buffer(0).row_bytes();
+        for plane in [0x24, 0x26] {
+            let mut master = self.plane(plane).to_vec();
+            let mut slave = self.plane(plane | 0x80).to_vec();
+            if x {
+                // the regions are symmetric, so the rows swap controllers
+                for row in master
+                    .chunks_mut(row_bytes)
+                    .chain(slave.chunks_mut(row_bytes))
+                {
+                    row.reverse();
+                    row.iter_mut().for_each(|byte| *byte = byte.reverse_bits());
+                }
+                std::mem::swap(&mut master, &mut slave);
+            }
+            if y {
+                for data in [&mut master, &mut slave] {
+                    *data = data.rchunks(row_bytes).flatten().copied().collect();
+                }
+            }
+            self.plane_mut(plane).copy_from_slice(&master);
+            self.plane_mut(plane | 0x80).copy_from_slice(&slave);
+        }
+        let flip = Orientation {
+            rotation: Rotation::Deg0,
+            mirror_x: x,
+            mirror_y: y,
+        };
+        self.dirty = self
+            .dirty
+            .map(|dirty| flip.rect_to_native(&dirty, NATIVE_SIZE));
+    }
 
-        self.inner.command_data(0x18, [0x80])?;
-        self.inner.command_data(0x22, [0xb1])?;
-        self.inner.spi_interface.command(0x20)?;
-        self.wait_busy()?;
+    /// Clear the "old data" planes (0x26/0xa6) for binary refresh.
+    fn clear_old(&mut self) {
+        for buf in self.buf.buffers_mut() {
+            buf.plane_mut(1).fill(0);
+        }
+    }
 
-        self.inner.command_data(0x1a, [0x64, 0x00])?;
-        self.inner.command_data(0x22, [0x91])?;
-        self.inner.spi_interface.command(0x20)?;
-        self.wait_busy()?;
+    /// Use the current image as the "old data" for partial refresh.
+    fn copy_new_to_old(&mut self) {
+        for buf in self.buf.buffers_mut() {
+            let new = buf.plane(0).to_vec();
+            buf.plane_mut(1).copy_from_slice(&new);
+        }
+    }
 
-        self.set_address()?;
+    /// Use the image in `area`, widened to whole bytes, as the "old data".
+    fn copy_new_to_old_in(&mut self, area: &Rectangle) {
+        let screen = self.buf.bounding_box();
+        let area = area.intersection(&screen);
+        let Some(bottom_right) = area.bottom_right() else {
+            return;
+        };
+        let widened = Rectangle::with_corners(
+            Point::new(area.top_left.x / 8 * 8, area.top_left.y),
+            Point::new(bottom_right.x / 8 * 8 + 7, bottom_right.y),
+        )
+        .intersection(&screen);
+        for point in widened.points() {
+            if let Some(bit) = self.buf.get_bit(0, point) {
+                self.buf.set_bit(1, point, bit);
+            }
+        }
+    }
 
-        self.inner.state.init_for = Some(DisplayMode::Fast);
-        Ok(())
+    fn mapping_to_binary(&mut self, f: impl Fn(Gray2) -> BinaryColor) {
+        for point in self.buf.bounding_box().points() {
+            // get color
+            let Some(color) = self.get_gray(point) else {
+                continue;
+            };
+            // set pixel
+            self.set_binary(Pixel(point, f(color)));
+        }
     }
 
-    fn ensure_inited_binary_fast(&mut self) -> Result<(), anyhow::Error> {
-        if !self.inner.state.is_ready_for(DisplayMode::Fast) {
-            self.init_binary_fast()?;
+    fn mapping_to_gray2(&mut self, f: impl Fn(BinaryColor) -> Gray2) {
+        for point in self.buf.bounding_box().points() {
+            // get color
+            let Some(color) = self.get_binary(point) else {
+                continue;
+            };
+            // set pixel
+            self.set_gray(Pixel(point, f(color)));
         }
-        debug_assert!(self.inner.state.is_ready_for(DisplayMode::Fast));
-        Ok(())
     }
 
-    pub fn display_binary_fast(&mut self) -> Result<(), anyhow::Error> {
-        self.ensure_inited_binary_fast()?;
+    fn dither_to_binary(&mut self, kernel: ErrorDiffusionKernel) {
+        let area = self.buf.bounding_box();
+        let luma: Vec<u8> = area
+            .points()
+            .map(|point| self.get_gray(point).map_or(0, |color| color.luma() * 85))
+            .collect();
+        let levels = dither(
+            &luma,
+            area.size.width as usize,
+            2,
+            Dither::ErrorDiffusion(kernel),
+        );
+        for (point, level) in area.points().zip(levels) {
+            self.set_binary(Pixel(point, BinaryColor::from_bits(level)));
+        }
+    }
 
-        // send data
-        self.inner.buffer2.fill(0);
-        self.inner.buffer3.fill(0);
-        self.inner.send_bufs_all()?;
-        // turn on display
-        self.inner.command_data(0x22, [0xc7])?;
-        self.inner.spi_interface.command(0x20)?;
+    fn smooth_to_gray2(&mut self) {
+        let area = self.buf.bounding_box();
+        let on: Vec<bool> = area
+            .points()
+            .map(|point| self.get_binary(point) == Some(BinaryColor::On))
+            .collect();
+        let width = area.size.width as usize;
+        for point in area.points() {
+            let neighbours =
+                Rectangle::new(point - Point::new(1, 1), Size::new(3, 3)).intersection(&area);
+            let count = neighbours.points().count() as u32;
+            let on_count = neighbours
+                .points()
+                .filter(|p| on[p.y as usize * width + p.x as usize])
+                .count() as u32;
+            let luma = (on_count * 3 + count / 2) / count;
+            self.set_gray(Pixel(point, Gray2::new(luma as u8)));
+        }
+    }
 
-        self.inner.spi_interface.delay(DelayStep::Us(200));
-        self.wait_busy()?;
-        Ok(())
+    fn set_binary(&mut self, Pixel(point, color): Pixel<BinaryColor>) {
+        if self.get_binary(point).is_some_and(|old| old != color) {
+            self.buf.set_bit(0, point, color.is_on());
+            self.mark_dirty(Rectangle::new(point, Size::new(1, 1)));
+        }
     }
 
-    fn init_binary_partial(&mut self) -> Result<(), anyhow::Error> {
-        self.power_on()?;
-        self.inner.command_data(0x3c, [0x80])?;
-        self.set_address()?;
-        self.inner
-            .buffer2
-            .copy_from_slice(self.inner.buffer0.as_slice());
-        self.inner
-            .buffer3
-            .copy_from_slice(self.inner.buffer1.as_slice());
-        self.inner.send_bufs([0x26, 0xa6])?;
-        self.inner.state.init_for = Some(DisplayMode::Partial);
-        Ok(())
+    fn get_binary(&self, point: Point) -> Option<BinaryColor> {
+        self.buf.get_bit(0, point).map(BinaryColor::from)
     }
 
-    fn ensure_inited_binary_partial(&mut self) -> Result<(), anyhow::Error> {
-        if !self.inner.state.is_ready_for(DisplayMode::Partial) {
-            self.init_binary_partial()?;
+    fn set_gray(&mut self, Pixel(point, color): Pixel<Gray2>) {
+        if self.get_gray(point).is_some_and(|old| old != color) {
+            self.buf.set(point, color);
+            self.mark_dirty(Rectangle::new(point, Size::new(1, 1)));
         }
-        debug_assert!(self.inner.state.is_ready_for(DisplayMode::Partial));
-        Ok(())
     }
 
-    pub fn display_binary_partial(&mut self) -> Result<(), anyhow::Error> {
-        self.ensure_inited_binary_partial()?;
+    fn get_gray(&self, point: Point) -> Option<Gray2> {
+        self.buf.get(point)
+    }
 
-        // send buffer
-        self.inner.send_bufs([0x24, 0xa4])?;
-        // turn on display
-        self.inner.command_data(0x22, [0xff])?;
-        self.inner.spi_interface.command(0x20)?;
+    /// Fill `area` of the screen, `bits` is the color split into planes.
+    fn fill(&mut self, area: &Rectangle, bits: u8, planes: usize) {
+        for plane in 0..planes {
+            self.buf.fill_plane(plane, area, bits >> plane & 1 != 0);
+        }
+        self.mark_dirty(area.intersection(&self.buf.bounding_box()));
+    }
 
-        self.inner.spi_interface.delay(DelayStep::Us(200));
-        self.wait_busy()?;
-        Ok(())
+    /// The dirty area as a fraction of the screen.
+    fn dirty_ratio(&self) -> f32 {
+        let screen = self.buf.size();
+        self.dirty.map_or(0.0, |dirty| {
+            (dirty.size.width * dirty.size.height) as f32 / (screen.width * screen.height) as f32
+        })
     }
+
+    fn mark_dirty(&mut self, area: Rectangle) {
+        if area.is_zero_sized() {
+            return;
+        }
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => bounding_box_union(&dirty, &area),
+            None => area,
+        });
+    }
+
+    /// The pixels in `area` have been sent to the controller.
+    ///
+    /// The dirty box shrinks when `area` covers one of its edges over the whole span,
+    /// otherwise what is left is not a box and it is kept as is.
+    fn mark_clean(&mut self, area: &Rectangle) {
+        let Some(dirty) = self.dirty else {
+            return;
+        };
+        let clean = area.intersection(&dirty);
+        if clean.is_zero_sized() {
+            return;
+        }
+        if clean == dirty {
+            self.dirty = None;
+            return;
+        }
+        let (dirty_end, clean_end) = (dirty.top_left + dirty.size, clean.top_left + clean.size);
+        let (mut top_left, mut end) = (dirty.top_left, dirty_end);
+        if clean.size.height == dirty.size.height {
+            if clean.top_left.x == dirty.top_left.x {
+                top_left.x = clean_end.x;
+            } else if clean_end.x == dirty_end.x {
+                end.x = clean.top_left.x;
+            }
+        } else if clean.size.width == dirty.size.width {
+            if clean.top_left.y == dirty.top_left.y {
+                top_left.y = clean_end.y;
+            } else if clean_end.y == dirty_end.y {
+                end.y = clean.top_left.y;
+            }
+        }
+        self.dirty = Some(Rectangle::with_corners(top_left, end - Point::new(1, 1)));
+    }
+
+    fn encode(
+        &self,
+        color: ColorInBuf,
+        orientation: Orientation,
+        format: ExportFormat,
+    ) -> io::Result<Vec<u8>> {
+        match color {
+            ColorInBuf::Binary => export::encode(&self.view::<BinaryColor>(orientation), format),
+            ColorInBuf::Gray => export::encode(&self.view::<Gray2>(orientation), format),
+        }
+    }
+
+    fn view<C>(&self, orientation: Orientation) -> FrameView<'_, C> {
+        FrameView {
+            frame: self,
+            orientation,
+            color: PhantomData,
+        }
+    }
+
+    /// The bytes of `plane` of `region` inside the RAM window of `area`,
+    /// see [`ram_plane`](Self::ram_plane).
+    fn window(
+        &self,
+        region: usize,
+        plane: usize,
+        area: &Rectangle,
+        ram_mirror: Orientation,
+    ) -> Vec<u8> {
+        let bytes = self.buf.buffer(region).rows(plane, area).flatten();
+        if ram_mirror.mirror_x {
+            bytes.map(|byte| byte.reverse_bits()).collect()
+        } else {
+            bytes.copied().collect()
+        }
+    }
+}
+
+/// The frame read as color `C` in `orientation`, stitched across the seam.
+struct FrameView<'a, C> {
+    frame: &'a Frame,
+    orientation: Orientation,
+    color: PhantomData<C>,
 }
 
-impl<'a, C> OriginDimensions for Epd5in79<'a, C> {
+impl<C> OriginDimensions for FrameView<'_, C> {
     fn size(&self) -> Size {
-        (792, 272).into()
+        self.orientation.size(NATIVE_SIZE)
     }
 }
 
-impl<'a> DrawTarget for Epd5in79<'a, BinaryColor> {
+impl GetPixel for FrameView<'_, BinaryColor> {
     type Color = BinaryColor;
-    type Error = Infallible;
 
-    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
-    where
-        I: IntoIterator<Item = Pixel<Self::Color>>,
-    {
-        debug_assert!(matches!(self.inner.state.color_in_buf, ColorInBuf::Binary));
-        for pixel in pixels {
-            self.inner.set_binary(pixel);
-        }
-        Ok(())
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        self.frame
+            .get_binary(self.orientation.to_native(p, NATIVE_SIZE))
     }
 }
 
-impl<'a> DrawTarget for Epd5in79<'a, Gray2> {
+impl GetPixel for FrameView<'_, Gray2> {
     type Color = Gray2;
-    type Error = Infallible;
 
-    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
-    where
-        I: IntoIterator<Item = Pixel<Self::Color>>,
-    {
-        debug_assert!(matches!(self.inner.state.color_in_buf, ColorInBuf::Gray));
-        for pixel in pixels {
-            self.inner.set_gray(pixel);
-        }
-        Ok(())
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        self.frame
+            .get_gray(self.orientation.to_native(p, NATIVE_SIZE))
     }
 }
 
+fn bounding_box_union(a: &Rectangle, b: &Rectangle) -> Rectangle {
+    let top_left = a.top_left.component_min(b.top_left);
+    let bottom_right = (a.top_left + a.size).component_max(b.top_left + b.size);
+    Rectangle::with_corners(top_left, bottom_right - Point::new(1, 1))
+}
+
+/// The parameter of the auto-write commands (0x46/0x47) that fills the whole RAM with `bit`.
+///
+/// Bit 7 is the value of the first step, the steps are as high and as wide as possible,
+/// so the pattern never alternates.
+fn auto_write_pattern(bit: bool) -> u8 {
+    (bit as u8) << 7 | 0x77
+}
+
+/// The controller showing the framebuffer of `region`.
+///
+/// The regions are symmetric, mirroring left to right swaps them.
+fn controller(region: usize, ram_mirror: Orientation) -> usize {
+    region ^ ram_mirror.mirror_x as usize
+}
+
+/// The data entry mode (0x11) of the controller showing the framebuffer of `region`.
+///
+/// The rows are sent top to bottom, the bytes of a row left to right.
+fn data_entry_mode(region: usize, ram_mirror: Orientation) -> (u8, [u8; 1]) {
+    // the master counts X up for the bytes of the master region, the slave down,
+    // as the source outputs of the slave are reversed this also holds for the mirrored image
+    let x_up = region == 0;
+    // both controllers show the last RAM row at the top
+    let y_up = ram_mirror.mirror_y;
+    let cmd = CASCADE.command(controller(region, ram_mirror), 0x11);
+    (cmd, [x_up as u8 | (y_up as u8) << 1])
+}
+
+/// Commands that set the RAM window and the RAM counter of the controller showing `region`
+/// to `area`, in the coordinates of the region, widened to whole bytes.
+fn ram_window(region: usize, area: &Rectangle, ram_mirror: Orientation) -> [(u8, Vec<u8>); 4] {
+    let bottom_right = area.bottom_right().unwrap_or(area.top_left);
+    let (mut x_start, mut x_end) = ((area.top_left.x / 8) as u8, (bottom_right.x / 8) as u8);
+    if region == 1 {
+        // counted down, see `data_entry_mode`
+        let last = (CASCADE.regions()[region].width / 8 - 1) as u8;
+        (x_start, x_end) = (last - x_start, last - x_end);
+    }
+    let ram_y = |y: i32| {
+        if ram_mirror.mirror_y {
+            y as u16
+        } else {
+            HIGH as u16 - 1 - y as u16
+        }
+    };
+    let [y_start_l, y_start_h] = ram_y(area.top_left.y).to_le_bytes();
+    let [y_end_l, y_end_h] = ram_y(bottom_right.y).to_le_bytes();
+
+    let cmd = |cmd| CASCADE.command(controller(region, ram_mirror), cmd);
+    [
+        (cmd(0x44), vec![x_start, x_end]),
+        (cmd(0x45), vec![y_start_l, y_start_h, y_end_l, y_end_h]),
+        (cmd(0x4e), vec![x_start]),
+        (cmd(0x4f), vec![y_start_l, y_start_h]),
+    ]
+}
+
 #[derive(Debug, Clone, Copy)]
 enum ColorInBuf {
     Binary,
@@ -656,125 +1264,847 @@ enum ColorInBuf {
 #[derive(Debug, Clone, Copy)]
 struct Epd5in79State {
     power_on: Option<Instant>,
+    /// The mode of the last deep sleep, only meaningful while `power_on` is `None`.
+    sleep_mode: DeepSleepMode,
     color_in_buf: ColorInBuf,
     init_for: Option<DisplayMode>,
+    /// The band of the waveform loaded by the init.
+    init_band: usize,
+    stats: RefreshStats,
+    /// Plane 1 of the framebuffer holds the binary image shown on the panel.
+    old_in_frame: bool,
+    /// The red RAM of the controllers holds the binary image shown on the panel.
+    old_in_ram: bool,
+    /// The bw RAM of the controllers holds the binary image shown on the panel.
+    new_in_ram: bool,
+    temperature: Option<Temperature>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum DisplayMode {
+/// The display updates of the panel, each with its own waveforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisplayMode {
+    /// [`RefreshMode::Full`].
     Full,
+    /// [`RefreshMode::Fast`].
     Fast,
+    /// [`RefreshMode::Partial`].
     Partial,
+    /// [`Epd5in79::display_gray2`].
     Gray2,
 }
 
+impl DisplayMode {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The bit of the display update control (0x22) selecting display mode 2,
+    /// which drives the pixels by the red RAM too.
+    fn display_mode_2(self) -> u8 {
+        match self {
+            Self::Full | Self::Fast => 0x00,
+            Self::Partial | Self::Gray2 => 0x08,
+        }
+    }
+}
+
+impl From<RefreshMode> for DisplayMode {
+    fn from(mode: RefreshMode) -> Self {
+        match mode {
+            RefreshMode::Full => Self::Full,
+            RefreshMode::Fast => Self::Fast,
+            RefreshMode::Partial => Self::Partial,
+        }
+    }
+}
+
+/// The waveforms of the vendor driver, by [`DisplayMode`].
+fn default_waveforms() -> [WaveformBands; 4] {
+    [
+        WaveformBands::new(WaveformSource::Otp),
+        // the waveform of 100 °C is too short for the slow particles of a cold panel
+        WaveformBands::new(WaveformSource::OtpAt(100.0)).with_band(10.0, WaveformSource::Otp),
+        WaveformBands::new(WaveformSource::Otp),
+        WaveformBands::new(WaveformSource::Lut(Waveform::gray2())),
+    ]
+}
+
 impl Epd5in79State {
+    fn new() -> Self {
+        Self {
+            power_on: None,
+            sleep_mode: DeepSleepMode::PowerOff,
+            color_in_buf: ColorInBuf::Binary,
+            init_for: None,
+            init_band: 0,
+            stats: RefreshStats::new(),
+            old_in_frame: false,
+            old_in_ram: false,
+            new_in_ram: false,
+            temperature: None,
+        }
+    }
+
+    /// The refresh mode of `refresh()`, `changed` is the fraction of the screen that changed.
+    fn choose_mode(&self, policy: &RefreshPolicy, changed: f32, dirty: bool) -> RefreshMode {
+        match policy.choose(&self.stats, changed) {
+            // the fast and partial waveforms are not rated in the cold
+            RefreshMode::Fast | RefreshMode::Partial
+                if dirty && self.temperature.is_some_and(Temperature::is_below_rated) =>
+            {
+                RefreshMode::Full
+            }
+            mode => mode,
+        }
+    }
+
+    fn sleeping_in(&self) -> Option<DeepSleepMode> {
+        self.is_deepsleep().then_some(self.sleep_mode)
+    }
+
+    /// The whole framebuffer was shown by a binary refresh in `mode`
+    /// and copied to the "old data".
+    fn shown_all(&mut self, mode: RefreshMode) {
+        self.old_in_frame = true;
+        // only display mode 2 copies the bw RAM to the red RAM
+        self.old_in_ram = mode == RefreshMode::Partial;
+        self.new_in_ram = true;
+    }
+
     fn is_deepsleep(&self) -> bool {
         self.power_on.is_none()
     }
 
-    fn check_deepsleep(&self) -> Result<(), anyhow::Error> {
+    fn check_deepsleep<SpiE, PinE, BusyE>(&self) -> Result<(), Error<SpiE, PinE, BusyE>> {
         if self.is_deepsleep() {
-            anyhow::bail!("epd is in deep sleep mode");
+            return Err(Error::DeepSleep);
         }
         Ok(())
     }
 
-    fn is_ready_for(&mut self, mode: DisplayMode) -> bool {
-        (!self.is_deepsleep()) && self.init_for == Some(mode)
+    fn is_ready_for(&self, mode: DisplayMode, band: usize) -> bool {
+        (!self.is_deepsleep()) && self.init_for == Some(mode) && self.init_band == band
     }
 }
 
-fn is_point_in_screen(point: Point) -> bool {
-    point.x >= 0 && point.x < 792 && point.y >= 0 && point.y < 272
-}
+#[cfg(test)]
+mod tests {
+    use waveshare_epd_core::mock::{MockBusyPin, MockDelay, MockHal, MockOutputPin, MockSpi, Pin};
+
+    use super::*;
+
+    type MockEpd = Epd5in79Impl<MockSpi, MockBusyPin, MockOutputPin, MockDelay>;
+
+    /// The data entry mode and RAM window of both controllers, see `Driver::set_address`.
+    const ADDRESS: [u8; 10] = [0x11, 0x44, 0x45, 0x4e, 0x4f, 0x91, 0xc4, 0xc5, 0xce, 0xcf];
+    const PLANES: [u8; 4] = [0x24, 0x26, 0xa4, 0xa6];
+
+    const MIRROR_X: Orientation = Orientation {
+        rotation: Rotation::Deg0,
+        mirror_x: true,
+        mirror_y: false,
+    };
+    const MIRROR_Y: Orientation = Orientation {
+        rotation: Rotation::Deg0,
+        mirror_x: false,
+        mirror_y: true,
+    };
+
+    fn mock_epd(hal: &MockHal) -> MockEpd {
+        Epd5in79Impl::new(
+            hal.spi(),
+            hal.pin(Pin::Rst),
+            hal.pin(Pin::Dc),
+            Some(hal.pin(Pin::Cs)),
+            hal.busy(),
+            hal.pin(Pin::Pwr),
+            hal.delay(),
+        )
+    }
+
+    /// The commands sent, without their data.
+    fn sent(hal: &MockHal) -> Vec<u8> {
+        hal.commands().iter().map(|c| c.cmd).collect()
+    }
+
+    /// The data of the last `cmd` sent.
+    fn last_data(hal: &MockHal, cmd: u8) -> Vec<u8> {
+        let commands = hal.commands();
+        let last = commands.iter().rev().find(|c| c.cmd == cmd);
+        last.expect("command not sent").data.clone()
+    }
+
+    fn black(x: i32, y: i32) -> Pixel<BinaryColor> {
+        Pixel(Point::new(x, y), BinaryColor::Off)
+    }
 
-#[rustfmt::skip]
-static LUT_DATA: &[u8; 227] = &[
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    #[test]
+    fn full_init() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.as_binary().display_binary_full().unwrap();
+
+        assert_eq!(hal.pin_levels(Pin::Pwr), [true]);
+        assert_eq!(hal.pin_levels(Pin::Rst), [true, false, true]);
+        let expected = [&[0x12][..], &ADDRESS, &[0x18], &PLANES, &[0x22, 0x20]].concat();
+        assert_eq!(sent(&hal), expected);
+        hal.assert_sequence([(0x11, &[0x01][..]), (0x91, &[0x00]), (0x18, &[0x80])]);
+        hal.assert_command(0x44, [0, 49]);
+        hal.assert_command(0xc4, [49, 0]);
+        // the temperature of the sensor selects the waveform of the OTP
+        assert_eq!(last_data(&hal, 0x22), [0xf7]);
+        assert!(PLANES
+            .iter()
+            .all(|&cmd| last_data(&hal, cmd).len() == 50 * HIGH as usize));
+    }
 
-    0x01, 0x4A, 0x00, 0x00, 0x00, 0x01, 0x00,
-    0x01, 0x82, 0x42, 0x00, 0x00, 0x10, 0x00,
-    0x01, 0x8A, 0x00, 0x00, 0x00, 0x01, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    #[test]
+    fn fast_init() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.as_binary().display_binary_fast().unwrap();
+
+        let expected = [
+            &[0x12, 0x18, 0x22, 0x20, 0x1a, 0x22, 0x20][..],
+            &ADDRESS,
+            &PLANES,
+            &[0x22, 0x20],
+        ]
+        .concat();
+        assert_eq!(sent(&hal), expected);
+        // the waveform of 100 °C is loaded once by the init
+        hal.assert_sequence([
+            (0x22, &[0xb1][..]),
+            (0x1a, &[0x64, 0x00]),
+            (0x22, &[0x91]),
+            (0x22, &[0xc7]),
+        ]);
+    }
 
-    0x01, 0x41, 0x00, 0x00, 0x00, 0x01, 0x00,
-    0x01, 0x82, 0x42, 0x00, 0x00, 0x10, 0x00,
-    0x01, 0x81, 0x00, 0x00, 0x00, 0x01, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    #[test]
+    fn fast_below_band() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.set_temperature(Some(5.0)).unwrap();
+        epd.as_binary().display_binary_fast().unwrap();
+
+        // the OTP waveform of the supplied temperature, loaded by the display update
+        let expected = [&[0x12, 0x18][..], &ADDRESS, &PLANES, &[0x1a, 0x22, 0x20]].concat();
+        assert_eq!(sent(&hal), expected);
+        assert_eq!(last_data(&hal, 0x1a), [0x05, 0x00]);
+        assert_eq!(last_data(&hal, 0x22), [0xd7]);
+    }
 
-    0x01, 0x81, 0x00, 0x00, 0x00, 0x01, 0x00,
-    0x01, 0x82, 0x42, 0x00, 0x00, 0x10, 0x00,
-    0x01, 0x41, 0x00, 0x00, 0x00, 0x01, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    #[test]
+    fn implausible_temperature() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.set_temperature(Some(5.0)).unwrap();
+        for celsius in [f32::NAN, -41.0, 86.0] {
+            assert!(matches!(
+                epd.set_temperature(Some(celsius)),
+                Err(Error::ImplausibleTemperature(_))
+            ));
+        }
+        assert_eq!(epd.temperature().unwrap().celsius, 5.0);
+
+        // the default fast waveform is outside of the sensor range, but fits the register
+        epd.as_binary().display_binary_fast().unwrap();
+        for celsius in [f32::NAN, 128.0] {
+            epd.set_waveform_bands(
+                DisplayMode::Fast,
+                WaveformBands::new(WaveformSource::OtpAt(celsius)),
+            );
+            hal.clear();
+            assert!(matches!(
+                epd.as_binary().display_binary_fast(),
+                Err(Error::ImplausibleTemperature(_))
+            ));
+            hal.assert_no_command(0x1a);
+        }
+    }
 
-    0x01, 0x8A, 0x00, 0x00, 0x00, 0x01, 0x00,
-    0x01, 0x82, 0x42, 0x00, 0x00, 0x10, 0x00,
-    0x01, 0x4A, 0x00, 0x00, 0x00, 0x01, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    #[test]
+    fn partial_init() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.as_binary().display_binary_partial().unwrap();
+
+        // the framebuffer is the "old data", then the new data is sent
+        let expected = [
+            &[0x12, 0x3c, 0x18][..],
+            &ADDRESS,
+            &[0x26, 0xa6, 0x24, 0xa4, 0x22, 0x20],
+        ]
+        .concat();
+        assert_eq!(sent(&hal), expected);
+        hal.assert_command(0x3c, [0x80]);
+        assert_eq!(last_data(&hal, 0x22), [0xff]);
+    }
 
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    #[test]
+    fn gray2_init() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.as_gray2().display_gray2().unwrap();
+
+        let expected = [
+            &[0x12, 0x0c, 0x3c][..],
+            &ADDRESS,
+            &[0x32, 0x3f, 0x03, 0x04, 0x2c],
+            &PLANES,
+            &[0x22, 0x20],
+        ]
+        .concat();
+        assert_eq!(sent(&hal), expected);
+        hal.assert_command(0x0c, [0x8b, 0x9c, 0xa6, 0x0f]);
+        hal.assert_command(0x3c, [0x81]);
+        assert_eq!(last_data(&hal, 0x32), Waveform::gray2().lut_bytes());
+        assert_eq!(last_data(&hal, 0x22), [0xcf]);
+    }
 
-    0x02, 0x00, 0x00
-];
+    #[test]
+    fn init_once_per_mode() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        let mut binary = epd.as_binary();
+        binary.display_binary_full().unwrap();
+        hal.clear();
+        binary.display_binary_full().unwrap();
+        assert_eq!(sent(&hal), [&PLANES[..], &[0x22, 0x20]].concat());
+
+        binary.display_binary_fast().unwrap();
+        hal.assert_command(0x12, []);
+    }
 
-fn set_binary_value(color: BinaryColor, offset: u8, value: &mut u8) {
-    if color.is_on() {
-        *value |= 1 << offset;
-    } else {
-        *value &= !(1 << offset);
+    #[test]
+    fn busy_timeout() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.driver.config.busy_timeout = Duration::from_millis(1);
+        hal.set_busy_idle(true);
+        assert!(matches!(
+            epd.as_binary().display_binary_full(),
+            Err(Error::TimeOut(_))
+        ));
     }
-}
 
-fn set_gray_value(color: Gray2, offset: u8, bw_value: &mut u8, r_value: &mut u8) {
-    match color.luma() {
-        0 => {
-            *bw_value &= !(1 << offset);
-            *r_value &= !(1 << offset);
+    #[test]
+    fn seam() {
+        // the columns 392..400 are shown by both controllers
+        let mut frame = Frame::new();
+        for x in [391, 392, 399, 400] {
+            frame.set_binary(Pixel(Point::new(x, 0), BinaryColor::Off));
         }
-        1 => {
-            *bw_value |= 1 << offset;
-            *r_value &= !(1 << offset);
+        assert_eq!(frame.plane(0x24)[48..50], [0xfe, 0x7e]);
+        assert_eq!(frame.plane(0xa4)[..2], [0x7e, 0x7f]);
+        assert_eq!(frame.plane(0x24)[50..], frame.plane(0xa4)[50..]);
+        assert_eq!(
+            frame.dirty,
+            Some(Rectangle::new(Point::new(391, 0), Size::new(10, 1)))
+        );
+    }
+
+    #[test]
+    fn seam_on_both_controllers() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        let mut binary = epd.as_binary();
+        binary
+            .draw_iter([black(391, 0), black(395, 0), black(400, 0)])
+            .unwrap();
+        binary.display_binary_full().unwrap();
+
+        let master = last_data(&hal, 0x24);
+        let slave = last_data(&hal, 0xa4);
+        assert_eq!(master[48..50], [0xfe, 0xef]);
+        assert_eq!(slave[..2], [0xef, 0x7f]);
+        assert!(master[50..]
+            .iter()
+            .chain(&slave[2..])
+            .all(|&byte| byte == 0xff));
+    }
+
+    #[test]
+    fn partial_region_across_seam() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        let mut binary = epd.as_binary();
+        binary.display_binary_partial().unwrap();
+        hal.clear();
+        binary.draw_iter([black(390, 0), black(401, 0)]).unwrap();
+        let area = Rectangle::new(Point::new(388, 0), Size::new(16, 1));
+        binary.display_binary_partial_region(area).unwrap();
+
+        let expected = [
+            &[0x44, 0x45, 0x4e, 0x4f, 0x24, 0xc4, 0xc5, 0xce, 0xcf, 0xa4][..],
+            &ADDRESS,
+            &[0x22, 0x20],
+        ]
+        .concat();
+        assert_eq!(sent(&hal), expected);
+        hal.assert_sequence([
+            (0x44, &[48, 49][..]),
+            (0x45, &[0x0f, 0x01, 0x0f, 0x01]),
+            (0x24, &[0xfd, 0xff]),
+            (0xc4, &[49, 48]),
+            (0xa4, &[0xff, 0xbf]),
+        ]);
+    }
+
+    #[test]
+    fn dirty_area() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        assert_eq!(epd.dirty_area(), None);
+        let mut binary = epd.as_binary();
+        binary.draw_iter([black(10, 5), black(30, 20)]).unwrap();
+        binary
+            .fill_solid(
+                &Rectangle::new(Point::new(780, 260), Size::new(40, 40)),
+                BinaryColor::On,
+            )
+            .unwrap();
+        assert_eq!(
+            epd.dirty_area(),
+            Some(Rectangle::with_corners(
+                Point::new(10, 5),
+                Point::new(791, 271)
+            ))
+        );
+
+        epd.as_binary().display_binary_full().unwrap();
+        assert_eq!(epd.dirty_area(), None);
+    }
+
+    #[test]
+    fn partially_cleaned() {
+        let dirty = Rectangle::new(Point::new(10, 10), Size::new(20, 10));
+        let cases = [
+            // the left part over the whole height
+            (
+                Rectangle::new(Point::new(0, 0), Size::new(15, 30)),
+                Some(Rectangle::new(Point::new(15, 10), Size::new(15, 10))),
+            ),
+            // the right part
+            (
+                Rectangle::new(Point::new(20, 10), Size::new(10, 10)),
+                Some(Rectangle::new(Point::new(10, 10), Size::new(10, 10))),
+            ),
+            // the top rows over the whole width
+            (
+                Rectangle::new(Point::new(10, 0), Size::new(20, 12)),
+                Some(Rectangle::new(Point::new(10, 12), Size::new(20, 8))),
+            ),
+            // the bottom rows
+            (
+                Rectangle::new(Point::new(0, 18), Size::new(40, 5)),
+                Some(Rectangle::new(Point::new(10, 10), Size::new(20, 8))),
+            ),
+            // a hole in the middle keeps the whole box
+            (
+                Rectangle::new(Point::new(12, 0), Size::new(5, 30)),
+                Some(dirty),
+            ),
+            // a corner keeps the whole box
+            (
+                Rectangle::new(Point::new(0, 0), Size::new(15, 15)),
+                Some(dirty),
+            ),
+            (
+                Rectangle::new(Point::new(50, 50), Size::new(5, 5)),
+                Some(dirty),
+            ),
+            (Rectangle::new(Point::new(5, 5), Size::new(30, 30)), None),
+        ];
+        for (area, expected) in cases {
+            let mut frame = Frame::new();
+            frame.dirty = Some(dirty);
+            frame.mark_clean(&area);
+            assert_eq!(frame.dirty, expected, "{area:?}");
         }
-        2 => {
-            *bw_value &= !(1 << offset);
-            *r_value |= 1 << offset;
+    }
+
+    #[test]
+    fn partial_region_keeps_rest_dirty() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        let mut binary = epd.as_binary();
+        binary.display_binary_partial().unwrap();
+        binary.draw_iter([black(10, 0), black(100, 0)]).unwrap();
+
+        let left = Rectangle::new(Point::new(0, 0), Size::new(64, 1));
+        binary.display_binary_partial_region(left).unwrap();
+        assert_eq!(
+            epd.dirty_area(),
+            Some(Rectangle::new(Point::new(64, 0), Size::new(37, 1)))
+        );
+
+        let right = Rectangle::new(Point::new(64, 0), Size::new(64, 1));
+        epd.as_binary()
+            .display_binary_partial_region(right)
+            .unwrap();
+        assert_eq!(epd.dirty_area(), None);
+    }
+
+    #[test]
+    fn refresh_policy() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        let mut binary = epd.as_binary();
+        binary.draw_iter([black(0, 0)]).unwrap();
+        assert_eq!(binary.refresh().unwrap(), Some(RefreshMode::Full));
+
+        binary.draw_iter([black(1, 0)]).unwrap();
+        assert_eq!(binary.refresh().unwrap(), Some(RefreshMode::Partial));
+        assert_eq!(last_data(&hal, 0x24), [0x3f]);
+
+        hal.clear();
+        assert_eq!(binary.refresh().unwrap(), None);
+        assert!(hal.commands().is_empty());
+
+        let screen = binary.bounding_box();
+        binary.fill_solid(&screen, BinaryColor::Off).unwrap();
+        assert_eq!(binary.refresh().unwrap(), Some(RefreshMode::Fast));
+
+        // the fast and partial waveforms are not rated in the cold
+        binary.set_temperature(Some(-5.0)).unwrap();
+        binary
+            .draw_iter([Pixel(Point::zero(), BinaryColor::On)])
+            .unwrap();
+        assert_eq!(binary.refresh().unwrap(), Some(RefreshMode::Full));
+    }
+
+    #[test]
+    fn cold_forces_full() {
+        let mut state = Epd5in79State::new();
+        state.stats.record(RefreshMode::Full);
+        let policy = RefreshPolicy::DEFAULT;
+        assert_eq!(state.choose_mode(&policy, 0.1, true), RefreshMode::Partial);
+        assert_eq!(state.choose_mode(&policy, 0.9, true), RefreshMode::Fast);
+
+        state.temperature = Some(Temperature {
+            celsius: -5.0,
+            source: TemperatureSource::External,
+        });
+        assert_eq!(state.choose_mode(&policy, 0.1, true), RefreshMode::Full);
+        assert_eq!(state.choose_mode(&policy, 0.9, true), RefreshMode::Full);
+        // nothing is refreshed without changes
+        assert_eq!(state.choose_mode(&policy, 0.0, false), RefreshMode::Partial);
+    }
+
+    #[test]
+    fn deep_sleep_saves_state_file() {
+        let path = std::env::temp_dir().join(format!("epd5in79-{}-sleep", std::process::id()));
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.set_state_file(Some(path.clone()));
+        let mut binary = epd.as_binary();
+        binary.draw_iter([black(395, 3)]).unwrap();
+        binary.display_binary_full().unwrap();
+        assert!(!path.exists());
+        epd.deep_sleep().unwrap();
+
+        let mut frame = Frame::new();
+        let mut state = Epd5in79State::new();
+        let loaded = state_file::load(&path, &mut frame, &mut state, Orientation::DEFAULT);
+        let _ = std::fs::remove_file(&path);
+        assert!(loaded.unwrap());
+        assert_eq!(state.sleeping_in(), Some(epd.deep_sleep_mode()));
+        assert_eq!(frame.get_binary(Point::new(395, 3)), Some(BinaryColor::Off));
+        assert_eq!(frame.dirty, None);
+    }
+
+    #[test]
+    fn export_seam() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.as_binary()
+            .draw_iter([391, 392, 395, 399, 400].map(|x| black(x, 1)))
+            .unwrap();
+
+        let pbm = epd.export_bytes(ExportFormat::Pbm).unwrap();
+        let header = b"P4\n792 272\n";
+        assert_eq!(pbm[..header.len()], *header);
+        let rows = &pbm[header.len()..];
+        assert_eq!(rows.len(), 99 * 272);
+        // PBM uses 1 for black, the columns shown by both controllers are exported once
+        assert_eq!(rows[99 + 48..99 + 51], [0x01, 0x91, 0x80]);
+        assert!(rows[..99].iter().all(|&byte| byte == 0));
+        assert_eq!(rows.iter().map(|byte| byte.count_ones()).sum::<u32>(), 5);
+
+        let mut gray = epd.as_gray2();
+        gray.clear(Gray2::WHITE).unwrap();
+        gray.draw_iter([
+            Pixel(Point::new(391, 0), Gray2::BLACK),
+            Pixel(Point::new(392, 0), Gray2::new(1)),
+            Pixel(Point::new(399, 0), Gray2::new(2)),
+        ])
+        .unwrap();
+        let pgm = epd.export_bytes(ExportFormat::Pgm).unwrap();
+        let header = b"P5\n792 272\n255\n";
+        assert_eq!(pgm[..header.len()], *header);
+        let row = &pgm[header.len()..][..792];
+        assert_eq!(
+            row[390..401],
+            [0xff, 0x00, 0x55, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xaa, 0xff]
+        );
+    }
+
+    fn rotated(rotation: Rotation, mirror_x: bool) -> Orientation {
+        Orientation {
+            rotation,
+            mirror_x,
+            mirror_y: false,
         }
-        3 => {
-            *bw_value |= 1 << offset;
-            *r_value |= 1 << offset;
+    }
+
+    #[test]
+    fn split_orientation() {
+        for (orientation, frame_rotation, (ram_x, ram_y)) in [
+            (
+                rotated(Rotation::Deg0, false),
+                Rotation::Deg0,
+                (false, false),
+            ),
+            (rotated(Rotation::Deg0, true), Rotation::Deg0, (true, false)),
+            (
+                rotated(Rotation::Deg180, false),
+                Rotation::Deg0,
+                (true, true),
+            ),
+            (
+                rotated(Rotation::Deg90, false),
+                Rotation::Deg90,
+                (false, false),
+            ),
+            (
+                rotated(Rotation::Deg90, true),
+                Rotation::Deg90,
+                (false, true),
+            ),
+            (
+                rotated(Rotation::Deg270, false),
+                Rotation::Deg90,
+                (true, true),
+            ),
+        ] {
+            let config = Config::DEFAULT.with_orientation(orientation);
+            assert_eq!(config.orientation, orientation);
+            assert_eq!(config.frame_orientation.rotation, frame_rotation);
+            let ram_mirror = (config.ram_mirror.mirror_x, config.ram_mirror.mirror_y);
+            assert_eq!(ram_mirror, (ram_x, ram_y), "{orientation:?}");
         }
-        _ => unreachable!(),
+        let config = Config::DEFAULT.with_orientation(rotated(Rotation::Deg270, false));
+        assert_eq!(config.size(), Size::new(HIGH, WIDTH));
     }
-}
 
-fn get_binary_from_value(offset: u8, value: u8) -> BinaryColor {
-    if value & 1 << offset != 0 {
-        BinaryColor::On
-    } else {
-        BinaryColor::Off
+    #[test]
+    fn rotated_draw_target() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.set_rotation(Rotation::Deg90);
+        let mut binary = epd.as_binary();
+        assert_eq!(binary.size(), Size::new(HIGH, WIDTH));
+        binary.display_binary_partial().unwrap();
+        hal.clear();
+
+        // the top left corner of the portrait image is the top right corner of the panel
+        binary.draw_iter([black(0, 0)]).unwrap();
+        assert_eq!(binary.pixel(Point::zero()), Some(BinaryColor::Off));
+        assert_eq!(binary.pixel(Point::new(1, 0)), Some(BinaryColor::On));
+        assert_eq!(binary.pixel(Point::new(0, WIDTH as i32)), None);
+        let corner = Rectangle::new(Point::zero(), Size::new(1, 1));
+        assert_eq!(epd.dirty_area(), Some(corner));
+
+        epd.as_binary()
+            .display_binary_partial_region(corner)
+            .unwrap();
+        hal.assert_sequence([(0xc4, &[0, 0][..]), (0xce, &[0]), (0xa4, &[0xfe])]);
+        assert_eq!(epd.dirty_area(), None);
+    }
+
+    #[test]
+    fn data_entry_modes() {
+        // master, slave
+        assert_eq!(data_entry_mode(0, Orientation::DEFAULT), (0x11, [0x01]));
+        assert_eq!(data_entry_mode(1, Orientation::DEFAULT), (0x91, [0x00]));
+        assert_eq!(data_entry_mode(0, MIRROR_Y), (0x11, [0x03]));
+        assert_eq!(data_entry_mode(1, MIRROR_Y), (0x91, [0x02]));
+        // the regions swap controllers
+        assert_eq!(data_entry_mode(0, MIRROR_X), (0x91, [0x01]));
+        assert_eq!(data_entry_mode(1, MIRROR_X), (0x11, [0x00]));
+    }
+
+    #[test]
+    fn ram_windows() {
+        let region = Rectangle::new(Point::zero(), Size::new(400, HIGH));
+        assert_eq!(
+            ram_window(0, &region, Orientation::DEFAULT),
+            [
+                (0x44, vec![0, 49]),
+                (0x45, vec![0x0f, 0x01, 0x00, 0x00]),
+                (0x4e, vec![0]),
+                (0x4f, vec![0x0f, 0x01]),
+            ]
+        );
+        // the slave counts X down
+        assert_eq!(
+            ram_window(1, &region, Orientation::DEFAULT),
+            [
+                (0xc4, vec![49, 0]),
+                (0xc5, vec![0x0f, 0x01, 0x00, 0x00]),
+                (0xce, vec![49]),
+                (0xcf, vec![0x0f, 0x01]),
+            ]
+        );
+
+        // widened to whole bytes
+        let area = Rectangle::new(Point::new(9, 10), Size::new(10, 5));
+        assert_eq!(
+            ram_window(0, &area, MIRROR_Y),
+            [
+                (0x44, vec![1, 2]),
+                (0x45, vec![10, 0, 14, 0]),
+                (0x4e, vec![1]),
+                (0x4f, vec![10, 0]),
+            ]
+        );
+        assert_eq!(
+            ram_window(1, &area, MIRROR_X),
+            [
+                (0x44, vec![48, 47]),
+                (0x45, vec![0x05, 0x01, 0x01, 0x01]),
+                (0x4e, vec![48]),
+                (0x4f, vec![0x05, 0x01]),
+            ]
+        );
+    }
+
+    #[test]
+    fn ram_plane_mirrored() {
+        let mut frame = Frame::new();
+        frame.set_binary(Pixel(Point::new(0, 0), BinaryColor::Off));
+        assert_eq!(frame.ram_plane(0x24, Orientation::DEFAULT)[0], 0x7f);
+        assert_eq!(frame.ram_plane(0xa4, Orientation::DEFAULT)[0], 0xff);
+        // the master region is shown by the slave, its bits reversed
+        assert_eq!(frame.ram_plane(0xa4, MIRROR_X)[0], 0xfe);
+        assert_eq!(frame.ram_plane(0x24, MIRROR_X)[0], 0xff);
+    }
+
+    #[test]
+    fn upside_down_in_ram() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.set_rotation(Rotation::Deg180);
+        let mut binary = epd.as_binary();
+        binary.draw_iter([black(0, 0)]).unwrap();
+        binary.display_binary_full().unwrap();
+
+        // both controllers count Y up, the master region is sent to the slave
+        hal.assert_sequence([(0x91, &[0x03][..]), (0x11, &[0x02])]);
+        // the top left corner of the image is the bottom right corner of the panel,
+        // the first byte of the slave RAM as the gates are scanned from the bottom
+        let slave = last_data(&hal, 0xa4);
+        assert_eq!(slave[0], 0xfe);
+        assert!(slave[1..].iter().all(|&byte| byte == 0xff));
+        assert!(last_data(&hal, 0x24).iter().all(|&byte| byte == 0xff));
+    }
+
+    #[test]
+    fn failed_clear_keeps_frame() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        let gray = Pixel(Point::new(10, 10), Gray2::new(1));
+        let mut gray2 = epd.as_gray2();
+        gray2.draw_iter([gray]).unwrap();
+        gray2.display_gray2().unwrap();
+
+        epd.driver.config.busy_timeout = Duration::from_millis(1);
+        hal.set_busy_idle(true);
+        assert!(matches!(
+            epd.clear_screen(BinaryColor::On, RefreshMode::Full),
+            Err(Error::TimeOut(_))
+        ));
+        assert!(matches!(epd.driver.state.color_in_buf, ColorInBuf::Gray));
+        assert_eq!(epd.as_gray2().pixel(gray.0), Some(gray.1));
+
+        hal.set_busy_idle(false);
+        epd.clear_screen(BinaryColor::On, RefreshMode::Full)
+            .unwrap();
+        assert!(matches!(epd.driver.state.color_in_buf, ColorInBuf::Binary));
+        assert_eq!(epd.as_binary().pixel(gray.0), Some(BinaryColor::On));
+    }
+
+    #[test]
+    fn deep_sleep_power_off() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.as_binary().display_binary_full().unwrap();
+        epd.deep_sleep().unwrap();
+
+        assert_eq!(hal.commands().last().unwrap().cmd, 0x10);
+        assert_eq!(last_data(&hal, 0x10), [0x03]);
+        assert_eq!(hal.pin_levels(Pin::Pwr).last(), Some(&false));
+        assert_eq!(hal.pin_levels(Pin::Rst).last(), Some(&false));
+        assert_eq!(epd.sleeping_in(), Some(DeepSleepMode::PowerOff));
+        assert!(matches!(epd.as_binary().wait_busy(), Err(Error::DeepSleep)));
+    }
+
+    #[test]
+    fn deep_sleep_retain_ram() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        epd.set_deep_sleep_mode(DeepSleepMode::RetainRam);
+        epd.as_binary().display_binary_partial().unwrap();
+        epd.deep_sleep().unwrap();
+        assert_eq!(last_data(&hal, 0x10), [0x01]);
+        assert_eq!(hal.pin_levels(Pin::Pwr), [true]);
+
+        // the "old data" is still in the RAM
+        hal.clear();
+        epd.as_binary().display_binary_partial().unwrap();
+        let expected = [&[0x12, 0x3c, 0x18][..], &ADDRESS, &[0x24, 0xa4, 0x22, 0x20]].concat();
+        assert_eq!(sent(&hal), expected);
     }
-}
 
-fn get_gray_from_values(offset: u8, bw_value: u8, r_value: u8) -> Gray2 {
-    match (bw_value & 1 << offset != 0, r_value & 1 << offset != 0) {
-        (true, true) => Gray2::WHITE,
-        (false, true) => Gray2::new(2),
-        (true, false) => Gray2::new(1),
-        (false, false) => Gray2::BLACK,
+    #[test]
+    fn first_partial_region() {
+        let hal = MockHal::new();
+        let mut epd = mock_epd(&hal);
+        let mut binary = epd.as_binary();
+        binary.draw_iter([black(0, 0), black(100, 0)]).unwrap();
+        let area = Rectangle::new(Point::zero(), Size::new(8, 1));
+        binary.display_binary_partial_region(area).unwrap();
+
+        // the bw RAM gets the old data once, outside of the region it stays on the screen
+        let expected = [
+            &[0x12, 0x3c, 0x18][..],
+            &ADDRESS,
+            &[0x26, 0xa6],
+            &ADDRESS,
+            &[0x24, 0xa4, 0x44, 0x45, 0x4e, 0x4f, 0x24],
+            &ADDRESS,
+            &[0x22, 0x20],
+        ]
+        .concat();
+        assert_eq!(sent(&hal), expected);
+        assert_eq!(last_data(&hal, 0x24), [0x7f]);
+        assert_eq!(
+            epd.dirty_area(),
+            Some(Rectangle::new(Point::new(8, 0), Size::new(93, 1)))
+        );
+
+        // the next region only sends its window
+        hal.clear();
+        let area = Rectangle::new(Point::new(96, 0), Size::new(8, 1));
+        epd.as_binary().display_binary_partial_region(area).unwrap();
+        let expected = [&[0x44, 0x45, 0x4e, 0x4f, 0x24][..], &ADDRESS, &[0x22, 0x20]].concat();
+        assert_eq!(sent(&hal), expected);
+        assert_eq!(last_data(&hal, 0x24), [0xf7]);
+        assert_eq!(
+            epd.dirty_area(),
+            Some(Rectangle::new(Point::new(8, 0), Size::new(88, 1)))
+        );
     }
 }
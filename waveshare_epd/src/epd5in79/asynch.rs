@@ -0,0 +1,562 @@
+//! Async driver for **epd5in79**, built on [`AsyncSpiInterface`].
+//!
+//! It runs the same command sequences as [`Epd5in79Impl`](super::Epd5in79Impl),
+//! but waits for the busy pin by interrupt, so it can be driven from tokio or embassy
+//! without a dedicated thread.
+//!
+//! Async code cannot run in [`Drop`], so unlike the blocking driver,
+//! [`Epd5in79AsyncImpl`] does **not** enter deep sleep mode automatically,
+//! call [`Epd5in79AsyncImpl::deep_sleep`] explicitly.
+
+use std::{
+    convert::Infallible,
+    fmt::Debug,
+    io,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use embedded_graphics_core::{
+    image::GetPixel,
+    pixelcolor::{BinaryColor, Gray2},
+    prelude::*,
+    primitives::Rectangle,
+};
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::{
+    delay::DelayNs,
+    digital::Wait,
+    spi::{self, SpiDevice},
+};
+use waveshare_epd_core::{
+    async_spi_interface::AsyncSpiInterface,
+    dither::ErrorDiffusionKernel,
+    epd::RefreshMode,
+    error::ConfigError,
+    export::ExportFormat,
+    orientation::{Orientation, Rotation},
+    refresh::{RefreshPolicy, RefreshStats},
+    spi_interface::{DelayStep, SpiInterfaceError},
+};
+
+use super::{
+    driver::{Bus, BusError, Driver},
+    Config, DeepSleepMode, DisplayMode, Temperature, Waveform, WaveformBands, WaveformSource,
+};
+
+pub struct Epd5in79AsyncImpl<SPI, BUSY, OUT, DELAY> {
+    driver: Driver<AsyncSpiInterface<SPI, BUSY, OUT, DELAY>>,
+}
+
+impl<SPI, BUSY, OUT, DELAY> Debug for Epd5in79AsyncImpl<SPI, BUSY, OUT, DELAY> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Epd5in79AsyncImpl")
+            .field("state", &self.driver.state)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Spi, I, O, D> Bus for AsyncSpiInterface<Spi, I, O, D>
+where
+    Spi: SpiDevice,
+    I: Wait,
+    O: OutputPin,
+    D: DelayNs,
+{
+    type SpiError = <Spi as spi::ErrorType>::Error;
+    type PinError = O::Error;
+    type BusyError = I::Error;
+
+    async fn command(&mut self, cmd: u8) -> Result<(), BusError<Self>> {
+        AsyncSpiInterface::command(self, cmd).await
+    }
+
+    async fn command_data(
+        &mut self,
+        cmd: u8,
+        data: &[u8],
+        chunk_size: usize,
+    ) -> Result<(), BusError<Self>> {
+        AsyncSpiInterface::command_data(self, cmd, data, chunk_size).await
+    }
+
+    async fn command_read(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), BusError<Self>> {
+        AsyncSpiInterface::command_read(self, cmd, buf).await
+    }
+
+    fn set_rst_pin(&mut self, active: bool) -> Result<(), BusError<Self>> {
+        AsyncSpiInterface::set_rst_pin(self, active)
+    }
+
+    fn set_power(&mut self, on: bool) -> Result<(), BusError<Self>> {
+        AsyncSpiInterface::set_power(self, on)
+    }
+
+    async fn delay(&mut self, delay: DelayStep) {
+        AsyncSpiInterface::delay(self, delay).await
+    }
+
+    /// Waits for the falling edge instead of polling.
+    async fn wait_busy(
+        &mut self,
+        _poll_step: DelayStep,
+        timeout: Duration,
+    ) -> Result<(), BusError<Self>> {
+        self.wait_busy_timeout(timeout).await?;
+        Ok(())
+    }
+}
+
+impl<SPI, BUSY, OUT, DELAY> Epd5in79AsyncImpl<SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: Wait,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    pub fn new(
+        spi: SPI,
+        rst_pin: OUT,
+        dc_pin: OUT,
+        cs_pin: Option<OUT>,
+        busy_pin: BUSY,
+        pwr_pin: OUT,
+        delay: DELAY,
+    ) -> Self {
+        Self {
+            driver: Driver::new(AsyncSpiInterface::new(
+                spi, rst_pin, dc_pin, cs_pin, busy_pin, pwr_pin, delay,
+            )),
+        }
+    }
+
+    pub(super) fn with_config(mut self, config: Config) -> Self {
+        self.driver.config = config;
+        self
+    }
+
+    pub fn as_binary(&mut self) -> Epd5in79Async<'_, BinaryColor, SPI, BUSY, OUT, DELAY> {
+        self.as_binary_with(BinaryColor::from)
+    }
+
+    pub fn as_binary_with(
+        &mut self,
+        f: impl Fn(Gray2) -> BinaryColor,
+    ) -> Epd5in79Async<'_, BinaryColor, SPI, BUSY, OUT, DELAY> {
+        self.driver.mapping_to_binary(f);
+        Epd5in79Async {
+            inner: self,
+            color: PhantomData,
+        }
+    }
+
+    pub fn as_gray2(&mut self) -> Epd5in79Async<'_, Gray2, SPI, BUSY, OUT, DELAY> {
+        self.as_gray2_with(Gray2::from)
+    }
+
+    pub fn as_gray2_with(
+        &mut self,
+        f: impl Fn(BinaryColor) -> Gray2,
+    ) -> Epd5in79Async<'_, Gray2, SPI, BUSY, OUT, DELAY> {
+        self.driver.mapping_to_gray2(f);
+        Epd5in79Async {
+            inner: self,
+            color: PhantomData,
+        }
+    }
+
+    /// See [`Epd5in79Impl::as_binary_dithered`](super::Epd5in79Impl::as_binary_dithered).
+    pub fn as_binary_dithered(
+        &mut self,
+        kernel: ErrorDiffusionKernel,
+    ) -> Epd5in79Async<'_, BinaryColor, SPI, BUSY, OUT, DELAY> {
+        self.driver.dither_to_binary(kernel);
+        Epd5in79Async {
+            inner: self,
+            color: PhantomData,
+        }
+    }
+
+    /// See [`Epd5in79Impl::as_gray2_smoothed`](super::Epd5in79Impl::as_gray2_smoothed).
+    pub fn as_gray2_smoothed(&mut self) -> Epd5in79Async<'_, Gray2, SPI, BUSY, OUT, DELAY> {
+        self.driver.smooth_to_gray2();
+        Epd5in79Async {
+            inner: self,
+            color: PhantomData,
+        }
+    }
+
+    /// See [`Epd5in79Impl::deep_sleep`](super::Epd5in79Impl::deep_sleep).
+    ///
+    /// The state file is written with blocking file IO once the panel sleeps,
+    /// leave it unset and call [`save_state`](Self::save_state) elsewhere to avoid it.
+    pub async fn deep_sleep(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        let mode = self.driver.config.deep_sleep_mode;
+        self.driver.enter_deep_sleep(mode).await?;
+        self.driver.save_state_file()
+    }
+
+    /// See [`Epd5in79Impl::clear_screen`](super::Epd5in79Impl::clear_screen).
+    pub async fn clear_screen(
+        &mut self,
+        color: BinaryColor,
+        mode: RefreshMode,
+    ) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.driver.clear_screen(color, mode).await
+    }
+
+    /// See [`Epd5in79Impl::read_temperature`](super::Epd5in79Impl::read_temperature).
+    pub async fn read_temperature(
+        &mut self,
+    ) -> Result<Temperature, SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.driver.read_temperature().await
+    }
+
+    /// See [`Epd5in79Impl::set_temperature`](super::Epd5in79Impl::set_temperature).
+    pub fn set_temperature(
+        &mut self,
+        celsius: Option<f32>,
+    ) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.driver.set_temperature(celsius)
+    }
+
+    /// See [`Epd5in79Impl::temperature`](super::Epd5in79Impl::temperature).
+    pub fn temperature(&self) -> Option<Temperature> {
+        self.driver.state.temperature
+    }
+
+    /// See [`Epd5in79Impl::waveform_bands`](super::Epd5in79Impl::waveform_bands).
+    pub fn waveform_bands(&self, mode: DisplayMode) -> &WaveformBands {
+        &self.driver.waveforms[mode.index()]
+    }
+
+    /// See [`Epd5in79Impl::set_waveform_bands`](super::Epd5in79Impl::set_waveform_bands).
+    pub fn set_waveform_bands(&mut self, mode: DisplayMode, bands: WaveformBands) {
+        self.driver.set_waveform_bands(mode, bands);
+    }
+
+    /// See [`Epd5in79Impl::add_waveform_band`](super::Epd5in79Impl::add_waveform_band).
+    pub fn add_waveform_band(&mut self, mode: DisplayMode, below: f32, waveform: WaveformSource) {
+        self.driver.add_waveform_band(mode, below, waveform);
+    }
+
+    /// See [`Epd5in79Impl::load_custom_waveform`](super::Epd5in79Impl::load_custom_waveform).
+    pub fn load_custom_waveform(
+        &mut self,
+        mode: DisplayMode,
+        waveform: Waveform,
+    ) -> Result<(), ConfigError> {
+        self.driver.load_custom_waveform(mode, waveform)
+    }
+
+    /// See [`Epd5in79Impl::clear_for_storage`](super::Epd5in79Impl::clear_for_storage).
+    ///
+    /// The state file is written with blocking file IO, like [`deep_sleep`](Self::deep_sleep).
+    pub async fn clear_for_storage(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.driver.clear_for_storage().await?;
+        self.driver.save_state_file()
+    }
+
+    /// See [`Epd5in79Impl::set_state_file`](super::Epd5in79Impl::set_state_file).
+    pub fn set_state_file(&mut self, path: Option<PathBuf>) {
+        self.driver.state_file = path;
+    }
+
+    /// See [`Epd5in79Impl::export_bytes`](super::Epd5in79Impl::export_bytes).
+    pub fn export_bytes(&self, format: ExportFormat) -> io::Result<Vec<u8>> {
+        self.driver.export_bytes(format)
+    }
+
+    pub fn export(&self, path: impl AsRef<Path>, format: ExportFormat) -> io::Result<()> {
+        std::fs::write(path, self.export_bytes(format)?)
+    }
+
+    pub fn save_state(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.driver.save_state(path.as_ref())
+    }
+
+    /// See [`Epd5in79Impl::restore_state`](super::Epd5in79Impl::restore_state).
+    pub fn restore_state(&mut self, path: impl AsRef<Path>) -> io::Result<bool> {
+        self.driver.restore_state(path.as_ref())
+    }
+
+    pub fn power_on_dur(&self) -> Option<Duration> {
+        self.driver.state.power_on.map(|i| i.elapsed())
+    }
+
+    /// See [`Epd5in79Impl::dirty_area`](super::Epd5in79Impl::dirty_area).
+    pub fn dirty_area(&self) -> Option<Rectangle> {
+        self.driver.dirty_area()
+    }
+
+    pub fn reset_dirty(&mut self) {
+        self.driver.frame.dirty = None;
+    }
+
+    pub fn refresh_policy(&self) -> RefreshPolicy {
+        self.driver.config.refresh_policy
+    }
+
+    pub fn set_refresh_policy(&mut self, policy: RefreshPolicy) {
+        self.driver.config.refresh_policy = policy;
+    }
+
+    pub fn refresh_stats(&self) -> RefreshStats {
+        self.driver.state.stats
+    }
+
+    pub fn deep_sleep_mode(&self) -> DeepSleepMode {
+        self.driver.config.deep_sleep_mode
+    }
+
+    /// See [`Epd5in79Impl::set_deep_sleep_mode`](super::Epd5in79Impl::set_deep_sleep_mode).
+    pub fn set_deep_sleep_mode(&mut self, mode: DeepSleepMode) {
+        self.driver.config.deep_sleep_mode = mode;
+    }
+
+    /// See [`Epd5in79Impl::sleeping_in`](super::Epd5in79Impl::sleeping_in).
+    pub fn sleeping_in(&self) -> Option<DeepSleepMode> {
+        self.driver.state.sleeping_in()
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.driver.config.orientation
+    }
+
+    /// See [`Epd5in79Impl::set_orientation`](super::Epd5in79Impl::set_orientation).
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.driver.reorient(orientation);
+    }
+
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.set_orientation(Orientation {
+            rotation,
+            ..self.driver.config.orientation
+        });
+    }
+
+    pub fn set_mirror(&mut self, x: bool, y: bool) {
+        self.set_orientation(Orientation {
+            mirror_x: x,
+            mirror_y: y,
+            ..self.driver.config.orientation
+        });
+    }
+}
+
+pub struct Epd5in79Async<'a, C, SPI, BUSY, OUT, DELAY> {
+    inner: &'a mut Epd5in79AsyncImpl<SPI, BUSY, OUT, DELAY>,
+    color: PhantomData<C>,
+}
+
+impl<'a, C, SPI, BUSY, OUT, DELAY> Debug for Epd5in79Async<'a, C, SPI, BUSY, OUT, DELAY> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Epd5in79Async")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, SPI, BUSY, OUT, DELAY> GetPixel for Epd5in79Async<'a, BinaryColor, SPI, BUSY, OUT, DELAY> {
+    type Color = BinaryColor;
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        self.inner.driver.pixel_binary(p)
+    }
+}
+
+impl<'a, SPI, BUSY, OUT, DELAY> GetPixel for Epd5in79Async<'a, Gray2, SPI, BUSY, OUT, DELAY> {
+    type Color = Gray2;
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        self.inner.driver.pixel_gray(p)
+    }
+}
+
+impl<'a, C, SPI, BUSY, OUT, DELAY> std::ops::Deref for Epd5in79Async<'a, C, SPI, BUSY, OUT, DELAY> {
+    type Target = Epd5in79AsyncImpl<SPI, BUSY, OUT, DELAY>;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner
+    }
+}
+
+impl<'a, C, SPI, BUSY, OUT, DELAY> std::ops::DerefMut
+    for Epd5in79Async<'a, C, SPI, BUSY, OUT, DELAY>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner
+    }
+}
+
+impl<'a, C, SPI, BUSY, OUT, DELAY> Epd5in79Async<'a, C, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: Wait,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    pub async fn wait_busy(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.inner.driver.wait_busy().await
+    }
+}
+
+impl<'a, SPI, BUSY, OUT, DELAY> Epd5in79Async<'a, Gray2, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: Wait,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    pub async fn display_gray2(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.inner.driver.display_gray2().await
+    }
+}
+
+impl<'a, SPI, BUSY, OUT, DELAY> Epd5in79Async<'a, BinaryColor, SPI, BUSY, OUT, DELAY>
+where
+    SPI: SpiDevice,
+    BUSY: Wait,
+    OUT: OutputPin,
+    DELAY: DelayNs,
+{
+    pub async fn display_binary_full(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.inner.driver.display_binary_full().await
+    }
+
+    pub async fn display_binary_fast(&mut self) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.inner.driver.display_binary_fast().await
+    }
+
+    pub async fn display_binary_partial(
+        &mut self,
+    ) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.inner.driver.display_binary_partial().await
+    }
+
+    /// See [`Epd5in79::display_binary_partial_region`](super::Epd5in79::display_binary_partial_region).
+    pub async fn display_binary_partial_region(
+        &mut self,
+        area: Rectangle,
+    ) -> Result<(), SpiInterfaceError<SPI, BUSY, OUT>> {
+        let area = self.inner.driver.config.rect_to_frame(&area);
+        self.inner.driver.display_binary_partial_native(area).await
+    }
+
+    /// See [`Epd5in79::refresh`](super::Epd5in79::refresh).
+    pub async fn refresh(
+        &mut self,
+    ) -> Result<Option<RefreshMode>, SpiInterfaceError<SPI, BUSY, OUT>> {
+        self.inner.driver.refresh().await
+    }
+}
+
+impl<'a, C, SPI, BUSY, OUT, DELAY> OriginDimensions
+    for Epd5in79Async<'a, C, SPI, BUSY, OUT, DELAY>
+{
+    fn size(&self) -> Size {
+        self.inner.driver.config.size()
+    }
+}
+
+impl<'a, SPI, BUSY, OUT, DELAY> DrawTarget
+    for Epd5in79Async<'a, BinaryColor, SPI, BUSY, OUT, DELAY>
+{
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.inner.driver.draw_binary(pixels);
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.inner.driver.fill_solid(area, color);
+        Ok(())
+    }
+}
+
+impl<'a, SPI, BUSY, OUT, DELAY> DrawTarget for Epd5in79Async<'a, Gray2, SPI, BUSY, OUT, DELAY> {
+    type Color = Gray2;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.inner.driver.draw_gray(pixels);
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.inner.driver.fill_solid(area, color);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use waveshare_epd_core::mock::{MockHal, Pin};
+
+    use super::super::{driver::block_on, Epd5in79Impl};
+    use super::*;
+
+    #[test]
+    fn same_commands_as_blocking() {
+        let area = Rectangle::new(Point::new(388, 0), Size::new(16, 8));
+        let pixels = [Pixel(Point::new(395, 3), BinaryColor::Off)];
+
+        let blocking = MockHal::new();
+        {
+            let hal = &blocking;
+            let mut epd = Epd5in79Impl::new(
+                hal.spi(),
+                hal.pin(Pin::Rst),
+                hal.pin(Pin::Dc),
+                Some(hal.pin(Pin::Cs)),
+                hal.busy(),
+                hal.pin(Pin::Pwr),
+                hal.delay(),
+            );
+            let mut binary = epd.as_binary();
+            binary.display_binary_full().unwrap();
+            binary.draw_iter(pixels).unwrap();
+            binary.display_binary_partial_region(area).unwrap();
+            binary.display_binary_fast().unwrap();
+            epd.as_gray2().display_gray2().unwrap();
+            epd.deep_sleep().unwrap();
+        }
+
+        let asynch = MockHal::new();
+        {
+            let hal = &asynch;
+            let mut epd = Epd5in79AsyncImpl::new(
+                hal.spi(),
+                hal.pin(Pin::Rst),
+                hal.pin(Pin::Dc),
+                Some(hal.pin(Pin::Cs)),
+                hal.busy(),
+                hal.pin(Pin::Pwr),
+                hal.delay(),
+            );
+            // the mock never suspends while the busy pin is idle
+            block_on(async {
+                let mut binary = epd.as_binary();
+                binary.display_binary_full().await?;
+                binary.draw_iter(pixels).unwrap();
+                binary.display_binary_partial_region(area).await?;
+                binary.display_binary_fast().await?;
+                epd.as_gray2().display_gray2().await?;
+                epd.deep_sleep().await
+            })
+            .unwrap();
+        }
+
+        assert_eq!(asynch.commands(), blocking.commands());
+        for pin in [Pin::Rst, Pin::Pwr] {
+            assert_eq!(asynch.pin_levels(pin), blocking.pin_levels(pin));
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! Builder for [`Epd5in79Impl`].
+
+use std::{io, path::PathBuf, time::Duration};
+
+use embedded_hal::{
+    delay::DelayNs,
+    digital::{InputPin, OutputPin},
+    spi::SpiDevice,
+};
+use waveshare_epd_core::{
+    error::{ConfigError, Error},
+    orientation::Orientation,
+    refresh::RefreshPolicy,
+    spi_interface::{DelayStep, SpiInterfaceError},
+};
+
+use super::{Config, DeepSleepMode, Epd5in79Impl};
+
+#[cfg(feature = "linux")]
+use crate::linux::{spidev_bufsiz, Bias, BusyWait, CdevBusyPin};
+#[cfg(feature = "linux")]
+use embedded_hal::spi::{Mode, MODE_0};
+#[cfg(feature = "linux")]
+use linux_embedded_hal::{
+    gpio_cdev::{Chip, LineRequestFlags},
+    spidev::{SpiModeFlags, SpidevOptions},
+    CdevPin, Delay, SpidevDevice,
+};
+#[cfg(feature = "linux")]
+use std::path::Path;
+#[cfg(feature = "linux")]
+use waveshare_epd_core::spi_interface::PinDefinition;
+
+#[cfg(feature = "linux")]
+use super::Epd5in79Linux;
+
+/// The maximum SPI clock of the controller for write operations.
+pub const MAX_SPI_SPEED_HZ: u32 = 20_000_000;
+/// Linux limits the consumer label of a GPIO line to 31 bytes.
+#[cfg(feature = "linux")]
+const MAX_CONSUMER_LEN: usize = 31;
+
+/// Configure and create an [`Epd5in79Impl`].
+///
+/// # Examples
+/// ```no_run
+/// # #[cfg(feature = "linux")] {
+/// # use std::{io, path::PathBuf, time::Duration};
+/// # use waveshare_epd::epd5in79::Epd5in79Builder;
+/// let mut epd_impl = Epd5in79Builder::new()
+///     .spi_speed_hz(1_000_000)
+///     .chunk_size(65536)
+///     .busy_timeout(Duration::from_secs(10))
+///     .open("/dev/spidev0.0", "/dev/gpiochip0")
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Epd5in79Builder {
+    chunk_size: usize,
+    busy_timeout: Duration,
+    busy_poll_step: DelayStep,
+    refresh_policy: RefreshPolicy,
+    deep_sleep_mode: DeepSleepMode,
+    orientation: Orientation,
+    state_file: Option<PathBuf>,
+
+    #[cfg(feature = "linux")]
+    pins: PinDefinition,
+    #[cfg(feature = "linux")]
+    spi_speed_hz: u32,
+    #[cfg(feature = "linux")]
+    spi_mode: Mode,
+    #[cfg(feature = "linux")]
+    three_wire: bool,
+    #[cfg(feature = "linux")]
+    consumer_prefix: String,
+    #[cfg(feature = "linux")]
+    busy_bias: Bias,
+    #[cfg(feature = "linux")]
+    busy_wait: BusyWait,
+}
+
+impl Default for Epd5in79Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Epd5in79Builder {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: Config::DEFAULT.chunk_size,
+            busy_timeout: Config::DEFAULT.busy_timeout,
+            busy_poll_step: Config::DEFAULT.busy_poll_step,
+            refresh_policy: Config::DEFAULT.refresh_policy,
+            deep_sleep_mode: Config::DEFAULT.deep_sleep_mode,
+            orientation: Config::DEFAULT.orientation,
+            state_file: None,
+
+            #[cfg(feature = "linux")]
+            pins: PinDefinition::DEFAULT,
+            #[cfg(feature = "linux")]
+            spi_speed_hz: 4_000_000,
+            #[cfg(feature = "linux")]
+            spi_mode: MODE_0,
+            #[cfg(feature = "linux")]
+            three_wire: false,
+            #[cfg(feature = "linux")]
+            consumer_prefix: "epd5in79".to_owned(),
+            #[cfg(feature = "linux")]
+            busy_bias: Bias::PullDown,
+            #[cfg(feature = "linux")]
+            busy_wait: BusyWait::default(),
+        }
+    }
+
+    /// Maximum bytes per SPI write, default `4096`.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// How long to wait for the busy pin, default 5 s.
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = timeout;
+        self
+    }
+
+    /// The delay between two reads of the busy pin when polling, default 200 µs.
+    pub fn busy_poll_step(mut self, step: DelayStep) -> Self {
+        self.busy_poll_step = step;
+        self
+    }
+
+    /// The policy used by `refresh()`, default [`RefreshPolicy::DEFAULT`].
+    pub fn refresh_policy(mut self, policy: RefreshPolicy) -> Self {
+        self.refresh_policy = policy;
+        self
+    }
+
+    /// The mode entered by `deep_sleep()`, default [`DeepSleepMode::PowerOff`].
+    pub fn deep_sleep_mode(mut self, mode: DeepSleepMode) -> Self {
+        self.deep_sleep_mode = mode;
+        self
+    }
+
+    /// How the panel is mounted, default [`Orientation::DEFAULT`],
+    /// see [`Epd5in79Impl::set_orientation`].
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// Restore the framebuffer and refresh counters from `path` if it exists,
+    /// and save them there on deep sleep, disabled by default.
+    ///
+    /// This keeps the "old data" of partial refresh correct across process restarts.
+    /// A file that is not a valid state file is ignored, other errors reading it fail the
+    /// build with [`Error::StateFile`].
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.chunk_size == 0 {
+            return Err(ConfigError::new("chunk_size", "must not be zero"));
+        }
+        if self.busy_timeout.is_zero() {
+            return Err(ConfigError::new("busy_timeout", "must not be zero"));
+        }
+        let step = self.busy_poll_step.as_duration();
+        if step.is_zero() {
+            return Err(ConfigError::new("busy_poll_step", "must not be zero"));
+        }
+        if step >= self.busy_timeout {
+            return Err(ConfigError::new(
+                "busy_poll_step",
+                format!("{:?} is not less than busy_timeout", step),
+            ));
+        }
+        if let Some(ratio) = self.refresh_policy.fast_above {
+            if !(0.0..=1.0).contains(&ratio) {
+                return Err(ConfigError::new(
+                    "refresh_policy",
+                    format!("fast_above {} is not in 0.0..=1.0", ratio),
+                ));
+            }
+        }
+
+        #[cfg(feature = "linux")]
+        {
+            if self.spi_speed_hz == 0 || self.spi_speed_hz > MAX_SPI_SPEED_HZ {
+                return Err(ConfigError::new(
+                    "spi_speed_hz",
+                    format!("must be in 1..={}", MAX_SPI_SPEED_HZ),
+                ));
+            }
+            let longest = self.consumer_prefix.len() + "_busy_pin".len();
+            if longest > MAX_CONSUMER_LEN {
+                return Err(ConfigError::new(
+                    "consumer_prefix",
+                    format!("labels must not exceed {} bytes", MAX_CONSUMER_LEN),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn config(&self) -> Config {
+        Config {
+            chunk_size: self.chunk_size,
+            busy_timeout: self.busy_timeout,
+            busy_poll_step: self.busy_poll_step,
+            refresh_policy: self.refresh_policy,
+            deep_sleep_mode: self.deep_sleep_mode,
+            ..Config::DEFAULT
+        }
+        .with_orientation(self.orientation)
+    }
+
+    /// Create the driver on top of any embedded-hal implementation.
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    pub fn build<SPI, BUSY, OUT, DELAY>(
+        &self,
+        spi: SPI,
+        rst_pin: OUT,
+        dc_pin: OUT,
+        cs_pin: Option<OUT>,
+        busy_pin: BUSY,
+        pwr_pin: OUT,
+        delay: DELAY,
+    ) -> Result<Epd5in79Impl<SPI, BUSY, OUT, DELAY>, SpiInterfaceError<SPI, BUSY, OUT>>
+    where
+        SPI: SpiDevice,
+        BUSY: InputPin,
+        OUT: OutputPin,
+        DELAY: DelayNs,
+    {
+        self.validate()?;
+        let mut epd = Epd5in79Impl::new(spi, rst_pin, dc_pin, cs_pin, busy_pin, pwr_pin, delay);
+        epd.driver.config = self.config();
+        if let Some(path) = &self.state_file {
+            check_restored(epd.restore_state(path))?;
+            epd.set_state_file(Some(path.clone()));
+        }
+        Ok(epd)
+    }
+
+    /// Create the async driver on top of any embedded-hal-async implementation.
+    #[cfg(feature = "async")]
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    pub fn build_async<SPI, BUSY, OUT, DELAY>(
+        &self,
+        spi: SPI,
+        rst_pin: OUT,
+        dc_pin: OUT,
+        cs_pin: Option<OUT>,
+        busy_pin: BUSY,
+        pwr_pin: OUT,
+        delay: DELAY,
+    ) -> Result<
+        super::asynch::Epd5in79AsyncImpl<SPI, BUSY, OUT, DELAY>,
+        SpiInterfaceError<SPI, BUSY, OUT>,
+    >
+    where
+        SPI: embedded_hal_async::spi::SpiDevice,
+        BUSY: embedded_hal_async::digital::Wait,
+        OUT: OutputPin,
+        DELAY: embedded_hal_async::delay::DelayNs,
+    {
+        self.validate()?;
+        let mut epd = super::asynch::Epd5in79AsyncImpl::new(
+            spi, rst_pin, dc_pin, cs_pin, busy_pin, pwr_pin, delay,
+        )
+        .with_config(self.config());
+        if let Some(path) = &self.state_file {
+            check_restored(epd.restore_state(path))?;
+            epd.set_state_file(Some(path.clone()));
+        }
+        Ok(epd)
+    }
+}
+
+#[cfg(feature = "linux")]
+impl Epd5in79Builder {
+    /// GPIO line numbers, default [`PinDefinition::DEFAULT`].
+    pub fn pins(mut self, pins: PinDefinition) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    /// SPI clock, default 4 MHz, at most [`MAX_SPI_SPEED_HZ`].
+    pub fn spi_speed_hz(mut self, hz: u32) -> Self {
+        self.spi_speed_hz = hz;
+        self
+    }
+
+    /// SPI mode, default [`MODE_0`].
+    pub fn spi_mode(mut self, mode: Mode) -> Self {
+        self.spi_mode = mode;
+        self
+    }
+
+    /// Share one data line for both directions (`SPI_3WIRE`), default `false`.
+    ///
+    /// Needed by [`Epd5in79Impl::read_temperature`] when the data line of the panel is the only
+    /// data line, as on the Waveshare HAT.
+    pub fn three_wire(mut self, three_wire: bool) -> Self {
+        self.three_wire = three_wire;
+        self
+    }
+
+    /// GPIO consumer labels are `{prefix}_rst_pin`, `{prefix}_busy_pin`..., default `epd5in79`.
+    pub fn consumer_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.consumer_prefix = prefix.into();
+        self
+    }
+
+    /// Bias of the busy line, default [`Bias::PullDown`].
+    pub fn busy_bias(mut self, bias: Bias) -> Self {
+        self.busy_bias = bias;
+        self
+    }
+
+    /// How to wait for the busy pin, default [`BusyWait::Poll`].
+    pub fn busy_wait(mut self, busy_wait: BusyWait) -> Self {
+        self.busy_wait = busy_wait;
+        self
+    }
+
+    fn label(&self, pin: &str) -> String {
+        format!("{}_{}", self.consumer_prefix, pin)
+    }
+
+    /// Open `spidev` and `gpio-cdev` devices and create the driver.
+    pub fn open(
+        &self,
+        spi_path: impl AsRef<Path>,
+        gpio_path: impl AsRef<Path>,
+    ) -> Result<Epd5in79Linux, SpiInterfaceError<SpidevDevice, CdevBusyPin, CdevPin>> {
+        self.validate()?;
+        if let Some(bufsiz) = spidev_bufsiz() {
+            if self.chunk_size > bufsiz {
+                return Err(ConfigError::new(
+                    "chunk_size",
+                    format!("exceeds the spidev buffer size ({} bytes)", bufsiz),
+                )
+                .into());
+            }
+        }
+
+        let mut spi = SpidevDevice::open(spi_path).map_err(Error::open)?;
+        spi.0
+            .configure(
+                &SpidevOptions::new()
+                    .max_speed_hz(self.spi_speed_hz)
+                    .mode(spi_mode_flags(self.spi_mode, self.three_wire))
+                    .build(),
+            )
+            .map_err(Error::open)?;
+        let mut chip = Chip::new(gpio_path).map_err(Error::open)?;
+        let rst_pin = request_pin(
+            &mut chip,
+            self.pins.rst_pin,
+            LineRequestFlags::OUTPUT,
+            &self.label("rst_pin"),
+        )?;
+        let dc_pin = request_pin(
+            &mut chip,
+            self.pins.dc_pin,
+            LineRequestFlags::OUTPUT,
+            &self.label("dc_pin"),
+        )?;
+        let pwr_pin = request_pin(
+            &mut chip,
+            self.pins.pwr_pin,
+            LineRequestFlags::OUTPUT,
+            &self.label("pwr_pin"),
+        )?;
+        let busy_pin = CdevBusyPin::request(
+            &mut chip,
+            self.pins.busy_pin,
+            LineRequestFlags::INPUT | self.busy_bias.flags(),
+            &self.label("busy_pin"),
+            self.busy_wait,
+        )
+        .map_err(Error::open)?;
+        let cs_pin = if let Some(cs_pin_n) = self.pins.cs_pin {
+            Some(request_pin(
+                &mut chip,
+                cs_pin_n,
+                LineRequestFlags::OUTPUT | LineRequestFlags::ACTIVE_LOW,
+                &self.label("cs_pin"),
+            )?)
+        } else {
+            None
+        };
+
+        let mut epd = self.build(spi, rst_pin, dc_pin, cs_pin, busy_pin, pwr_pin, Delay)?;
+        epd.driver.bus.enable_wait_idle();
+        Ok(epd)
+    }
+}
+
+/// A state file that is not valid is ignored, the driver starts cold and overwrites it
+/// on the next deep sleep.
+fn check_restored<SpiE, PinE, BusyE>(
+    restored: io::Result<bool>,
+) -> Result<(), Error<SpiE, PinE, BusyE>> {
+    match restored {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Ok(()),
+        Err(e) => Err(Error::StateFile(e)),
+    }
+}
+
+#[cfg(feature = "linux")]
+fn spi_mode_flags(mode: Mode, three_wire: bool) -> SpiModeFlags {
+    use embedded_hal::spi::{Phase, Polarity};
+
+    let mut flags = SpiModeFlags::empty();
+    if mode.polarity == Polarity::IdleHigh {
+        flags |= SpiModeFlags::SPI_CPOL;
+    }
+    if mode.phase == Phase::CaptureOnSecondTransition {
+        flags |= SpiModeFlags::SPI_CPHA;
+    }
+    if three_wire {
+        flags |= SpiModeFlags::SPI_3WIRE;
+    }
+    flags
+}
+
+#[cfg(feature = "linux")]
+fn request_pin(
+    chip: &mut Chip,
+    line: u32,
+    flags: LineRequestFlags,
+    consumer: &str,
+) -> Result<CdevPin, SpiInterfaceError<SpidevDevice, CdevBusyPin, CdevPin>> {
+    chip.get_line(line)
+        .and_then(|line| line.request(flags, 0, consumer))
+        .and_then(CdevPin::new)
+        .map_err(Error::open)
+}
@@ -0,0 +1,762 @@
+//! The command sequences of **epd5in79**, shared by the blocking and the async driver.
+//!
+//! [`Driver`] holds the framebuffer and the state of the panel and sends the commands
+//! through a [`Bus`], the only part that differs between the drivers.
+//! The sequences are written once as `async fn`: the async driver awaits them,
+//! the blocking driver runs them with [`block_on`], as its bus never suspends.
+
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::pin,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use embedded_graphics_core::{
+    pixelcolor::{BinaryColor, Gray2},
+    prelude::*,
+    primitives::Rectangle,
+};
+use embedded_hal::{
+    delay::DelayNs,
+    digital::{InputPin, OutputPin},
+    spi::{self, SpiDevice},
+};
+use waveshare_epd_core::{
+    bit_plane::PlaneColor,
+    dither::ErrorDiffusionKernel,
+    epd::RefreshMode,
+    error::{ConfigError, Error},
+    export::ExportFormat,
+    orientation::Orientation,
+    spi_interface::{DelayStep, SpiInterface},
+};
+
+use super::{
+    auto_write_pattern, controller, data_entry_mode, default_waveforms, ram_window, state_file,
+    temperature, ColorInBuf, Config, DeepSleepMode, DisplayMode, Epd5in79State, Frame, Temperature,
+    TemperatureSource, Waveform, WaveformBands, WaveformSource, CASCADE, HIGH,
+};
+
+/// The [`Error`] of a [`Bus`].
+pub(super) type BusError<B> =
+    Error<<B as Bus>::SpiError, <B as Bus>::PinError, <B as Bus>::BusyError>;
+
+/// The I/O of the panel, see [`SpiInterface`].
+pub(super) trait Bus {
+    type SpiError;
+    type PinError;
+    type BusyError;
+
+    async fn command(&mut self, cmd: u8) -> Result<(), BusError<Self>>;
+
+    async fn command_data(
+        &mut self,
+        cmd: u8,
+        data: &[u8],
+        chunk_size: usize,
+    ) -> Result<(), BusError<Self>>;
+
+    async fn command_read(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), BusError<Self>>;
+
+    fn set_rst_pin(&mut self, active: bool) -> Result<(), BusError<Self>>;
+
+    fn set_power(&mut self, on: bool) -> Result<(), BusError<Self>>;
+
+    async fn delay(&mut self, delay: DelayStep);
+
+    /// Wait until the busy pin is idle, `poll_step` is only used by buses that poll it.
+    async fn wait_busy(
+        &mut self,
+        poll_step: DelayStep,
+        timeout: Duration,
+    ) -> Result<(), BusError<Self>>;
+}
+
+impl<Spi, I, O, D> Bus for SpiInterface<Spi, I, O, D>
+where
+    Spi: SpiDevice,
+    I: InputPin,
+    O: OutputPin,
+    D: DelayNs,
+{
+    type SpiError = <Spi as spi::ErrorType>::Error;
+    type PinError = O::Error;
+    type BusyError = I::Error;
+
+    async fn command(&mut self, cmd: u8) -> Result<(), BusError<Self>> {
+        SpiInterface::command(self, cmd)
+    }
+
+    async fn command_data(
+        &mut self,
+        cmd: u8,
+        data: &[u8],
+        chunk_size: usize,
+    ) -> Result<(), BusError<Self>> {
+        SpiInterface::command_data(self, cmd, data, chunk_size)
+    }
+
+    async fn command_read(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), BusError<Self>> {
+        SpiInterface::command_read(self, cmd, buf)
+    }
+
+    fn set_rst_pin(&mut self, active: bool) -> Result<(), BusError<Self>> {
+        SpiInterface::set_rst_pin(self, active)
+    }
+
+    fn set_power(&mut self, on: bool) -> Result<(), BusError<Self>> {
+        SpiInterface::set_power(self, on)
+    }
+
+    async fn delay(&mut self, delay: DelayStep) {
+        SpiInterface::delay(self, delay)
+    }
+
+    async fn wait_busy(
+        &mut self,
+        poll_step: DelayStep,
+        timeout: Duration,
+    ) -> Result<(), BusError<Self>> {
+        self.wait_busy_timeout(poll_step, timeout)?;
+        Ok(())
+    }
+}
+
+/// Run a command sequence on a bus that never suspends, like [`SpiInterface`].
+///
+/// A bus that suspends anyway leaves the sequence unfinished with [`Error::Suspended`].
+pub(super) fn block_on<T, SpiE, PinE, BusyE>(
+    future: impl Future<Output = Result<T, Error<SpiE, PinE, BusyE>>>,
+) -> Result<T, Error<SpiE, PinE, BusyE>> {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => Err(Error::Suspended),
+    }
+}
+
+pub(super) struct Driver<B> {
+    pub(super) bus: B,
+    pub(super) frame: Frame,
+    pub(super) state: Epd5in79State,
+    pub(super) config: Config,
+    pub(super) state_file: Option<PathBuf>,
+    /// By [`DisplayMode`].
+    pub(super) waveforms: [WaveformBands; 4],
+}
+
+impl<B> Driver<B> {
+    pub(super) fn new(bus: B) -> Self {
+        Self {
+            bus,
+            frame: Frame::new(),
+            state: Epd5in79State::new(),
+            config: Config::DEFAULT,
+            state_file: None,
+            waveforms: default_waveforms(),
+        }
+    }
+
+    pub(super) fn mapping_to_binary(&mut self, f: impl Fn(Gray2) -> BinaryColor) {
+        if matches!(self.state.color_in_buf, ColorInBuf::Binary) {
+            return;
+        }
+        let shown = self.frame.dirty.is_none();
+        self.frame.mapping_to_binary(f);
+        self.binary_in_buf(shown);
+    }
+
+    pub(super) fn dither_to_binary(&mut self, kernel: ErrorDiffusionKernel) {
+        if matches!(self.state.color_in_buf, ColorInBuf::Binary) {
+            return;
+        }
+        let shown = self.frame.dirty.is_none();
+        self.frame.dither_to_binary(kernel);
+        self.binary_in_buf(shown);
+    }
+
+    /// The framebuffer was converted to binary, `shown` if the grays were on the panel.
+    fn binary_in_buf(&mut self, shown: bool) {
+        self.state.color_in_buf = ColorInBuf::Binary;
+        self.state.old_in_frame = shown;
+        if shown {
+            // the best guess of the grays on the panel
+            self.frame.copy_new_to_old();
+        }
+    }
+
+    pub(super) fn mapping_to_gray2(&mut self, f: impl Fn(BinaryColor) -> Gray2) {
+        if matches!(self.state.color_in_buf, ColorInBuf::Gray) {
+            return;
+        }
+        self.frame.mapping_to_gray2(f);
+        self.state.color_in_buf = ColorInBuf::Gray;
+        self.state.old_in_frame = false;
+    }
+
+    pub(super) fn smooth_to_gray2(&mut self) {
+        if matches!(self.state.color_in_buf, ColorInBuf::Gray) {
+            return;
+        }
+        self.frame.smooth_to_gray2();
+        self.state.color_in_buf = ColorInBuf::Gray;
+        self.state.old_in_frame = false;
+    }
+
+    /// Change the orientation, keeping the image of the panel in the framebuffer.
+    pub(super) fn reorient(&mut self, orientation: Orientation) {
+        let ram_mirror = self.config.ram_mirror;
+        self.config = self.config.with_orientation(orientation);
+        if self.config.ram_mirror != ram_mirror {
+            // the buffer is written in the new direction of the address counters
+            self.frame.mirror(
+                ram_mirror.mirror_x != self.config.ram_mirror.mirror_x,
+                ram_mirror.mirror_y != self.config.ram_mirror.mirror_y,
+            );
+            // the data entry mode is set by the init
+            self.state.init_for = None;
+        }
+    }
+
+    pub(super) fn pixel_binary(&self, p: Point) -> Option<BinaryColor> {
+        debug_assert!(matches!(self.state.color_in_buf, ColorInBuf::Binary));
+        self.frame.get_binary(self.config.to_frame(p))
+    }
+
+    pub(super) fn pixel_gray(&self, p: Point) -> Option<Gray2> {
+        debug_assert!(matches!(self.state.color_in_buf, ColorInBuf::Gray));
+        self.frame.get_gray(self.config.to_frame(p))
+    }
+
+    pub(super) fn draw_binary(&mut self, pixels: impl IntoIterator<Item = Pixel<BinaryColor>>) {
+        debug_assert!(matches!(self.state.color_in_buf, ColorInBuf::Binary));
+        let config = self.config;
+        for Pixel(point, color) in pixels {
+            self.frame.set_binary(Pixel(config.to_frame(point), color));
+        }
+    }
+
+    pub(super) fn draw_gray(&mut self, pixels: impl IntoIterator<Item = Pixel<Gray2>>) {
+        debug_assert!(matches!(self.state.color_in_buf, ColorInBuf::Gray));
+        let config = self.config;
+        for Pixel(point, color) in pixels {
+            self.frame.set_gray(Pixel(config.to_frame(point), color));
+        }
+    }
+
+    pub(super) fn fill_solid<C: PlaneColor>(&mut self, area: &Rectangle, color: C) {
+        let area = self.config.rect_to_frame(area);
+        self.frame.fill(&area, color.to_bits(), C::PLANES);
+    }
+
+    /// The waveform of `mode` at the last temperature, and its band.
+    fn waveform(&self, mode: DisplayMode) -> (usize, &WaveformSource) {
+        self.waveforms[mode.index()].select_indexed(self.state.temperature.map(|t| t.celsius))
+    }
+
+    /// Whether `mode` is initialized with the waveform of the temperature.
+    fn is_ready_for(&self, mode: DisplayMode) -> bool {
+        self.state.is_ready_for(mode, self.waveform(mode).0)
+    }
+
+    /// The whole framebuffer was shown by a binary refresh in `mode`.
+    fn shown_all(&mut self, mode: RefreshMode) {
+        self.frame.copy_new_to_old();
+        self.state.shown_all(mode);
+    }
+
+    pub(super) fn set_waveform_bands(&mut self, mode: DisplayMode, bands: WaveformBands) {
+        self.waveforms[mode.index()] = bands;
+        self.state.init_for = None;
+    }
+
+    pub(super) fn add_waveform_band(
+        &mut self,
+        mode: DisplayMode,
+        below: f32,
+        waveform: WaveformSource,
+    ) {
+        self.waveforms[mode.index()].insert(below, waveform);
+        self.state.init_for = None;
+    }
+
+    pub(super) fn load_custom_waveform(
+        &mut self,
+        mode: DisplayMode,
+        waveform: Waveform,
+    ) -> Result<(), ConfigError> {
+        waveform.validate()?;
+        self.set_waveform_bands(mode, WaveformBands::new(WaveformSource::Lut(waveform)));
+        Ok(())
+    }
+
+    pub(super) fn export_bytes(&self, format: ExportFormat) -> io::Result<Vec<u8>> {
+        self.frame.encode(
+            self.state.color_in_buf,
+            self.config.frame_orientation,
+            format,
+        )
+    }
+
+    pub(super) fn save_state(&self, path: &Path) -> io::Result<()> {
+        state_file::save(path, &self.frame, &self.state, self.config.ram_mirror)
+    }
+
+    /// Save the state file if one is set, after [`enter_deep_sleep`](Self::enter_deep_sleep).
+    ///
+    /// This is blocking file IO, kept out of the command sequences.
+    pub(super) fn save_state_file<SpiE, PinE, BusyE>(
+        &self,
+    ) -> Result<(), Error<SpiE, PinE, BusyE>> {
+        match &self.state_file {
+            Some(path) => self.save_state(path).map_err(Error::StateFile),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) fn restore_state(&mut self, path: &Path) -> io::Result<bool> {
+        state_file::load(
+            path,
+            &mut self.frame,
+            &mut self.state,
+            self.config.ram_mirror,
+        )
+    }
+
+    pub(super) fn dirty_area(&self) -> Option<Rectangle> {
+        self.frame
+            .dirty
+            .map(|dirty| self.config.rect_from_frame(&dirty))
+    }
+}
+
+impl<B: Bus> Driver<B> {
+    /// Send the plane of the framebuffer written by `plane` to the RAM `cmd`.
+    async fn send_plane(&mut self, cmd: u8, plane: u8) -> Result<(), BusError<B>> {
+        let data = self.frame.ram_plane(plane, self.config.ram_mirror);
+        self.bus
+            .command_data(cmd, &data, self.config.chunk_size)
+            .await
+    }
+
+    async fn send_bufs(&mut self, cmds: impl IntoIterator<Item = u8>) -> Result<(), BusError<B>> {
+        for cmd in cmds {
+            self.send_plane(cmd, cmd).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_bufs_all(&mut self) -> Result<(), BusError<B>> {
+        self.send_bufs([0x24, 0x26, 0xa4, 0xa6]).await
+    }
+
+    async fn command_data(&mut self, cmd: u8, data: impl AsRef<[u8]>) -> Result<(), BusError<B>> {
+        self.bus
+            .command_data(cmd, data.as_ref(), self.config.chunk_size)
+            .await
+    }
+
+    pub(super) async fn enter_deep_sleep(
+        &mut self,
+        mode: DeepSleepMode,
+    ) -> Result<(), BusError<B>> {
+        if !self.state.is_deepsleep() {
+            self.command_data(0x10, [mode.command_data()]).await?;
+            self.state.power_on = None;
+            self.state.sleep_mode = mode;
+            if mode == DeepSleepMode::PowerOff {
+                self.bus.set_power(false)?;
+                self.bus.set_rst_pin(false)?;
+                self.state.old_in_ram = false;
+                self.state.new_in_ram = false;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_address(&mut self) -> Result<(), BusError<B>> {
+        let full = Rectangle::new(Point::zero(), Size::new(400, HIGH));
+        for region in 0..CASCADE.regions().len() {
+            let (cmd, data) = data_entry_mode(region, self.config.ram_mirror);
+            self.command_data(cmd, data).await?;
+            self.set_ram_window(region, &full).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_ram_window(&mut self, region: usize, area: &Rectangle) -> Result<(), BusError<B>> {
+        for (cmd, data) in ram_window(region, area, self.config.ram_mirror) {
+            self.command_data(cmd, data).await?;
+        }
+        Ok(())
+    }
+
+    async fn hw_reset(&mut self) -> Result<(), BusError<B>> {
+        self.bus.set_rst_pin(true)?;
+        self.bus.delay(DelayStep::Us(200)).await;
+        self.bus.set_rst_pin(false)?;
+        self.bus.delay(DelayStep::Us(200)).await;
+        self.bus.set_rst_pin(true)?;
+        self.bus.delay(DelayStep::Us(200)).await;
+        self.wait_busy_without_check().await?;
+        self.state.power_on = Some(Instant::now());
+        Ok(())
+    }
+
+    async fn sw_reset(&mut self) -> Result<(), BusError<B>> {
+        self.bus.command(0x12).await?;
+        self.wait_busy_without_check().await?;
+        Ok(())
+    }
+
+    /// Leave deep sleep mode without touching the framebuffer.
+    pub(super) async fn power_on(&mut self) -> Result<(), BusError<B>> {
+        if self.state.is_deepsleep() {
+            self.bus.set_power(true)?;
+            self.hw_reset().await?;
+        }
+        self.sw_reset().await?;
+        self.state.init_for = None;
+        Ok(())
+    }
+
+    pub(super) fn set_temperature(&mut self, celsius: Option<f32>) -> Result<(), BusError<B>> {
+        self.state.temperature = celsius
+            .map(|celsius| temperature::checked(celsius, TemperatureSource::External))
+            .transpose()?;
+        Ok(())
+    }
+
+    pub(super) async fn read_temperature(&mut self) -> Result<Temperature, BusError<B>> {
+        if self.state.is_deepsleep() {
+            self.power_on().await?;
+        }
+        // select the internal sensor and load its value into the temperature register
+        self.command_data(0x18, [0x80]).await?;
+        self.activate(0xa1).await?;
+
+        let mut data = [0; 2];
+        self.bus.command_read(0x1b, &mut data).await?;
+        let temperature =
+            temperature::checked(temperature::from_register(data), TemperatureSource::Sensor)?;
+        self.state.temperature = Some(temperature);
+        Ok(temperature)
+    }
+
+    /// Load the waveform of `mode` for the temperature, part of the init of `mode`.
+    async fn load_waveform(&mut self, mode: DisplayMode) -> Result<(), BusError<B>> {
+        let (band, waveform) = self.waveform(mode);
+        match waveform.clone() {
+            // loaded by every display update, for the temperature of the internal sensor
+            WaveformSource::Otp => self.command_data(0x18, [0x80]).await?,
+            WaveformSource::OtpAt(celsius) => {
+                let register = temperature::otp_at(celsius)?;
+                self.command_data(0x18, [0x80]).await?;
+                self.activate(0xb1).await?;
+                self.command_data(0x1a, register).await?;
+                // load the waveform, but not the temperature
+                self.activate(0x91 | mode.display_mode_2()).await?;
+            }
+            WaveformSource::Lut(waveform) => {
+                for (cmd, data) in waveform.commands() {
+                    self.command_data(cmd, data).await?;
+                }
+            }
+        }
+        self.state.init_band = band;
+        Ok(())
+    }
+
+    /// Show the RAM with the waveform of `mode`.
+    async fn turn_on_display(&mut self, mode: DisplayMode) -> Result<(), BusError<B>> {
+        let otp = matches!(self.waveform(mode).1, WaveformSource::Otp);
+        let control = match self.state.temperature {
+            Some(temperature) if otp && temperature.source == TemperatureSource::External => {
+                // the waveform of the supplied temperature instead of the sensor's
+                self.command_data(0x1a, temperature::to_register(temperature.celsius))
+                    .await?;
+                0xd7
+            }
+            // the sensor is read again by every update
+            _ if otp => 0xf7,
+            // loaded by the init
+            _ => 0xc7,
+        };
+        self.activate(control | mode.display_mode_2()).await
+    }
+
+    /// Run the display update control (0x22) `control` and wait for it.
+    async fn activate(&mut self, control: u8) -> Result<(), BusError<B>> {
+        self.command_data(0x22, [control]).await?;
+        self.bus.command(0x20).await?;
+        self.bus.delay(DelayStep::Us(200)).await;
+        self.wait_busy().await
+    }
+
+    async fn wait_busy_without_check(&mut self) -> Result<(), BusError<B>> {
+        self.bus
+            .wait_busy(self.config.busy_poll_step, self.config.busy_timeout)
+            .await
+    }
+
+    pub(super) async fn wait_busy(&mut self) -> Result<(), BusError<B>> {
+        self.state.check_deepsleep()?;
+        self.wait_busy_without_check().await
+    }
+
+    async fn init_gray2(&mut self) -> Result<(), BusError<B>> {
+        self.power_on().await?;
+        self.command_data(0x0c, [0x8b, 0x9c, 0xa6, 0x0f]).await?;
+        self.command_data(0x3c, [0x81]).await?;
+        self.set_address().await?;
+        self.load_waveform(DisplayMode::Gray2).await?;
+        self.state.init_for = Some(DisplayMode::Gray2);
+        Ok(())
+    }
+
+    async fn ensure_inited_gray2(&mut self) -> Result<(), BusError<B>> {
+        if !self.is_ready_for(DisplayMode::Gray2) {
+            self.init_gray2().await?;
+        }
+        Ok(())
+    }
+
+    pub(super) async fn display_gray2(&mut self) -> Result<(), BusError<B>> {
+        self.ensure_inited_gray2().await?;
+        debug_assert!(matches!(self.state.color_in_buf, ColorInBuf::Gray));
+
+        // send data
+        self.send_bufs_all().await?;
+        self.frame.dirty = None;
+        self.turn_on_display(DisplayMode::Gray2).await?;
+        self.state.stats.record(RefreshMode::Full);
+        // the red RAM holds the high bits of the grays
+        self.state.old_in_frame = false;
+        self.state.old_in_ram = false;
+        self.state.new_in_ram = false;
+        Ok(())
+    }
+
+    async fn init_binary_full(&mut self) -> Result<(), BusError<B>> {
+        self.power_on().await?;
+        self.set_address().await?;
+        self.load_waveform(DisplayMode::Full).await?;
+        self.state.init_for = Some(DisplayMode::Full);
+        Ok(())
+    }
+
+    async fn ensure_inited_binary_full(&mut self) -> Result<(), BusError<B>> {
+        if !self.is_ready_for(DisplayMode::Full) {
+            self.init_binary_full().await?;
+        }
+        debug_assert!(self.is_ready_for(DisplayMode::Full));
+        Ok(())
+    }
+
+    pub(super) async fn display_binary_full(&mut self) -> Result<(), BusError<B>> {
+        self.ensure_inited_binary_full().await?;
+
+        // send data
+        self.frame.clear_old();
+        self.send_bufs_all().await?;
+        self.frame.dirty = None;
+        self.turn_on_display(DisplayMode::Full).await?;
+        self.state.stats.record(RefreshMode::Full);
+        self.shown_all(RefreshMode::Full);
+        Ok(())
+    }
+
+    async fn init_binary_fast(&mut self) -> Result<(), BusError<B>> {
+        self.power_on().await?;
+        self.load_waveform(DisplayMode::Fast).await?;
+        self.set_address().await?;
+
+        self.state.init_for = Some(DisplayMode::Fast);
+        Ok(())
+    }
+
+    async fn ensure_inited_binary_fast(&mut self) -> Result<(), BusError<B>> {
+        if !self.is_ready_for(DisplayMode::Fast) {
+            self.init_binary_fast().await?;
+        }
+        debug_assert!(self.is_ready_for(DisplayMode::Fast));
+        Ok(())
+    }
+
+    pub(super) async fn display_binary_fast(&mut self) -> Result<(), BusError<B>> {
+        self.ensure_inited_binary_fast().await?;
+
+        // send data
+        self.frame.clear_old();
+        self.send_bufs_all().await?;
+        self.frame.dirty = None;
+        self.turn_on_display(DisplayMode::Fast).await?;
+        self.state.stats.record(RefreshMode::Fast);
+        self.shown_all(RefreshMode::Fast);
+        Ok(())
+    }
+
+    async fn init_binary_partial(&mut self) -> Result<(), BusError<B>> {
+        self.power_on().await?;
+        self.command_data(0x3c, [0x80]).await?;
+        self.load_waveform(DisplayMode::Partial).await?;
+        self.set_address().await?;
+        // the RAM is kept by a software reset and deep sleep mode 1
+        let state = self.state;
+        if !state.old_in_frame && (!state.old_in_ram || !state.new_in_ram) {
+            // nothing better is known than the framebuffer
+            self.frame.copy_new_to_old();
+            self.state.old_in_frame = true;
+        }
+        if !state.old_in_ram {
+            self.send_bufs([0x26, 0xa6]).await?;
+            self.state.old_in_ram = true;
+        }
+        self.state.init_for = Some(DisplayMode::Partial);
+        Ok(())
+    }
+
+    async fn ensure_inited_binary_partial(&mut self) -> Result<(), BusError<B>> {
+        if !self.is_ready_for(DisplayMode::Partial) {
+            self.init_binary_partial().await?;
+        }
+        debug_assert!(self.is_ready_for(DisplayMode::Partial));
+        Ok(())
+    }
+
+    pub(super) async fn display_binary_partial(&mut self) -> Result<(), BusError<B>> {
+        self.ensure_inited_binary_partial().await?;
+
+        // send buffer
+        self.send_bufs([0x24, 0xa4]).await?;
+        self.frame.dirty = None;
+        self.turn_on_display(DisplayMode::Partial).await?;
+        self.state.stats.record(RefreshMode::Partial);
+        self.shown_all(RefreshMode::Partial);
+        Ok(())
+    }
+
+    /// `area` is in the coordinates of the framebuffer.
+    pub(super) async fn display_binary_partial_native(
+        &mut self,
+        area: Rectangle,
+    ) -> Result<(), BusError<B>> {
+        if CASCADE.split(&area).next().is_none() {
+            return Ok(());
+        }
+        self.ensure_inited_binary_partial().await?;
+        if !self.state.new_in_ram {
+            // the bw RAM outside of the region keeps the shown image
+            self.set_address().await?;
+            self.send_plane(0x24, 0x26).await?;
+            self.send_plane(0xa4, 0xa6).await?;
+            self.state.new_in_ram = true;
+        }
+
+        // send buffer
+        let ram_mirror = self.config.ram_mirror;
+        for (region, local) in CASCADE.split(&area) {
+            self.set_ram_window(region, &local).await?;
+            let data = self.frame.window(region, 0, &local, ram_mirror);
+            self.command_data(CASCADE.command(controller(region, ram_mirror), 0x24), data)
+                .await?;
+        }
+        self.frame.mark_clean(&area);
+        // the other display functions send whole planes
+        self.set_address().await?;
+        self.turn_on_display(DisplayMode::Partial).await?;
+        self.state.stats.record(RefreshMode::Partial);
+        self.frame.copy_new_to_old_in(&area);
+        Ok(())
+    }
+
+    pub(super) async fn refresh(&mut self) -> Result<Option<RefreshMode>, BusError<B>> {
+        let dirty = self.frame.dirty;
+        let mode = self.state.choose_mode(
+            &self.config.refresh_policy,
+            self.frame.dirty_ratio(),
+            dirty.is_some(),
+        );
+        match mode {
+            RefreshMode::Full => self.display_binary_full().await?,
+            RefreshMode::Fast => self.display_binary_fast().await?,
+            RefreshMode::Partial => match dirty {
+                Some(area) => self.display_binary_partial_native(area).await?,
+                None => return Ok(None),
+            },
+        }
+        Ok(Some(mode))
+    }
+
+    pub(super) async fn clear_screen(
+        &mut self,
+        color: BinaryColor,
+        mode: RefreshMode,
+    ) -> Result<(), BusError<B>> {
+        match mode {
+            RefreshMode::Full => self.ensure_inited_binary_full().await?,
+            RefreshMode::Fast => self.ensure_inited_binary_fast().await?,
+            RefreshMode::Partial => self.ensure_inited_binary_partial().await?,
+        }
+
+        // fill ram, partial refresh keeps the old data
+        self.state.old_in_ram = false;
+        self.state.new_in_ram = false;
+        for region in 0..CASCADE.regions().len() {
+            self.auto_write(CASCADE.command(region, 0x47), color.is_on())
+                .await?;
+            if mode != RefreshMode::Partial {
+                self.auto_write(CASCADE.command(region, 0x46), false)
+                    .await?;
+            }
+        }
+        // the address counters are left anywhere
+        self.set_address().await?;
+        self.turn_on_display(mode.into()).await?;
+
+        // the whole framebuffer is overwritten, no need to map the colors
+        self.state.color_in_buf = ColorInBuf::Binary;
+        let screen = self.frame.buf.bounding_box();
+        self.frame
+            .fill(&screen, color.to_bits(), BinaryColor::PLANES);
+        if mode != RefreshMode::Partial {
+            self.frame.clear_old();
+        }
+        self.frame.dirty = None;
+        self.state.stats.record(mode);
+        self.shown_all(mode);
+        Ok(())
+    }
+
+    async fn auto_write(&mut self, cmd: u8, bit: bool) -> Result<(), BusError<B>> {
+        self.command_data(cmd, [auto_write_pattern(bit)]).await?;
+        self.wait_busy().await
+    }
+
+    pub(super) async fn clear_for_storage(&mut self) -> Result<(), BusError<B>> {
+        self.clear_screen(BinaryColor::On, RefreshMode::Full)
+            .await?;
+        self.enter_deep_sleep(DeepSleepMode::PowerOff).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    #[test]
+    fn block_on_suspended() {
+        let ready = block_on(async { Ok::<_, Error<Infallible, Infallible>>(1) });
+        assert_eq!(ready.unwrap(), 1);
+
+        let suspended = block_on(async {
+            std::future::pending::<()>().await;
+            Ok::<_, Error<Infallible, Infallible>>(())
+        });
+        assert!(matches!(suspended, Err(Error::Suspended)));
+    }
+}
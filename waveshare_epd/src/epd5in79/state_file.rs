@@ -0,0 +1,362 @@
+//! Opt-in state file, so partial refresh keeps working across process restarts.
+//!
+//! The file holds the framebuffer planes, the color in the buffer, the refresh counters,
+//! the deep sleep mode and the time it was saved. If the framebuffer had changes that were
+//! not displayed yet when it was saved, the restored counters force a full refresh on the
+//! next `refresh()`. The planes are stored as the panel shows them, independent of the
+//! orientation.
+//!
+//! The display mode the controllers were initialized for is not stored, a new process
+//! always initializes them again. Nor is the RAM trusted after [`DeepSleepMode::RetainRam`]:
+//! the panel may have lost power in between, so the "old data" is sent again.
+
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use waveshare_epd_core::orientation::Orientation;
+
+use super::{ColorInBuf, DeepSleepMode, Epd5in79State, Frame};
+
+const MAGIC: &[u8; 8] = b"EPD5IN79";
+const VERSION: u8 = 2;
+const PLANES: [u8; 4] = [0x24, 0x26, 0xa4, 0xa6];
+const PLANE_LEN: usize = 13600;
+/// magic, version, color, dirty, partials since full, total, saved at, last full, sleep mode.
+const HEADER_LEN: usize = 8 + 1 + 1 + 1 + 4 + 8 + 8 + 8 + 1;
+
+/// `ram_mirror` is the mirroring done by the controllers, see `Config::ram_mirror`.
+pub(super) fn save(
+    path: &Path,
+    frame: &Frame,
+    state: &Epd5in79State,
+    ram_mirror: Orientation,
+) -> io::Result<()> {
+    let now = SystemTime::now();
+    let last_full = state
+        .stats
+        .last_full
+        .and_then(|last_full| now.checked_sub(last_full.elapsed()))
+        .map_or(0, unix_secs);
+
+    let mut data = Vec::with_capacity(HEADER_LEN + PLANES.len() * PLANE_LEN);
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+    data.push(match state.color_in_buf {
+        ColorInBuf::Binary => 0,
+        ColorInBuf::Gray => 1,
+    });
+    data.push(frame.dirty.is_some() as u8);
+    data.extend_from_slice(&state.stats.partials_since_full.to_le_bytes());
+    data.extend_from_slice(&state.stats.total.to_le_bytes());
+    data.extend_from_slice(&unix_secs(now).to_le_bytes());
+    data.extend_from_slice(&last_full.to_le_bytes());
+    data.push(match state.sleeping_in() {
+        None => 0,
+        Some(DeepSleepMode::RetainRam) => 1,
+        Some(DeepSleepMode::PowerOff) => 2,
+    });
+    let frame = if ram_mirror.is_native() {
+        Cow::Borrowed(frame)
+    } else {
+        let mut native = frame.clone();
+        native.mirror(ram_mirror.mirror_x, ram_mirror.mirror_y);
+        Cow::Owned(native)
+    };
+    for cmd in PLANES {
+        data.extend_from_slice(frame.plane(cmd));
+    }
+
+    // never leave a truncated file behind
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, &data)?;
+    fs::rename(tmp, path)
+}
+
+/// Returns `false` if the file does not exist.
+pub(super) fn load(
+    path: &Path,
+    frame: &mut Frame,
+    state: &mut Epd5in79State,
+    ram_mirror: Orientation,
+) -> io::Result<bool> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if data.len() != HEADER_LEN + PLANES.len() * PLANE_LEN || &data[..8] != MAGIC {
+        return Err(invalid("not an epd5in79 state file"));
+    }
+    if data[8] != VERSION {
+        return Err(invalid(format!("unsupported version {}", data[8])));
+    }
+    let color_in_buf = match data[9] {
+        0 => ColorInBuf::Binary,
+        1 => ColorInBuf::Gray,
+        color => return Err(invalid(format!("invalid color {}", color))),
+    };
+    let dirty = data[10] != 0;
+    let partials_since_full = u32::from_le_bytes(data[11..15].try_into().unwrap());
+    let total = u64::from_le_bytes(data[15..23].try_into().unwrap());
+    // data[23..31] is the time the file was saved, only kept for inspection
+    let last_full = u64::from_le_bytes(data[31..39].try_into().unwrap());
+    let sleep_mode = match data[39] {
+        0 => None,
+        1 => Some(DeepSleepMode::RetainRam),
+        2 => Some(DeepSleepMode::PowerOff),
+        mode => return Err(invalid(format!("invalid sleep mode {}", mode))),
+    };
+
+    for (cmd, plane) in PLANES
+        .into_iter()
+        .zip(data[HEADER_LEN..].chunks_exact(PLANE_LEN))
+    {
+        frame.plane_mut(cmd).copy_from_slice(plane);
+    }
+    frame.mirror(ram_mirror.mirror_x, ram_mirror.mirror_y);
+    frame.dirty = None;
+    state.color_in_buf = color_in_buf;
+    state.stats.partials_since_full = partials_since_full;
+    state.stats.total = total;
+    state.stats.last_full = if dirty || last_full == 0 {
+        None
+    } else {
+        let age = SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_secs(last_full))
+            .unwrap_or_default();
+        Instant::now().checked_sub(age)
+    };
+    // a clean binary framebuffer is what the panel shows, the RAM is lost in any case
+    state.old_in_frame = !dirty && matches!(color_in_buf, ColorInBuf::Binary);
+    if state.old_in_frame {
+        frame.copy_new_to_old();
+    }
+    state.old_in_ram = false;
+    state.new_in_ram = false;
+    if let Some(sleep_mode) = sleep_mode {
+        state.sleep_mode = sleep_mode;
+    }
+    Ok(true)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics_core::{
+        pixelcolor::BinaryColor,
+        prelude::{Pixel, Point},
+    };
+    use waveshare_epd_core::epd::RefreshMode;
+
+    use super::*;
+
+    /// A file in the temp dir, removed with its temp file when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let name = format!("epd5in79-{}-{}", std::process::id(), name);
+            Self(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+            let _ = fs::remove_file(self.0.with_extension("state.tmp"));
+        }
+    }
+
+    /// A frame with black pixels at `points`, as the panel shows it.
+    fn shown_frame(points: &[Point]) -> Frame {
+        let mut frame = Frame::new();
+        for &point in points {
+            frame.set_binary(Pixel(point, BinaryColor::Off));
+        }
+        frame.copy_new_to_old();
+        frame.dirty = None;
+        frame
+    }
+
+    fn planes(frame: &Frame) -> Vec<Vec<u8>> {
+        PLANES
+            .iter()
+            .map(|&cmd| frame.plane(cmd).to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let file = TempFile::new("round_trip.state");
+        let frame = shown_frame(&[Point::new(0, 0), Point::new(395, 10), Point::new(791, 271)]);
+        let mut state = Epd5in79State::new();
+        state.stats.record(RefreshMode::Full);
+        state.stats.record(RefreshMode::Partial);
+        state.stats.record(RefreshMode::Partial);
+        state.sleep_mode = DeepSleepMode::RetainRam;
+        save(&file.0, &frame, &state, Orientation::DEFAULT).unwrap();
+
+        let mut loaded = Frame::new();
+        let mut restored = Epd5in79State::new();
+        assert!(load(&file.0, &mut loaded, &mut restored, Orientation::DEFAULT).unwrap());
+        assert_eq!(planes(&loaded), planes(&frame));
+        assert!(loaded.dirty.is_none());
+        assert!(matches!(restored.color_in_buf, ColorInBuf::Binary));
+        assert_eq!(restored.stats.partials_since_full, 2);
+        assert_eq!(restored.stats.total, 3);
+        assert!(restored.stats.last_full.is_some());
+        assert_eq!(restored.sleep_mode, DeepSleepMode::RetainRam);
+        assert!(restored.old_in_frame);
+        assert!(!restored.old_in_ram && !restored.new_in_ram);
+    }
+
+    #[test]
+    fn no_temp_file_left() {
+        let file = TempFile::new("no_temp_file_left.state");
+        save(
+            &file.0,
+            &Frame::new(),
+            &Epd5in79State::new(),
+            Orientation::DEFAULT,
+        )
+        .unwrap();
+        assert!(file.0.exists());
+        assert!(!file.0.with_extension("state.tmp").exists());
+    }
+
+    #[test]
+    fn dirty_forces_full() {
+        let file = TempFile::new("dirty_forces_full.state");
+        let mut frame = shown_frame(&[]);
+        frame.set_binary(Pixel(Point::new(10, 10), BinaryColor::Off));
+        let mut state = Epd5in79State::new();
+        state.stats.record(RefreshMode::Full);
+        save(&file.0, &frame, &state, Orientation::DEFAULT).unwrap();
+
+        let mut loaded = Frame::new();
+        let mut restored = Epd5in79State::new();
+        load(&file.0, &mut loaded, &mut restored, Orientation::DEFAULT).unwrap();
+        assert_eq!(
+            loaded.get_binary(Point::new(10, 10)),
+            Some(BinaryColor::Off)
+        );
+        assert_eq!(restored.stats.last_full, None);
+        assert!(!restored.old_in_frame);
+    }
+
+    #[test]
+    fn gray_is_not_old_data() {
+        let file = TempFile::new("gray_is_not_old_data.state");
+        let mut state = Epd5in79State::new();
+        state.color_in_buf = ColorInBuf::Gray;
+        save(&file.0, &shown_frame(&[]), &state, Orientation::DEFAULT).unwrap();
+
+        let mut restored = Epd5in79State::new();
+        load(
+            &file.0,
+            &mut Frame::new(),
+            &mut restored,
+            Orientation::DEFAULT,
+        )
+        .unwrap();
+        assert!(matches!(restored.color_in_buf, ColorInBuf::Gray));
+        assert!(!restored.old_in_frame);
+    }
+
+    #[test]
+    fn stored_as_shown() {
+        let file = TempFile::new("stored_as_shown.state");
+        let mirrored = Orientation {
+            mirror_x: true,
+            mirror_y: true,
+            ..Orientation::DEFAULT
+        };
+        let frame = shown_frame(&[Point::new(0, 0), Point::new(390, 5)]);
+        let state = Epd5in79State::new();
+        save(&file.0, &frame, &state, mirrored).unwrap();
+
+        let mut loaded = Frame::new();
+        load(&file.0, &mut loaded, &mut Epd5in79State::new(), mirrored).unwrap();
+        assert_eq!(planes(&loaded), planes(&frame));
+
+        // the panel shows the pixels mirrored
+        let mut native = Frame::new();
+        load(
+            &file.0,
+            &mut native,
+            &mut Epd5in79State::new(),
+            Orientation::DEFAULT,
+        )
+        .unwrap();
+        assert_eq!(
+            native.get_binary(Point::new(791, 271)),
+            Some(BinaryColor::Off)
+        );
+        assert_eq!(
+            native.get_binary(Point::new(401, 266)),
+            Some(BinaryColor::Off)
+        );
+        assert_eq!(native.get_binary(Point::new(0, 0)), Some(BinaryColor::On));
+    }
+
+    #[test]
+    fn missing_file() {
+        let file = TempFile::new("missing_file.state");
+        let mut frame = Frame::new();
+        let loaded = load(
+            &file.0,
+            &mut frame,
+            &mut Epd5in79State::new(),
+            Orientation::DEFAULT,
+        );
+        assert!(!loaded.unwrap());
+    }
+
+    #[test]
+    fn invalid_files() {
+        let file = TempFile::new("invalid_files.state");
+        save(
+            &file.0,
+            &Frame::new(),
+            &Epd5in79State::new(),
+            Orientation::DEFAULT,
+        )
+        .unwrap();
+        let valid = fs::read(&file.0).unwrap();
+
+        let mut truncated = valid.clone();
+        truncated.pop();
+        let mut magic = valid.clone();
+        magic[0] = b'X';
+        let mut version = valid.clone();
+        version[8] = VERSION + 1;
+        let mut color = valid.clone();
+        color[9] = 2;
+        let mut sleep_mode = valid.clone();
+        sleep_mode[39] = 3;
+        for data in [truncated, magic, version, color, sleep_mode] {
+            fs::write(&file.0, data).unwrap();
+            let err = load(
+                &file.0,
+                &mut Frame::new(),
+                &mut Epd5in79State::new(),
+                Orientation::DEFAULT,
+            )
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
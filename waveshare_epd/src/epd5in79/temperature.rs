@@ -0,0 +1,178 @@
+//! The temperature the waveforms of the panel depend on.
+//!
+//! The controller has a built-in sensor, loaded into the temperature register by the display
+//! update control (0x22) and read with 0x1b. Reading needs an SPI bus that can read the data
+//! line, so the temperature can also be supplied by the caller.
+//!
+//! The temperature selects the waveform of every display mode from its [`WaveformBands`].
+
+use std::ops::RangeInclusive;
+
+use waveshare_epd_core::error::Error;
+
+use super::Waveform;
+
+/// The operating temperature range of the panel in °C.
+///
+/// Below it, the fast and partial waveforms leave the image faint and ghosted.
+pub const RATED_TEMPERATURE: RangeInclusive<f32> = 0.0..=50.0;
+
+/// The readings of the built-in sensor that can be real in °C, others are rejected.
+/// A supplied temperature must be in it, too.
+pub const SENSOR_RANGE: RangeInclusive<f32> = -40.0..=85.0;
+
+/// The temperatures the temperature register (0x1a) holds in °C,
+/// the range of [`WaveformSource::OtpAt`].
+pub const REGISTER_RANGE: RangeInclusive<f32> = -128.0..=127.9375;
+
+/// Where a [`Temperature`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemperatureSource {
+    /// The built-in sensor of the controller, see [`Epd5in79Impl::read_temperature`](super::Epd5in79Impl::read_temperature).
+    Sensor,
+    /// Supplied by [`Epd5in79Impl::set_temperature`](super::Epd5in79Impl::set_temperature).
+    External,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Temperature {
+    pub celsius: f32,
+    pub source: TemperatureSource,
+}
+
+impl Temperature {
+    /// Whether the panel is in [`RATED_TEMPERATURE`].
+    pub fn is_rated(self) -> bool {
+        RATED_TEMPERATURE.contains(&self.celsius)
+    }
+
+    /// Whether the panel is colder than [`RATED_TEMPERATURE`],
+    /// `refresh()` then only uses full refresh.
+    pub fn is_below_rated(self) -> bool {
+        self.celsius < *RATED_TEMPERATURE.start()
+    }
+}
+
+/// A temperature from `source`, an error if it is outside of [`SENSOR_RANGE`] or NaN.
+pub(super) fn checked<SpiE, PinE, BusyE>(
+    celsius: f32,
+    source: TemperatureSource,
+) -> Result<Temperature, Error<SpiE, PinE, BusyE>> {
+    if !SENSOR_RANGE.contains(&celsius) {
+        return Err(Error::ImplausibleTemperature(celsius));
+    }
+    Ok(Temperature { celsius, source })
+}
+
+/// Encode the temperature of [`WaveformSource::OtpAt`] for the temperature register,
+/// an error if it is outside of [`REGISTER_RANGE`] or NaN.
+pub(super) fn otp_at<SpiE, PinE, BusyE>(celsius: f32) -> Result<[u8; 2], Error<SpiE, PinE, BusyE>> {
+    if !REGISTER_RANGE.contains(&celsius) {
+        return Err(Error::ImplausibleTemperature(celsius));
+    }
+    Ok(to_register(celsius))
+}
+
+/// Decode the temperature register, 12-bit two's complement in 1/16 °C, MSB first.
+pub(super) fn from_register(data: [u8; 2]) -> f32 {
+    (i16::from_be_bytes(data) >> 4) as f32 / 16.0
+}
+
+/// Encode `celsius` for the temperature register (0x1a), saturating.
+pub(super) fn to_register(celsius: f32) -> [u8; 2] {
+    let raw = (celsius * 16.0).round().clamp(-2048.0, 2047.0) as i16;
+    (raw << 4).to_be_bytes()
+}
+
+/// Where the waveform of a display update comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaveformSource {
+    /// The OTP waveform the controller picks for the temperature of the panel.
+    ///
+    /// A [`TemperatureSource::External`] temperature is written to the controller,
+    /// otherwise every update loads the sensor.
+    Otp,
+    /// The OTP waveform the controller picks for this temperature in °C,
+    /// whatever the temperature of the panel. The fast refresh uses the short one of 100 °C.
+    ///
+    /// Loading it fails with [`Error::ImplausibleTemperature`] outside of [`REGISTER_RANGE`].
+    OtpAt(f32),
+    /// A waveform written with 0x32, see [`Waveform`].
+    Lut(Waveform),
+}
+
+/// The waveforms of a [`DisplayMode`](super::DisplayMode) by temperature.
+///
+/// # Examples
+/// ```
+/// # use waveshare_epd::epd5in79::{WaveformBands, WaveformSource};
+/// let bands = WaveformBands::new(WaveformSource::OtpAt(100.0))
+///     .with_band(10.0, WaveformSource::Otp)
+///     .with_band(-5.0, WaveformSource::OtpAt(-5.0));
+/// assert_eq!(bands.select(Some(-10.0)), &WaveformSource::OtpAt(-5.0));
+/// assert_eq!(bands.select(Some(0.0)), &WaveformSource::Otp);
+/// assert_eq!(bands.select(Some(25.0)), &WaveformSource::OtpAt(100.0));
+/// // the temperature is unknown
+/// assert_eq!(bands.select(None), &WaveformSource::OtpAt(100.0));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaveformBands {
+    /// `(below, waveform)`, sorted by `below`.
+    bands: Vec<(f32, WaveformSource)>,
+    default: WaveformSource,
+}
+
+impl WaveformBands {
+    /// Use `default` at all temperatures.
+    pub fn new(default: WaveformSource) -> Self {
+        Self {
+            bands: Vec::new(),
+            default,
+        }
+    }
+
+    /// See [`insert`](Self::insert).
+    pub fn with_band(mut self, below: f32, waveform: WaveformSource) -> Self {
+        self.insert(below, waveform);
+        self
+    }
+
+    /// Use `waveform` below `below` °C, down to the next lower band.
+    ///
+    /// Replaces the band with the same bound.
+    pub fn insert(&mut self, below: f32, waveform: WaveformSource) {
+        match self.bands.binary_search_by(|(b, _)| b.total_cmp(&below)) {
+            Ok(i) => self.bands[i].1 = waveform,
+            Err(i) => self.bands.insert(i, (below, waveform)),
+        }
+    }
+
+    /// The bands, `(below, waveform)` sorted by `below`.
+    pub fn bands(&self) -> &[(f32, WaveformSource)] {
+        &self.bands
+    }
+
+    /// The waveform above all bands, or if the temperature is unknown.
+    pub fn default_waveform(&self) -> &WaveformSource {
+        &self.default
+    }
+
+    /// The waveform at `celsius`.
+    pub fn select(&self, celsius: Option<f32>) -> &WaveformSource {
+        self.select_indexed(celsius).1
+    }
+
+    /// The waveform at `celsius`, and its index, `bands().len()` for the default.
+    pub(super) fn select_indexed(&self, celsius: Option<f32>) -> (usize, &WaveformSource) {
+        let band = celsius.and_then(|celsius| {
+            self.bands
+                .iter()
+                .enumerate()
+                .find(|(_, (below, _))| celsius < *below)
+        });
+        match band {
+            Some((i, (_, waveform))) => (i, waveform),
+            None => (self.bands.len(), &self.default),
+        }
+    }
+}
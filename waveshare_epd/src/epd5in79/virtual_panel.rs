@@ -0,0 +1,1132 @@
+//! A virtual epd5in79, to develop and test without hardware.
+//!
+//! [`VirtualEpd5in79`] hands out SPI, GPIO and delay handles that interpret the command stream
+//! like the two SSD1683 controllers of the panel: data entry mode, RAM windows and counters,
+//! writes to the bw (0x24/0xa4) and red (0x26/0xa6) RAM, auto-write patterns (0x46/0x47),
+//! resets, deep sleep, the temperature sensor (0x18/0x1a/0x1b) and display updates (0x22/0x20).
+//! After each display update, [`VirtualEpd5in79::screen`] holds the image the panel would show,
+//! each controller scans its RAM from the address counter in the directions of its data entry mode.
+//!
+//! Time is virtual, delays advance a clock and a display update keeps the busy pin high
+//! for the duration configured in [`BusyTiming`], without sleeping.
+//!
+//! With [`VirtualEpd5in79::set_physics`], every pixel is simulated as a particle driven by the
+//! waveforms, to preview the ghosting of partial refreshes and tune a
+//! [`RefreshPolicy`](waveshare_epd_core::refresh::RefreshPolicy) without wearing out a panel.
+//!
+//! # Examples
+//! ```
+//! # use embedded_graphics::{image::GetPixel, pixelcolor::{BinaryColor, Gray2, GrayColor}, prelude::*, primitives::{PrimitiveStyle, Rectangle}};
+//! # use waveshare_epd::epd5in79::virtual_panel::VirtualEpd5in79;
+//! let panel = VirtualEpd5in79::new();
+//! let mut epd_impl = panel.driver();
+//! let mut epd = epd_impl.as_binary();
+//! epd.clear(BinaryColor::On).unwrap();
+//! Rectangle::new(Point::new(390, 10), Size::new(20, 20))
+//!     .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+//!     .draw(&mut epd)
+//!     .unwrap();
+//! epd.display_binary_full().unwrap();
+//!
+//! let screen = panel.screen();
+//! assert_eq!(screen.pixel(Point::new(400, 20)), Some(Gray2::BLACK));
+//! assert_eq!(screen.pixel(Point::new(400, 40)), Some(Gray2::WHITE));
+//! ```
+
+mod physics;
+
+pub use physics::Physics;
+
+use std::{
+    convert::Infallible,
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use embedded_graphics_core::{
+    geometry::{Point, Size},
+    pixelcolor::{Gray2, GrayColor},
+};
+use embedded_hal::{
+    delay::DelayNs,
+    digital::{ErrorType as DigitalErrorType, InputPin, OutputPin},
+    spi::{ErrorType as SpiErrorType, Operation, SpiDevice},
+};
+use waveshare_epd_core::{
+    bit_plane::BitPlaneBuffer,
+    export::{self, ExportFormat},
+};
+
+use super::{temperature, Epd5in79Impl, CASCADE, HIGH, WIDTH};
+use physics::{keeps_unchanged, Drive, Simulation, Waveforms};
+
+/// [`Epd5in79Impl`] on top of a [`VirtualEpd5in79`].
+pub type Epd5in79Virtual = Epd5in79Impl<VirtualSpi, VirtualBusyPin, VirtualOutputPin, VirtualDelay>;
+
+/// [`Epd5in79AsyncImpl`](super::asynch::Epd5in79AsyncImpl) on top of a [`VirtualEpd5in79`].
+#[cfg(feature = "async")]
+pub type Epd5in79VirtualAsync =
+    super::asynch::Epd5in79AsyncImpl<VirtualSpi, VirtualBusyPin, VirtualOutputPin, VirtualDelay>;
+
+/// RAM of one controller in bytes, 400 sources by 300 gates.
+const RAM_COLUMNS: usize = 50;
+const RAM_ROWS: usize = 300;
+
+/// Commands that only address one controller, `| 0x80` selects the slave.
+/// All other commands are received by both.
+const PER_CONTROLLER: [u8; 9] = [0x11, 0x24, 0x26, 0x44, 0x45, 0x46, 0x47, 0x4e, 0x4f];
+
+/// How long the busy pin stays high.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyTiming {
+    /// After a hardware or software reset.
+    pub reset: Duration,
+    /// Display update loading the OTP waveform in display mode 1 (`0x22` = `0xf7`).
+    pub full: Duration,
+    /// Display update in display mode 1 with the loaded waveform (`0x22` = `0xc7`).
+    pub fast: Duration,
+    /// Display update loading the OTP waveform in display mode 2 (`0x22` = `0xff`).
+    pub partial: Duration,
+    /// Display update in display mode 2 with a waveform written by `0x32` (`0x22` = `0xcf`),
+    /// that drives the pixels which do not change too.
+    pub gray2: Duration,
+    /// Activation without display, e.g. loading the temperature or the waveform.
+    pub other: Duration,
+}
+
+impl BusyTiming {
+    /// Roughly the times of the real panel at room temperature.
+    pub const DEFAULT: Self = Self {
+        reset: Duration::from_millis(10),
+        full: Duration::from_millis(3000),
+        fast: Duration::from_millis(1500),
+        partial: Duration::from_millis(300),
+        gray2: Duration::from_millis(3000),
+        other: Duration::from_millis(50),
+    };
+
+    /// Never busy.
+    pub const INSTANT: Self = Self {
+        reset: Duration::ZERO,
+        full: Duration::ZERO,
+        fast: Duration::ZERO,
+        partial: Duration::ZERO,
+        gray2: Duration::ZERO,
+        other: Duration::ZERO,
+    };
+
+    fn duration(&self, update: Update) -> Duration {
+        match update {
+            Update::Full => self.full,
+            Update::Fast => self.fast,
+            Update::Partial => self.partial,
+            Update::Gray2 => self.gray2,
+            Update::Other => self.other,
+        }
+    }
+}
+
+impl Default for BusyTiming {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The kind of an activation (0x20), decoded from the display update control (0x22).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Update {
+    Full,
+    Fast,
+    Partial,
+    Gray2,
+    Other,
+}
+
+/// Registers and RAM of one SSD1683.
+#[derive(Debug, Clone)]
+struct Controller {
+    bw: Vec<u8>,
+    red: Vec<u8>,
+    data_entry: u8,
+    x_window: (u16, u16),
+    y_window: (u16, u16),
+    x: u16,
+    y: u16,
+    /// The address counter set by the last 0x4e and 0x4f, where the scan of the screen starts.
+    origin: (u16, u16),
+}
+
+impl Controller {
+    fn new() -> Self {
+        let mut controller = Self {
+            bw: vec![0; RAM_COLUMNS * RAM_ROWS],
+            red: vec![0; RAM_COLUMNS * RAM_ROWS],
+            data_entry: 0,
+            x_window: (0, 0),
+            y_window: (0, 0),
+            x: 0,
+            y: 0,
+            origin: (0, 0),
+        };
+        controller.reset();
+        controller
+    }
+
+    /// Reset the registers, the RAM is kept.
+    fn reset(&mut self) {
+        self.data_entry = 0x03;
+        self.x_window = (0, RAM_COLUMNS as u16 - 1);
+        self.y_window = (0, RAM_ROWS as u16 - 1);
+        self.x = 0;
+        self.y = 0;
+        self.origin = (0, 0);
+    }
+
+    fn clear_ram(&mut self) {
+        self.bw.fill(0);
+        self.red.fill(0);
+    }
+
+    /// Fill the whole RAM with the regular pattern of `0x46` (red) or `0x47` (bw).
+    ///
+    /// Steps of `8 << n` rows (bits 6..4) and sources (bits 2..0) alternate like a
+    /// checkerboard, bit 7 is the value of the first step.
+    fn auto_write(&mut self, red: bool, pattern: u8) {
+        let first = pattern & 0x80 != 0;
+        let height = 8usize << (pattern >> 4 & 0x07);
+        let width = 8usize << (pattern & 0x07);
+        let ram = if red { &mut self.red } else { &mut self.bw };
+        for (y, row) in ram.chunks_exact_mut(RAM_COLUMNS).enumerate() {
+            for (column, byte) in row.iter_mut().enumerate() {
+                let odd = (y / height + column * 8 / width) % 2 == 1;
+                *byte = if first != odd { 0xff } else { 0x00 };
+            }
+        }
+    }
+
+    /// Write one byte at the address counter and advance it by the data entry mode.
+    fn write(&mut self, red: bool, byte: u8) {
+        let (x, y) = (self.x as usize, self.y as usize);
+        if x < RAM_COLUMNS && y < RAM_ROWS {
+            let ram = if red { &mut self.red } else { &mut self.bw };
+            ram[y * RAM_COLUMNS + x] = byte;
+        }
+
+        let x_inc = self.data_entry & 0x01 != 0;
+        let y_inc = self.data_entry & 0x02 != 0;
+        if self.data_entry & 0x04 == 0 {
+            if step(&mut self.x, self.x_window, x_inc) {
+                step(&mut self.y, self.y_window, y_inc);
+            }
+        } else if step(&mut self.y, self.y_window, y_inc) {
+            step(&mut self.x, self.x_window, x_inc);
+        }
+    }
+
+    /// The bw and red bit of a pixel in controller coordinates.
+    ///
+    /// The screen is scanned from the address counter set by 0x4e/0x4f, in the directions of
+    /// the data entry mode (0x11) and wrapping in the RAM window (0x44/0x45),
+    /// the top left pixel is the MSB of the byte at the counter.
+    fn pixel(&self, local: Point) -> (bool, bool) {
+        let column = scan(
+            self.origin.0,
+            self.x_window,
+            self.data_entry & 0x01 != 0,
+            local.x / 8,
+        );
+        let row = scan(
+            self.origin.1,
+            self.y_window,
+            self.data_entry & 0x02 != 0,
+            local.y,
+        );
+        let (Some(column), Some(row)) = (column, row) else {
+            return (false, false);
+        };
+        if column >= RAM_COLUMNS || row >= RAM_ROWS {
+            return (false, false);
+        }
+        let index = row * RAM_COLUMNS + column;
+        let mask = 0x80 >> (local.x % 8);
+        (self.bw[index] & mask != 0, self.red[index] & mask != 0)
+    }
+}
+
+/// The address `n` steps after `origin`, wrapping in `window` like the address counter.
+///
+/// Without wrapping if `origin` is outside of the window, `None` below address 0.
+fn scan(origin: u16, (start, end): (u16, u16), inc: bool, n: i32) -> Option<usize> {
+    let dir = if inc { 1 } else { -1 };
+    let (origin, start, end) = (origin as i32, start as i32, end as i32);
+    let len = (end - start) * dir + 1;
+    let offset = (origin - start) * dir;
+    let address = if len > 0 && (0..len).contains(&offset) {
+        start + (offset + n) % len * dir
+    } else {
+        origin + n * dir
+    };
+    usize::try_from(address).ok()
+}
+
+/// Move `pos` one step towards the end of `window`, wrapping to its start.
+///
+/// Returns `true` if it wrapped.
+fn step(pos: &mut u16, (start, end): (u16, u16), inc: bool) -> bool {
+    if *pos == end {
+        *pos = start;
+        return true;
+    }
+    *pos = if inc {
+        pos.wrapping_add(1)
+    } else {
+        pos.wrapping_sub(1)
+    };
+    false
+}
+
+/// The controllers a command goes to, and the command without the slave bit.
+fn route(cmd: u8) -> (u8, &'static [usize]) {
+    let base = cmd & !0x80;
+    match (PER_CONTROLLER.contains(&base), cmd & 0x80 != 0) {
+        (true, false) => (base, &[0]),
+        (true, true) => (base, &[1]),
+        (false, _) => (cmd, &[0, 1]),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Line {
+    Rst,
+    Dc,
+    Cs,
+    Pwr,
+}
+
+#[derive(Debug)]
+struct Panel {
+    controllers: [Controller; 2],
+    screen: BitPlaneBuffer<Gray2>,
+    timing: BusyTiming,
+    clock: Duration,
+    busy_until: Duration,
+    powered: bool,
+    rst: bool,
+    dc: bool,
+    deep_sleep: bool,
+    command: Option<u8>,
+    data: Vec<u8>,
+    update_control: u8,
+    /// The waveform written by 0x32, `None` while the OTP waveform is loaded.
+    lut: Option<Vec<u8>>,
+    /// The temperature sensor control (0x18).
+    sensor_select: u8,
+    /// The temperature register, written by 0x1a or loaded from the sensor.
+    temperature: [u8; 2],
+    /// The reading of the built-in sensor in °C.
+    sensor: f32,
+    simulation: Option<Simulation>,
+}
+
+impl Panel {
+    fn new(timing: BusyTiming) -> Self {
+        Self {
+            controllers: [Controller::new(), Controller::new()],
+            screen: BitPlaneBuffer::new(Size::new(WIDTH, HIGH), Gray2::WHITE),
+            timing,
+            clock: Duration::ZERO,
+            busy_until: Duration::ZERO,
+            powered: true,
+            rst: true,
+            dc: false,
+            deep_sleep: false,
+            command: None,
+            data: Vec::new(),
+            update_control: 0xff,
+            lut: None,
+            sensor_select: 0x48,
+            temperature: [0; 2],
+            sensor: 25.0,
+            simulation: None,
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        self.clock < self.busy_until
+    }
+
+    fn busy_for(&mut self, duration: Duration) {
+        self.busy_until = self.clock + duration;
+    }
+
+    fn reset(&mut self) {
+        for controller in &mut self.controllers {
+            controller.reset();
+        }
+        self.command = None;
+        self.update_control = 0xff;
+        self.lut = None;
+        self.sensor_select = 0x48;
+        self.busy_for(self.timing.reset);
+    }
+
+    fn set_pin(&mut self, line: Line, level: bool) {
+        match line {
+            Line::Rst => {
+                // the controllers reset on the rising edge
+                if level && !self.rst && self.powered {
+                    self.deep_sleep = false;
+                    self.reset();
+                }
+                self.rst = level;
+            }
+            Line::Dc => self.dc = level,
+            Line::Cs => {}
+            Line::Pwr => {
+                if !level && self.powered {
+                    // the image stays, the RAM is lost
+                    for controller in &mut self.controllers {
+                        controller.clear_ram();
+                    }
+                    self.deep_sleep = false;
+                    self.busy_until = self.clock;
+                }
+                if level && !self.powered {
+                    self.reset();
+                }
+                self.powered = level;
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if !self.powered || !self.rst || self.deep_sleep {
+            return;
+        }
+        for &byte in data {
+            if self.dc {
+                self.data_byte(byte);
+            } else {
+                self.command_byte(byte);
+            }
+            if self.deep_sleep {
+                return;
+            }
+        }
+    }
+
+    /// Data read after a command, only the temperature register (0x1b) can be read.
+    fn read(&mut self, buf: &mut [u8]) {
+        buf.fill(0);
+        if !self.powered || !self.rst || self.deep_sleep || !self.dc {
+            return;
+        }
+        if self.command == Some(0x1b) {
+            for (byte, value) in buf.iter_mut().zip(self.temperature) {
+                *byte = value;
+            }
+        }
+    }
+
+    fn command_byte(&mut self, cmd: u8) {
+        self.command = Some(cmd);
+        self.data.clear();
+        match cmd {
+            0x12 => self.reset(),
+            0x20 => self.activate(),
+            0x32 => self.lut = Some(Vec::new()),
+            _ => {}
+        }
+    }
+
+    fn data_byte(&mut self, byte: u8) {
+        let Some(cmd) = self.command else {
+            return;
+        };
+        let (base, targets) = route(cmd);
+        match base {
+            0x24 | 0x26 => {
+                for &region in targets {
+                    self.controllers[region].write(base == 0x26, byte);
+                }
+                return;
+            }
+            0x32 => {
+                if let Some(lut) = &mut self.lut {
+                    lut.push(byte);
+                }
+                return;
+            }
+            _ => self.data.push(byte),
+        }
+
+        // every pattern matches once, when all parameters are received
+        match (base, self.data.as_slice()) {
+            (0x10, &[mode]) if mode & 0x03 != 0 => {
+                if mode & 0x03 == 0x03 {
+                    // deep sleep mode 2 does not retain the RAM
+                    for controller in &mut self.controllers {
+                        controller.clear_ram();
+                    }
+                }
+                self.deep_sleep = true;
+            }
+            (0x11, &[mode]) => {
+                for &region in targets {
+                    self.controllers[region].data_entry = mode;
+                }
+            }
+            (0x18, &[select]) => self.sensor_select = select,
+            (0x1a, &[msb, lsb]) => self.temperature = [msb, lsb],
+            (0x22, &[value]) => self.update_control = value,
+            (0x46 | 0x47, &[pattern]) => {
+                for &region in targets {
+                    self.controllers[region].auto_write(base == 0x46, pattern);
+                }
+                self.busy_for(self.timing.other);
+            }
+            (0x44, &[start, end]) => {
+                for &region in targets {
+                    self.controllers[region].x_window = (start as u16, end as u16);
+                }
+            }
+            (0x45, &[start_l, start_h, end_l, end_h]) => {
+                let start = u16::from_le_bytes([start_l, start_h]);
+                let end = u16::from_le_bytes([end_l, end_h]);
+                for &region in targets {
+                    self.controllers[region].y_window = (start, end);
+                }
+            }
+            (0x4e, &[x]) => {
+                for &region in targets {
+                    let controller = &mut self.controllers[region];
+                    controller.x = x as u16;
+                    controller.origin.0 = x as u16;
+                }
+            }
+            (0x4f, &[y_l, y_h]) => {
+                let y = u16::from_le_bytes([y_l, y_h]);
+                for &region in targets {
+                    let controller = &mut self.controllers[region];
+                    controller.y = y;
+                    controller.origin.1 = y;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn activate(&mut self) {
+        let value = self.update_control;
+        if value & 0x20 != 0 && self.sensor_select == 0x80 {
+            // there is no external sensor, it keeps the register
+            self.temperature = temperature::to_register(self.sensor);
+        }
+        if value & 0x10 != 0 {
+            // load the waveform from OTP
+            self.lut = None;
+        }
+        let update = match (value & 0x04 != 0, value & 0x08 != 0) {
+            (false, _) => Update::Other,
+            // a waveform driving the unchanged pixels too shows the RAM as gray
+            (true, true) if self.lut.as_deref().is_some_and(|lut| !keeps_unchanged(lut)) => {
+                Update::Gray2
+            }
+            (true, true) => Update::Partial,
+            (true, false) if value & 0x10 != 0 => Update::Full,
+            (true, false) => Update::Fast,
+        };
+        if update != Update::Other {
+            self.show(update);
+        }
+        self.busy_for(self.timing.duration(update));
+    }
+
+    fn show(&mut self, update: Update) {
+        let drives = match update {
+            _ if self.simulation.is_none() => None,
+            Update::Full => Some(Drive::otp(Waveforms::Full)),
+            Update::Fast => match &self.lut {
+                Some(lut) => Some(Drive::lut(lut)),
+                None => Some(Drive::otp(Waveforms::Fast)),
+            },
+            Update::Partial => match &self.lut {
+                Some(lut) => Some(Drive::lut(lut)),
+                None => Some(Drive::otp(Waveforms::Partial)),
+            },
+            Update::Gray2 => self.lut.as_deref().map(Drive::lut),
+            Update::Other => None,
+        };
+        for y in 0..HIGH as i32 {
+            for x in 0..WIDTH as i32 {
+                let point = Point::new(x, y);
+                let Some((region, local)) = CASCADE.locate(point) else {
+                    continue;
+                };
+                let (bw, red) = self.controllers[region].pixel(local);
+                let color = match update {
+                    Update::Gray2 => Gray2::new(bw as u8 | (red as u8) << 1),
+                    _ if bw => Gray2::WHITE,
+                    _ => Gray2::BLACK,
+                };
+                if let (Some(simulation), Some(drives)) = (&mut self.simulation, &drives) {
+                    // the LUT is selected by the red and bw bits
+                    let drive = &drives[(red as usize) << 1 | bw as usize];
+                    simulation.drive(point, color, drive, update == Update::Gray2);
+                }
+                // pixels that do not change are not driven
+                if update == Update::Partial && bw == red {
+                    continue;
+                }
+                self.screen.set(point, color);
+            }
+        }
+        if let Some(simulation) = &mut self.simulation {
+            simulation.finish_update();
+        }
+        if update == Update::Partial {
+            // the shown frame is the old data of the next partial update
+            for controller in &mut self.controllers {
+                controller.red.copy_from_slice(&controller.bw);
+            }
+        }
+    }
+}
+
+/// A virtual panel, and the factory of the handles driving it.
+#[derive(Debug, Clone)]
+pub struct VirtualEpd5in79 {
+    panel: Arc<Mutex<Panel>>,
+}
+
+impl Default for VirtualEpd5in79 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualEpd5in79 {
+    /// A white panel with [`BusyTiming::DEFAULT`].
+    pub fn new() -> Self {
+        Self::with_timing(BusyTiming::DEFAULT)
+    }
+
+    pub fn with_timing(timing: BusyTiming) -> Self {
+        Self {
+            panel: Arc::new(Mutex::new(Panel::new(timing))),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Panel> {
+        self.panel.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A driver connected to this panel.
+    pub fn driver(&self) -> Epd5in79Virtual {
+        Epd5in79Impl::new(
+            self.spi(),
+            self.rst_pin(),
+            self.dc_pin(),
+            Some(self.cs_pin()),
+            self.busy_pin(),
+            self.pwr_pin(),
+            self.delay(),
+        )
+    }
+
+    /// An async driver connected to this panel.
+    #[cfg(feature = "async")]
+    pub fn driver_async(&self) -> Epd5in79VirtualAsync {
+        super::asynch::Epd5in79AsyncImpl::new(
+            self.spi(),
+            self.rst_pin(),
+            self.dc_pin(),
+            Some(self.cs_pin()),
+            self.busy_pin(),
+            self.pwr_pin(),
+            self.delay(),
+        )
+    }
+
+    pub fn spi(&self) -> VirtualSpi {
+        VirtualSpi {
+            panel: self.clone(),
+        }
+    }
+
+    pub fn rst_pin(&self) -> VirtualOutputPin {
+        self.pin(Line::Rst)
+    }
+
+    pub fn dc_pin(&self) -> VirtualOutputPin {
+        self.pin(Line::Dc)
+    }
+
+    pub fn cs_pin(&self) -> VirtualOutputPin {
+        self.pin(Line::Cs)
+    }
+
+    pub fn pwr_pin(&self) -> VirtualOutputPin {
+        self.pin(Line::Pwr)
+    }
+
+    fn pin(&self, line: Line) -> VirtualOutputPin {
+        VirtualOutputPin {
+            panel: self.clone(),
+            line,
+        }
+    }
+
+    pub fn busy_pin(&self) -> VirtualBusyPin {
+        VirtualBusyPin {
+            panel: self.clone(),
+        }
+    }
+
+    pub fn delay(&self) -> VirtualDelay {
+        VirtualDelay {
+            panel: self.clone(),
+        }
+    }
+
+    pub fn set_timing(&self, timing: BusyTiming) {
+        self.lock().timing = timing;
+    }
+
+    /// The image the panel shows.
+    pub fn screen(&self) -> BitPlaneBuffer<Gray2> {
+        self.lock().screen.clone()
+    }
+
+    /// The RAM written by `cmd` (0x24, 0x26, 0xa4 or 0xa6), row-major in RAM addresses,
+    /// 50 bytes by 300 rows.
+    pub fn ram(&self, cmd: u8) -> Vec<u8> {
+        let panel = self.lock();
+        let controller = &panel.controllers[(cmd & 0x80 != 0) as usize];
+        match cmd & !0x80 {
+            0x24 => controller.bw.clone(),
+            0x26 => controller.red.clone(),
+            _ => panic!("not a RAM write command: {:#04x}", cmd),
+        }
+    }
+
+    /// The time passed on the virtual clock.
+    pub fn elapsed(&self) -> Duration {
+        self.lock().clock
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.lock().is_busy()
+    }
+
+    pub fn is_deep_sleep(&self) -> bool {
+        self.lock().deep_sleep
+    }
+
+    /// The temperature register, which selects the OTP waveforms.
+    pub fn temperature_register(&self) -> f32 {
+        temperature::from_register(self.lock().temperature)
+    }
+
+    /// Set the reading of the built-in temperature sensor, 25 °C by default.
+    pub fn set_sensor_temperature(&self, celsius: f32) {
+        self.lock().sensor = celsius;
+    }
+
+    /// Simulate the particles of every pixel, starting settled on the current screen,
+    /// `None` stops the simulation.
+    pub fn set_physics(&self, physics: Option<Physics>) {
+        let mut panel = self.lock();
+        panel.simulation = physics.map(|physics| Simulation::new(physics, &panel.screen));
+    }
+
+    /// The simulated 8-bit luma of every pixel, row-major, `None` without physics.
+    ///
+    /// Encode it with [`export::encode_luma`].
+    pub fn render(&self) -> Option<Vec<u8>> {
+        self.lock().simulation.as_ref().map(Simulation::render)
+    }
+
+    /// Mean difference between the simulated luma and [`Self::screen`], in `0.0..=1.0`,
+    /// `None` without physics.
+    pub fn ghosting(&self) -> Option<f32> {
+        let panel = self.lock();
+        let simulation = panel.simulation.as_ref()?;
+        Some(simulation.ghosting(&panel.screen))
+    }
+
+    /// Keep a [`render`](Self::render) of every display update from now on,
+    /// or stop and drop the recorded frames. Does nothing without physics.
+    pub fn record_timelapse(&self, recording: bool) {
+        if let Some(simulation) = &mut self.lock().simulation {
+            simulation.set_recording(recording);
+        }
+    }
+
+    /// The recorded frames, see [`Self::record_timelapse`].
+    pub fn timelapse(&self) -> Vec<Vec<u8>> {
+        self.lock()
+            .simulation
+            .as_ref()
+            .map_or_else(Vec::new, |simulation| simulation.timelapse().to_vec())
+    }
+
+    /// Write the recorded frames to `frame-0000.<extension>`, `frame-0001.<extension>`, ...
+    /// in `dir`. Returns the number of frames.
+    pub fn write_timelapse(
+        &self,
+        dir: impl AsRef<Path>,
+        format: ExportFormat,
+    ) -> io::Result<usize> {
+        let frames = self.timelapse();
+        for (i, frame) in frames.iter().enumerate() {
+            let path = dir
+                .as_ref()
+                .join(format!("frame-{:04}.{}", i, format.extension()));
+            fs::write(
+                path,
+                export::encode_luma(frame, Size::new(WIDTH, HIGH), format)?,
+            )?;
+        }
+        Ok(frames.len())
+    }
+}
+
+/// An [`SpiDevice`] feeding the panel, like a 3-wire bus.
+///
+/// Reads return the temperature register after 0x1b, zeros otherwise.
+#[derive(Debug, Clone)]
+pub struct VirtualSpi {
+    panel: VirtualEpd5in79,
+}
+
+impl SpiErrorType for VirtualSpi {
+    type Error = Infallible;
+}
+
+impl SpiDevice for VirtualSpi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let mut panel = self.panel.lock();
+        for op in operations {
+            match op {
+                Operation::Write(data) => panel.write(data),
+                Operation::Read(buf) => panel.read(buf),
+                Operation::Transfer(read, write) => {
+                    panel.write(write);
+                    read.fill(0);
+                }
+                Operation::TransferInPlace(buf) => {
+                    panel.write(buf);
+                    buf.fill(0);
+                }
+                Operation::DelayNs(ns) => panel.clock += Duration::from_nanos(*ns as u64),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The reset, data/command, chip select or power pin of the panel.
+#[derive(Debug, Clone)]
+pub struct VirtualOutputPin {
+    panel: VirtualEpd5in79,
+    line: Line,
+}
+
+impl DigitalErrorType for VirtualOutputPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for VirtualOutputPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.panel.lock().set_pin(self.line, false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.panel.lock().set_pin(self.line, true);
+        Ok(())
+    }
+}
+
+/// The busy pin of the panel, high level for busy.
+#[derive(Debug, Clone)]
+pub struct VirtualBusyPin {
+    panel: VirtualEpd5in79,
+}
+
+impl DigitalErrorType for VirtualBusyPin {
+    type Error = Infallible;
+}
+
+impl InputPin for VirtualBusyPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.panel.is_busy())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.panel.is_busy())
+    }
+}
+
+/// A [`DelayNs`] advancing the virtual clock, it never sleeps.
+#[derive(Debug, Clone)]
+pub struct VirtualDelay {
+    panel: VirtualEpd5in79,
+}
+
+impl VirtualDelay {
+    fn advance(&mut self, delay: Duration) {
+        self.panel.lock().clock += delay;
+    }
+}
+
+impl DelayNs for VirtualDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.advance(Duration::from_nanos(ns as u64));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.advance(Duration::from_micros(us as u64));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.advance(Duration::from_millis(ms as u64));
+    }
+}
+
+#[cfg(feature = "async")]
+mod asynch {
+    use embedded_hal_async::{
+        delay::DelayNs,
+        digital::Wait,
+        spi::{Operation, SpiDevice},
+    };
+
+    use super::{VirtualBusyPin, VirtualDelay, VirtualSpi};
+
+    impl SpiDevice for VirtualSpi {
+        async fn transaction(
+            &mut self,
+            operations: &mut [Operation<'_, u8>],
+        ) -> Result<(), Self::Error> {
+            embedded_hal::spi::SpiDevice::transaction(self, operations)
+        }
+    }
+
+    /// Waiting for idle advances the virtual clock to the end of the busy time,
+    /// waiting for busy while idle never completes.
+    impl Wait for VirtualBusyPin {
+        async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+            self.wait_for(true).await;
+            Ok(())
+        }
+
+        async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+            self.wait_for(false).await;
+            Ok(())
+        }
+
+        async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+            self.wait_for(false).await;
+            self.wait_for(true).await;
+            Ok(())
+        }
+
+        async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+            self.wait_for(true).await;
+            self.wait_for(false).await;
+            Ok(())
+        }
+
+        async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+            let busy = self.panel.is_busy();
+            self.wait_for(!busy).await;
+            Ok(())
+        }
+    }
+
+    impl VirtualBusyPin {
+        async fn wait_for(&mut self, busy: bool) {
+            let stuck = {
+                let mut panel = self.panel.lock();
+                if !busy {
+                    panel.clock = panel.clock.max(panel.busy_until);
+                }
+                panel.is_busy() != busy
+            };
+            if stuck {
+                // nothing makes the panel busy while waiting
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    impl DelayNs for VirtualDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            embedded_hal::delay::DelayNs::delay_ns(self, ns);
+        }
+
+        async fn delay_us(&mut self, us: u32) {
+            embedded_hal::delay::DelayNs::delay_us(self, us);
+        }
+
+        async fn delay_ms(&mut self, ms: u32) {
+            embedded_hal::delay::DelayNs::delay_ms(self, ms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::{
+        image::GetPixel, pixelcolor::BinaryColor, prelude::*, primitives::Rectangle,
+    };
+
+    use super::*;
+
+    /// Columns on both sides of the seam, 392..400 is shown by both controllers.
+    const AREA: Rectangle = Rectangle::new(Point::new(376, 0), Size::new(40, 24));
+
+    /// A checkerboard of 3x3 squares.
+    fn checker(point: Point) -> BinaryColor {
+        if (point.x / 3 + point.y / 3) % 2 == 0 {
+            BinaryColor::Off
+        } else {
+            BinaryColor::On
+        }
+    }
+
+    fn gray_ramp(point: Point) -> Gray2 {
+        Gray2::new(((point.x + point.y / 2) % 4) as u8)
+    }
+
+    /// Every pixel of the screen is `expected` in `area` and white elsewhere.
+    fn assert_screen(panel: &VirtualEpd5in79, area: &Rectangle, expected: impl Fn(Point) -> Gray2) {
+        let screen = panel.screen();
+        for point in screen.bounding_box().points() {
+            let color = if area.contains(point) {
+                expected(point)
+            } else {
+                Gray2::WHITE
+            };
+            assert_eq!(screen.pixel(point), Some(color), "{point:?}");
+        }
+    }
+
+    fn send(panel: &VirtualEpd5in79, cmd: u8, data: &[u8]) {
+        let (mut spi, mut dc) = (panel.spi(), panel.dc_pin());
+        dc.set_low().unwrap();
+        spi.write(&[cmd]).unwrap();
+        dc.set_high().unwrap();
+        spi.write(data).unwrap();
+    }
+
+    #[test]
+    fn binary_partial_across_seam() {
+        let panel = VirtualEpd5in79::with_timing(BusyTiming::INSTANT);
+        let mut epd_impl = panel.driver();
+        let mut epd = epd_impl.as_binary();
+        epd.clear(BinaryColor::On).unwrap();
+        epd.display_binary_partial().unwrap();
+        assert_screen(&panel, &AREA, |_| Gray2::WHITE);
+
+        epd.draw_iter(AREA.points().map(|point| Pixel(point, checker(point))))
+            .unwrap();
+        epd.display_binary_partial().unwrap();
+        assert_screen(&panel, &AREA, |point| checker(point).into());
+
+        // only the window is sent, it starts and ends on whole bytes
+        let window = Rectangle::new(Point::new(384, 8), Size::new(24, 8));
+        epd.fill_solid(&AREA, BinaryColor::On).unwrap();
+        epd.fill_solid(&window, BinaryColor::Off).unwrap();
+        epd.display_binary_partial_region(window).unwrap();
+        assert_screen(&panel, &AREA, |point| {
+            if window.contains(point) {
+                Gray2::BLACK
+            } else {
+                checker(point).into()
+            }
+        });
+    }
+
+    #[test]
+    fn gray2_across_seam() {
+        let panel = VirtualEpd5in79::with_timing(BusyTiming::INSTANT);
+        let mut epd_impl = panel.driver();
+        let mut epd = epd_impl.as_gray2();
+        epd.clear(Gray2::WHITE).unwrap();
+        epd.draw_iter(AREA.points().map(|point| Pixel(point, gray_ramp(point))))
+            .unwrap();
+        epd.display_gray2().unwrap();
+        assert_screen(&panel, &AREA, gray_ramp);
+    }
+
+    #[test]
+    fn scan_follows_address() {
+        let panel = VirtualEpd5in79::with_timing(BusyTiming::INSTANT);
+        panel.rst_pin().set_high().unwrap();
+        let top_left = Rectangle::new(Point::zero(), Size::new(4, 1));
+        for (mode, y_window, y) in [
+            (0x03, [0x00, 0x00, 0x2b, 0x01], [0x00, 0x00]),
+            (0x01, [0x0f, 0x01, 0x00, 0x00], [0x0f, 0x01]),
+        ] {
+            // white RAM, then 4 black pixels at the address counter
+            send(&panel, 0x47, &[0xf7]);
+            send(&panel, 0xc7, &[0xf7]);
+            send(&panel, 0x11, &[mode]);
+            send(&panel, 0x44, &[0, 49]);
+            send(&panel, 0x45, &y_window);
+            send(&panel, 0x4e, &[0]);
+            send(&panel, 0x4f, &y);
+            send(&panel, 0x24, &[0x0f]);
+            send(&panel, 0x22, &[0xc7]);
+            send(&panel, 0x20, &[]);
+            assert_screen(&panel, &top_left, |_| Gray2::BLACK);
+        }
+    }
+
+    #[test]
+    fn scan_wraps_in_window() {
+        // 3 bytes counted up from 48 wrap to 47
+        assert_eq!(scan(48, (47, 49), true, 0), Some(48));
+        assert_eq!(scan(48, (47, 49), true, 2), Some(47));
+        // counted down
+        assert_eq!(scan(49, (49, 0), false, 1), Some(48));
+        assert_eq!(scan(0, (49, 0), false, 1), Some(49));
+        // outside of the window
+        assert_eq!(scan(10, (0, 5), true, 2), Some(12));
+        assert_eq!(scan(0, (5, 0), true, 0), Some(0));
+        assert_eq!(scan(1, (1, 1), false, 0), Some(1));
+        assert_eq!(scan(0, (10, 20), false, 1), None);
+    }
+
+    #[test]
+    fn ghosting() {
+        let panel = VirtualEpd5in79::with_timing(BusyTiming::INSTANT);
+        let mut epd_impl = panel.driver();
+        let mut epd = epd_impl.as_binary();
+        epd.clear(BinaryColor::On).unwrap();
+        epd.display_binary_full().unwrap();
+        assert_eq!(panel.ghosting(), None);
+        panel.set_physics(Some(Physics::DEFAULT));
+        assert_eq!(panel.ghosting(), Some(0.0));
+
+        // black growing across the seam, every partial leaves the new pixels a bit gray
+        let mut ghosting = Vec::new();
+        for step in 0..8 {
+            let black = Rectangle::new(Point::zero(), Size::new(99 * (step + 1), HIGH));
+            epd.fill_solid(&black, BinaryColor::Off).unwrap();
+            epd.display_binary_partial().unwrap();
+            ghosting.push(panel.ghosting().unwrap());
+        }
+        assert!(ghosting.windows(2).all(|w| w[0] < w[1]), "{ghosting:?}");
+
+        epd.display_binary_full().unwrap();
+        let cleared = panel.ghosting().unwrap();
+        assert!(cleared < ghosting[7] / 10.0, "{cleared} {ghosting:?}");
+        assert_eq!(panel.render().unwrap().len(), (WIDTH * HIGH) as usize);
+    }
+}
@@ -0,0 +1,382 @@
+//! Particle model of the panel, see [`VirtualEpd5in79::set_physics`](super::VirtualEpd5in79::set_physics).
+//!
+//! Every pixel has an optical level between black and white and a residual charge.
+//! A display update drives each pixel with the waveform of the LUT selected by its red and
+//! bw RAM bits. Every phase moves the level towards the side it drives to, by the number of
+//! its frames, so short waveforms leave the old image behind and a waveform driving the
+//! wrong way leaves the pixel on the wrong side. The gray levels of the gray refresh depend
+//! on timing the model does not resolve, there the level approaches the gray of the RAM.
+//! Frames driven towards one side leave a charge that slows the later transitions of the pixel,
+//! so the ghosting of repeated partial refreshes grows. Every reversal of the drive
+//! (the flashing of a full refresh) removes part of it.
+//!
+//! The waveforms written by `0x32` are read in the layout of a [`Waveform`](crate::epd5in79::Waveform):
+//! a VCOM LUT and the LUTs of the four RAM bit combinations, each 6 groups of
+//! `[repeat, VS, TP A, TP B, TP C, TP D, reserved]`. VS holds the source voltage of the
+//! phases A to D, A in the high bits: `00` GND, `01` VSH1, `10` VSL, `11` VSH2.
+//! The OTP waveforms of the full, fast and partial refresh cannot be read from the panel,
+//! they are approximated in the same layout.
+
+use embedded_graphics_core::{pixelcolor::Gray2, prelude::*};
+use waveshare_epd_core::bit_plane::BitPlaneBuffer;
+
+use crate::epd5in79::waveform::{Group, Lut, Voltage, GROUPS, GROUP_LEN, LUT_LEN};
+
+/// Approximated OTP waveforms, the groups of the LUTs of old (red) and new (bw) bits 00 to 11.
+#[rustfmt::skip]
+static OTP_FULL: [&[[u8; GROUP_LEN]]; 4] = [
+    &[[1, 0x80, 20, 0, 0, 0, 0], [2, 0x60, 10, 10, 0, 0, 0], [1, 0x40, 40, 0, 0, 0, 0]],
+    &[[1, 0x40, 20, 0, 0, 0, 0], [2, 0x90, 10, 10, 0, 0, 0], [1, 0x80, 40, 0, 0, 0, 0]],
+    &[[1, 0x80, 20, 0, 0, 0, 0], [2, 0x60, 10, 10, 0, 0, 0], [1, 0x40, 40, 0, 0, 0, 0]],
+    &[[1, 0x40, 20, 0, 0, 0, 0], [2, 0x90, 10, 10, 0, 0, 0], [1, 0x80, 40, 0, 0, 0, 0]],
+];
+#[rustfmt::skip]
+static OTP_FAST: [&[[u8; GROUP_LEN]]; 4] = [
+    &[[1, 0x90, 12, 12, 0, 0, 0]],
+    &[[1, 0x60, 12, 12, 0, 0, 0]],
+    &[[1, 0x90, 12, 12, 0, 0, 0]],
+    &[[1, 0x60, 12, 12, 0, 0, 0]],
+];
+#[rustfmt::skip]
+static OTP_PARTIAL: [&[[u8; GROUP_LEN]]; 4] = [
+    &[],
+    &[[1, 0x80, 15, 0, 0, 0, 0]],
+    &[[1, 0x40, 15, 0, 0, 0, 0]],
+    &[],
+];
+
+/// Parameters of the particle model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Physics {
+    /// Driven frames after which the level of an uncharged pixel has moved 63% of the way
+    /// to the target.
+    pub response_frames: f32,
+    /// Charge left by a frame driven towards one side, a charge of `1.0` doubles the
+    /// response frames.
+    pub charge_per_frame: f32,
+    /// Fraction of the charge removed by every reversal of the drive, in `0.0..=1.0`.
+    pub shake_clear: f32,
+}
+
+impl Physics {
+    /// Partial refreshes leave visible ghosting after a few updates, a full refresh removes it.
+    pub const DEFAULT: Self = Self {
+        response_frames: 8.0,
+        charge_per_frame: 0.01,
+        shake_clear: 0.5,
+    };
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The waveforms of an update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Waveforms {
+    Full,
+    Fast,
+    Partial,
+}
+
+/// What a waveform does to a pixel.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct Drive {
+    /// The driven phases in order, `(towards white, frames)`.
+    phases: Vec<(bool, u32)>,
+    frames: u32,
+    /// Frames towards white minus frames towards black.
+    balance: i32,
+    reversals: u32,
+}
+
+impl Drive {
+    fn of(groups: impl IntoIterator<Item = Group>) -> Self {
+        let mut drive = Self::default();
+        let mut last = None;
+        for group in groups {
+            for _ in 0..group.repeat {
+                for phase in group.phases {
+                    if phase.voltage == Voltage::Gnd || phase.frames == 0 {
+                        continue;
+                    }
+                    let (white, frames) = (phase.voltage == Voltage::Vsl, phase.frames);
+                    if last.is_some_and(|last| last != white) {
+                        drive.reversals += 1;
+                    }
+                    last = Some(white);
+                    drive.phases.push((white, frames as u32));
+                    drive.frames += frames as u32;
+                    drive.balance += if white {
+                        frames as i32
+                    } else {
+                        -(frames as i32)
+                    };
+                }
+            }
+        }
+        drive
+    }
+
+    /// Drives of the built-in waveforms, by LUT.
+    pub(super) fn otp(waveforms: Waveforms) -> [Self; 4] {
+        let luts = match waveforms {
+            Waveforms::Full => &OTP_FULL,
+            Waveforms::Fast => &OTP_FAST,
+            Waveforms::Partial => &OTP_PARTIAL,
+        };
+        luts.map(|groups| Self::of(groups.iter().map(|group| Group::decode(group))))
+    }
+
+    /// Drives of a waveform written by `0x32`, by LUT.
+    ///
+    /// The bytes of a short write are zero, the groups they cover drive nothing.
+    pub(super) fn lut(lut: &[u8]) -> [Self; 4] {
+        let mut register = [0; LUT_LEN];
+        let len = lut.len().min(LUT_LEN);
+        register[..len].copy_from_slice(&lut[..len]);
+        let luts = [Lut::Bits00, Lut::Bits01, Lut::Bits10, Lut::Bits11];
+        luts.map(|l| {
+            let groups = register[l.offset()..].chunks_exact(GROUP_LEN).take(GROUPS);
+            Self::of(groups.map(Group::decode))
+        })
+    }
+}
+
+/// Whether a waveform written by `0x32` leaves the pixels of equal red and bw bits alone,
+/// like the partial refresh.
+pub(super) fn keeps_unchanged(lut: &[u8]) -> bool {
+    let drives = Drive::lut(lut);
+    drives[0].frames == 0 && drives[3].frames == 0
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Particle {
+    /// `0.0` black, `1.0` white.
+    level: f32,
+    charge: f32,
+}
+
+#[derive(Debug)]
+pub(super) struct Simulation {
+    physics: Physics,
+    size: Size,
+    particles: Vec<Particle>,
+    timelapse: Option<Vec<Vec<u8>>>,
+}
+
+impl Simulation {
+    /// Start settled on `screen`.
+    pub(super) fn new(physics: Physics, screen: &BitPlaneBuffer<Gray2>) -> Self {
+        let size = screen.size();
+        let particles = screen
+            .bounding_box()
+            .points()
+            .map(|point| Particle {
+                level: target(screen.get(point).unwrap_or(Gray2::WHITE)),
+                charge: 0.0,
+            })
+            .collect();
+        Self {
+            physics,
+            size,
+            particles,
+            timelapse: None,
+        }
+    }
+
+    /// Drive the pixel `point`, `gray` of the gray refresh of `color`.
+    pub(super) fn drive(&mut self, point: Point, color: Gray2, drive: &Drive, gray: bool) {
+        if drive.frames == 0 {
+            return;
+        }
+        let index = point.y as usize * self.size.width as usize + point.x as usize;
+        let physics = &self.physics;
+        let particle = &mut self.particles[index];
+
+        let response = physics.response_frames * (1.0 + particle.charge.abs());
+        let approach = |level: f32, goal: f32, frames: u32| {
+            goal + (level - goal) * (-(frames as f32) / response).exp()
+        };
+        if gray {
+            particle.level = approach(particle.level, target(color), drive.frames);
+        } else {
+            for &(white, frames) in &drive.phases {
+                let goal = if white { 1.0 } else { 0.0 };
+                particle.level = approach(particle.level, goal, frames);
+            }
+        }
+        particle.charge *= (1.0 - physics.shake_clear).powi(drive.reversals as i32);
+        particle.charge += drive.balance as f32 * physics.charge_per_frame;
+    }
+
+    /// Call after every display update.
+    pub(super) fn finish_update(&mut self) {
+        if self.timelapse.is_some() {
+            let frame = self.render();
+            self.timelapse.get_or_insert_with(Vec::new).push(frame);
+        }
+    }
+
+    pub(super) fn set_recording(&mut self, recording: bool) {
+        self.timelapse = recording.then(Vec::new);
+    }
+
+    pub(super) fn timelapse(&self) -> &[Vec<u8>] {
+        self.timelapse.as_deref().unwrap_or_default()
+    }
+
+    /// 8-bit luma of every pixel, row-major.
+    pub(super) fn render(&self) -> Vec<u8> {
+        self.particles
+            .iter()
+            .map(|particle| (particle.level * 255.0).round() as u8)
+            .collect()
+    }
+
+    /// Mean difference between the luma and `screen`, in `0.0..=1.0`.
+    pub(super) fn ghosting(&self, screen: &BitPlaneBuffer<Gray2>) -> f32 {
+        let sum: f32 = screen
+            .bounding_box()
+            .points()
+            .zip(&self.particles)
+            .map(|(point, particle)| {
+                let ideal = target(screen.get(point).unwrap_or(Gray2::WHITE));
+                (particle.level - ideal).abs()
+            })
+            .sum();
+        sum / self.particles.len().max(1) as f32
+    }
+}
+
+fn target(color: Gray2) -> f32 {
+    color.luma() as f32 / 3.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::epd5in79::waveform::{Lut, Waveform};
+
+    use super::*;
+
+    /// Drive the pixels of `screen` to `colors` with the drives of their old and new bits.
+    fn update(
+        simulation: &mut Simulation,
+        screen: &mut BitPlaneBuffer<Gray2>,
+        colors: &[Gray2],
+        drives: &[Drive; 4],
+    ) {
+        for (x, &color) in colors.iter().enumerate() {
+            let point = Point::new(x as i32, 0);
+            let old = screen.get(point) == Some(Gray2::WHITE);
+            let new = color == Gray2::WHITE;
+            simulation.drive(
+                point,
+                color,
+                &drives[(old as usize) << 1 | new as usize],
+                false,
+            );
+            screen.set(point, color);
+        }
+        simulation.finish_update();
+    }
+
+    #[test]
+    fn otp_drives() {
+        for drive in Drive::otp(Waveforms::Full) {
+            assert!(drive.reversals > 0, "{drive:?}");
+        }
+        let [keep_black, to_white, to_black, keep_white] = Drive::otp(Waveforms::Partial);
+        assert_eq!(keep_black.frames, 0);
+        assert_eq!(keep_white.frames, 0);
+        assert_eq!(to_white.phases, [(true, 15)]);
+        assert_eq!(to_black.phases, [(false, 15)]);
+        assert_eq!(to_black.balance, -15);
+    }
+
+    #[test]
+    fn lut_drives() {
+        let waveform = Waveform::gray2();
+        let drives = Drive::lut(waveform.lut_bytes());
+        for (drive, lut) in drives.iter().zip(&Lut::ALL[1..]) {
+            assert_eq!(drive.frames, waveform.frames(*lut), "{lut:?}");
+        }
+    }
+
+    #[test]
+    fn short_lut() {
+        // the bytes not written drive nothing
+        assert!(Drive::lut(&[]).iter().all(|drive| drive.frames == 0));
+        assert!(keeps_unchanged(&[]));
+
+        let bytes = Waveform::gray2().lut_bytes().to_vec();
+        let full = Drive::lut(&bytes);
+        let short = Drive::lut(&bytes[..Lut::Bits01.offset()]);
+        assert_eq!(short[0], full[0]);
+        assert!(short[0].frames > 0);
+        assert!(short[1..].iter().all(|drive| drive.frames == 0));
+    }
+
+    #[test]
+    fn keeps_unchanged_pixels() {
+        let gray = Waveform::gray2();
+        assert!(!keeps_unchanged(gray.lut_bytes()));
+        let still = [Group::default(); GROUPS];
+        let partial = gray
+            .with_groups(Lut::Bits00, still)
+            .with_groups(Lut::Bits11, still);
+        assert!(keeps_unchanged(partial.lut_bytes()));
+    }
+
+    #[test]
+    fn charge_slows_transitions() {
+        let white = BitPlaneBuffer::new(Size::new(2, 1), Gray2::WHITE);
+        let mut simulation = Simulation::new(Physics::DEFAULT, &white);
+        let [.., to_black, _] = Drive::otp(Waveforms::Partial);
+        let [_, to_white, ..] = Drive::otp(Waveforms::Partial);
+        // pixel 0 is pushed to black a few more times first
+        for _ in 0..10 {
+            simulation.drive(Point::new(0, 0), Gray2::BLACK, &to_black, false);
+        }
+        simulation.drive(Point::new(1, 0), Gray2::BLACK, &to_black, false);
+        for x in 0..2 {
+            simulation.drive(Point::new(x, 0), Gray2::WHITE, &to_white, false);
+        }
+        let luma = simulation.render();
+        assert!(luma[0] < luma[1], "{luma:?}");
+    }
+
+    #[test]
+    fn partials_ghost_and_full_clears() {
+        let mut screen = BitPlaneBuffer::new(Size::new(16, 1), Gray2::WHITE);
+        let mut simulation = Simulation::new(Physics::DEFAULT, &screen);
+        assert_eq!(simulation.ghosting(&screen), 0.0);
+        simulation.set_recording(true);
+
+        // a black pixel moving right
+        let partial = Drive::otp(Waveforms::Partial);
+        let mut ghosting = Vec::new();
+        for step in 0..16 {
+            let colors: Vec<_> = (0..16)
+                .map(|x| {
+                    if x == step {
+                        Gray2::BLACK
+                    } else {
+                        Gray2::WHITE
+                    }
+                })
+                .collect();
+            update(&mut simulation, &mut screen, &colors, &partial);
+            ghosting.push(simulation.ghosting(&screen));
+        }
+        assert!(ghosting.windows(2).all(|w| w[0] < w[1]), "{ghosting:?}");
+        assert_eq!(simulation.timelapse().len(), 16);
+
+        let full = Drive::otp(Waveforms::Full);
+        update(&mut simulation, &mut screen, &[Gray2::WHITE; 16], &full);
+        let cleared = simulation.ghosting(&screen);
+        assert!(cleared < ghosting[15] / 10.0, "{cleared} {ghosting:?}");
+    }
+}
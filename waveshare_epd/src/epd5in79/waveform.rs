@@ -0,0 +1,544 @@
+//! The waveforms written to the LUT register (0x32).
+//!
+//! A [`Waveform`] is the 227 bytes of 0x32 and the voltages sent after them:
+//! a VCOM LUT and the LUTs of the four combinations of the red and bw RAM bits,
+//! each 6 groups of `[repeat, VS, TP A, TP B, TP C, TP D, reserved]`, 14 reserved bytes,
+//! the frame rate and 2 reserved bytes. VS holds the [`Voltage`] of the phases A to D,
+//! A in the high bits, TP the frames of each phase.
+//!
+//! The voltages are the end option (0x3f), the gate voltage (0x03),
+//! the source voltages (0x04) and VCOM (0x2c), see [`Voltages`].
+
+use std::borrow::Cow;
+
+use waveshare_epd_core::error::ConfigError;
+
+/// Bytes written by 0x32.
+pub const LUT_LEN: usize = 227;
+/// Bytes of [`Waveform::to_bytes`], the LUT and the 6 bytes of the [`Voltages`].
+pub const WAVEFORM_LEN: usize = LUT_LEN + 6;
+/// Groups of every [`Lut`].
+pub const GROUPS: usize = 6;
+
+/// Bytes of a [`Group`].
+pub(super) const GROUP_LEN: usize = 7;
+const LUTS: usize = 5;
+/// The reserved bytes after the LUTs.
+const RESERVED: std::ops::Range<usize> = LUTS * GROUPS * GROUP_LEN..FRAME_RATE;
+const FRAME_RATE: usize = 224;
+
+/// The voltage of a phase.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Voltage {
+    #[default]
+    Gnd,
+    /// Drives to black.
+    Vsh1,
+    /// Drives to white.
+    Vsl,
+    /// Drives to black.
+    Vsh2,
+}
+
+impl Voltage {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            0x00 => Self::Gnd,
+            0x01 => Self::Vsh1,
+            0x02 => Self::Vsl,
+            _ => Self::Vsh2,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Phase {
+    pub voltage: Voltage,
+    pub frames: u8,
+}
+
+/// The phases A to D, run `repeat` times, a group with `repeat` `0` is skipped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Group {
+    pub repeat: u8,
+    pub phases: [Phase; 4],
+}
+
+impl Group {
+    /// The frames of the group, repeats included.
+    pub fn frames(&self) -> u32 {
+        let frames: u32 = self.phases.iter().map(|phase| phase.frames as u32).sum();
+        self.repeat as u32 * frames
+    }
+
+    /// Whether a phase drives the pixels.
+    pub fn is_driving(&self) -> bool {
+        self.repeat != 0
+            && self
+                .phases
+                .iter()
+                .any(|phase| phase.voltage != Voltage::Gnd && phase.frames != 0)
+    }
+
+    pub(super) fn decode(bytes: &[u8]) -> Self {
+        let vs = bytes[1];
+        Self {
+            repeat: bytes[0],
+            phases: std::array::from_fn(|i| Phase {
+                voltage: Voltage::from_bits(vs >> (6 - 2 * i)),
+                frames: bytes[2 + i],
+            }),
+        }
+    }
+
+    fn encode(&self) -> [u8; GROUP_LEN] {
+        let vs = self
+            .phases
+            .iter()
+            .enumerate()
+            .fold(0, |vs, (i, phase)| vs | phase.voltage.bits() << (6 - 2 * i));
+        let [a, b, c, d] = self.phases.map(|phase| phase.frames);
+        [self.repeat, vs, a, b, c, d, 0]
+    }
+}
+
+/// The LUTs of a [`Waveform`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lut {
+    Vcom,
+    /// `BitsXY` drives the pixels of red RAM bit X and bw RAM bit Y,
+    /// the old and new pixel of the partial refresh, the gray level `0bXY` of the gray refresh.
+    Bits00,
+    Bits01,
+    Bits10,
+    Bits11,
+}
+
+impl Lut {
+    pub const ALL: [Self; LUTS] = [
+        Self::Vcom,
+        Self::Bits00,
+        Self::Bits01,
+        Self::Bits10,
+        Self::Bits11,
+    ];
+
+    /// The first byte of the LUT.
+    pub(super) fn offset(self) -> usize {
+        self as usize * GROUPS * GROUP_LEN
+    }
+}
+
+/// The raw values of the commands sent after the LUT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Voltages {
+    /// End option (0x3f).
+    pub end_option: u8,
+    /// VGH (0x03).
+    pub gate: u8,
+    /// VSH1, VSH2 and VSL (0x04).
+    pub vsh1: u8,
+    pub vsh2: u8,
+    pub vsl: u8,
+    /// VCOM (0x2c).
+    pub vcom: u8,
+}
+
+impl Voltages {
+    /// The voltages of the gray refresh of the vendor driver:
+    /// 20 V gate, 15 V VSH1, 5 V VSH2, -15 V VSL and -1.6 V VCOM.
+    pub const DEFAULT: Self = Self {
+        end_option: 0x22,
+        gate: 0x17,
+        vsh1: 0x41,
+        vsh2: 0xa8,
+        vsl: 0x32,
+        vcom: 0x40,
+    };
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !matches!(self.gate, 0x00 | 0x03..=0x17) {
+            return Err(ConfigError::new(
+                "gate",
+                format!("{:#04x} is not 0x00 or in 0x03..=0x17", self.gate),
+            ));
+        }
+        for (field, value) in [("vsh1", self.vsh1), ("vsh2", self.vsh2)] {
+            if !matches!(value, 0x23..=0x50 | 0x8e..=0xce) {
+                return Err(ConfigError::new(
+                    field,
+                    format!("{:#04x} is not in 0x23..=0x50 or 0x8e..=0xce", value),
+                ));
+            }
+        }
+        if !matches!(self.vsl, 0x1a..=0x3a) || self.vsl & 0x01 != 0 {
+            return Err(ConfigError::new(
+                "vsl",
+                format!("{:#04x} is not even in 0x1a..=0x3a", self.vsl),
+            ));
+        }
+        if !matches!(self.vcom, 0x08..=0x78) {
+            return Err(ConfigError::new(
+                "vcom",
+                format!("{:#04x} is not in 0x08..=0x78", self.vcom),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Voltages {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A waveform loaded into the LUT register, see [`WaveformSource::Lut`](super::WaveformSource::Lut).
+///
+/// # Examples
+/// ```
+/// # use waveshare_epd::epd5in79::{Lut, Waveform};
+/// let gray = Waveform::gray2();
+/// assert_eq!(gray.groups(Lut::Bits00)[1].repeat, 1);
+///
+/// // a shorter last group of the black pixels
+/// let mut groups = gray.groups(Lut::Bits00);
+/// groups[2].phases[3].frames = 0;
+/// let darker = gray.with_groups(Lut::Bits00, groups);
+/// darker.validate().unwrap();
+///
+/// // the layout of the arrays of the vendor driver
+/// let bytes = darker.to_bytes();
+/// assert_eq!(Waveform::parse(&bytes).unwrap(), darker);
+/// assert!(Waveform::parse(&bytes[..100]).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Waveform {
+    /// [`LUT_LEN`] bytes.
+    lut: Cow<'static, [u8]>,
+    voltages: Voltages,
+}
+
+impl Waveform {
+    /// The waveform of the gray refresh.
+    pub fn gray2() -> Self {
+        Self {
+            lut: Cow::Borrowed(GRAY2_LUT),
+            voltages: Voltages::DEFAULT,
+        }
+    }
+
+    /// Parse [`LUT_LEN`] bytes of the LUT with [`Voltages::DEFAULT`],
+    /// or [`WAVEFORM_LEN`] bytes of the LUT and the voltages in the order of [`Voltages`].
+    pub fn parse(bytes: &[u8]) -> Result<Self, ConfigError> {
+        let (lut, voltages) = match bytes.len() {
+            LUT_LEN => (bytes, Voltages::DEFAULT),
+            WAVEFORM_LEN => {
+                let (lut, v) = bytes.split_at(LUT_LEN);
+                let voltages = Voltages {
+                    end_option: v[0],
+                    gate: v[1],
+                    vsh1: v[2],
+                    vsh2: v[3],
+                    vsl: v[4],
+                    vcom: v[5],
+                };
+                (lut, voltages)
+            }
+            len => {
+                return Err(ConfigError::new(
+                    "waveform",
+                    format!(
+                        "expected {} or {} bytes, got {}",
+                        LUT_LEN, WAVEFORM_LEN, len
+                    ),
+                ))
+            }
+        };
+        let waveform = Self {
+            lut: Cow::Owned(lut.to_vec()),
+            voltages,
+        };
+        waveform.validate()?;
+        Ok(waveform)
+    }
+
+    /// The LUT and the voltages, [`WAVEFORM_LEN`] bytes read by [`parse`](Self::parse).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let v = self.voltages;
+        let mut bytes = self.lut.to_vec();
+        bytes.extend([v.end_option, v.gate, v.vsh1, v.vsh2, v.vsl, v.vcom]);
+        bytes
+    }
+
+    /// The bytes written by 0x32.
+    pub fn lut_bytes(&self) -> &[u8] {
+        &self.lut
+    }
+
+    pub fn groups(&self, lut: Lut) -> [Group; GROUPS] {
+        let bytes = &self.lut[lut.offset()..];
+        std::array::from_fn(|i| Group::decode(&bytes[i * GROUP_LEN..]))
+    }
+
+    pub fn with_groups(mut self, lut: Lut, groups: [Group; GROUPS]) -> Self {
+        let bytes = &mut self.lut.to_mut()[lut.offset()..];
+        for (chunk, group) in bytes.chunks_exact_mut(GROUP_LEN).zip(&groups) {
+            chunk.copy_from_slice(&group.encode());
+        }
+        self
+    }
+
+    /// The frames of `lut`, repeats included.
+    pub fn frames(&self, lut: Lut) -> u32 {
+        self.groups(lut).iter().map(Group::frames).sum()
+    }
+
+    /// The raw frame rate setting.
+    pub fn frame_rate(&self) -> u8 {
+        self.lut[FRAME_RATE]
+    }
+
+    pub fn with_frame_rate(mut self, frame_rate: u8) -> Self {
+        self.lut.to_mut()[FRAME_RATE] = frame_rate;
+        self
+    }
+
+    pub fn voltages(&self) -> Voltages {
+        self.voltages
+    }
+
+    pub fn with_voltages(mut self, voltages: Voltages) -> Self {
+        self.voltages = voltages;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for lut in Lut::ALL {
+            let reserved = self.lut[lut.offset()..]
+                .chunks_exact(GROUP_LEN)
+                .take(GROUPS)
+                .any(|group| group[GROUP_LEN - 1] != 0);
+            if reserved {
+                return Err(ConfigError::new(
+                    "waveform",
+                    format!("the reserved byte of a group of {:?} is not zero", lut),
+                ));
+            }
+        }
+        if self.lut[RESERVED].iter().any(|&byte| byte != 0) {
+            return Err(ConfigError::new(
+                "waveform",
+                format!("the reserved bytes {:?} are not zero", RESERVED),
+            ));
+        }
+        if self.frame_rate() == 0 {
+            return Err(ConfigError::new("frame_rate", "must not be zero"));
+        }
+        let driving = Lut::ALL[1..]
+            .iter()
+            .any(|&lut| self.groups(lut).iter().any(Group::is_driving));
+        if !driving {
+            return Err(ConfigError::new("waveform", "no LUT drives the pixels"));
+        }
+        self.voltages.validate()
+    }
+
+    /// The commands loading the waveform.
+    pub(super) fn commands(&self) -> [(u8, Vec<u8>); 5] {
+        let v = self.voltages;
+        [
+            (0x32, self.lut.to_vec()),
+            (0x3f, vec![v.end_option]),
+            (0x03, vec![v.gate]),
+            (0x04, vec![v.vsh1, v.vsh2, v.vsl]),
+            (0x2c, vec![v.vcom]),
+        ]
+    }
+}
+
+#[rustfmt::skip]
+static GRAY2_LUT: &[u8; LUT_LEN] = &[
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+    0x01, 0x4A, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x01, 0x82, 0x42, 0x00, 0x00, 0x10, 0x00,
+    0x01, 0x8A, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+    0x01, 0x41, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x01, 0x82, 0x42, 0x00, 0x00, 0x10, 0x00,
+    0x01, 0x81, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+    0x01, 0x81, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x01, 0x82, 0x42, 0x00, 0x00, 0x10, 0x00,
+    0x01, 0x41, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+    0x01, 0x8A, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x01, 0x82, 0x42, 0x00, 0x00, 0x10, 0x00,
+    0x01, 0x4A, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+    0x02, 0x00, 0x00
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The field of the error parsing `bytes`.
+    fn parse_error(bytes: &[u8]) -> &'static str {
+        Waveform::parse(bytes).unwrap_err().field
+    }
+
+    #[test]
+    fn parse_lengths() {
+        let bytes = Waveform::gray2().to_bytes();
+        for len in [0, 100, LUT_LEN - 1, LUT_LEN + 1, WAVEFORM_LEN + 1] {
+            let mut bytes = bytes.clone();
+            bytes.resize(len, 0);
+            assert_eq!(parse_error(&bytes), "waveform", "{len} bytes");
+        }
+
+        let lut = Waveform::parse(&bytes[..LUT_LEN]).unwrap();
+        assert_eq!(lut.voltages(), Voltages::DEFAULT);
+        assert_eq!(lut.lut_bytes(), GRAY2_LUT);
+    }
+
+    #[test]
+    fn round_trip() {
+        let voltages = Voltages {
+            gate: 0x00,
+            vsh1: 0x8e,
+            vsh2: 0x50,
+            vsl: 0x1a,
+            vcom: 0x78,
+            ..Voltages::DEFAULT
+        };
+        let waveform = Waveform::gray2()
+            .with_frame_rate(0x04)
+            .with_voltages(voltages);
+        let bytes = waveform.to_bytes();
+        assert_eq!(bytes.len(), WAVEFORM_LEN);
+        assert_eq!(Waveform::parse(&bytes).unwrap(), waveform);
+    }
+
+    #[test]
+    fn groups_round_trip() {
+        let gray = Waveform::gray2();
+        for lut in Lut::ALL {
+            let groups = gray.groups(lut);
+            assert_eq!(gray.clone().with_groups(lut, groups), gray);
+        }
+        assert_eq!(gray.frames(Lut::Vcom), 0);
+        assert_eq!(gray.frames(Lut::Bits00), 1 + 0x52 + 1);
+    }
+
+    #[test]
+    fn reserved_bytes() {
+        let mut bytes = Waveform::gray2().to_bytes();
+        bytes[Lut::Bits01.offset() + GROUP_LEN - 1] = 0x01;
+        assert_eq!(parse_error(&bytes), "waveform");
+
+        let mut bytes = Waveform::gray2().to_bytes();
+        bytes[RESERVED.end - 1] = 0x01;
+        assert_eq!(parse_error(&bytes), "waveform");
+    }
+
+    #[test]
+    fn frame_rate() {
+        let bytes = Waveform::gray2().with_frame_rate(0).to_bytes();
+        assert_eq!(parse_error(&bytes), "frame_rate");
+    }
+
+    #[test]
+    fn not_driving() {
+        let gray = Waveform::gray2();
+        // a VCOM LUT alone does not move the particles
+        let idle = Lut::ALL[1..].iter().fold(gray.clone(), |waveform, &lut| {
+            waveform.with_groups(lut, [Group::default(); GROUPS])
+        });
+        let idle = idle.with_groups(Lut::Vcom, gray.groups(Lut::Bits00));
+        assert_eq!(parse_error(&idle.to_bytes()), "waveform");
+    }
+
+    #[test]
+    fn voltages() {
+        for (field, voltages) in [
+            (
+                "gate",
+                Voltages {
+                    gate: 0x01,
+                    ..Voltages::DEFAULT
+                },
+            ),
+            (
+                "gate",
+                Voltages {
+                    gate: 0x18,
+                    ..Voltages::DEFAULT
+                },
+            ),
+            (
+                "vsh1",
+                Voltages {
+                    vsh1: 0x22,
+                    ..Voltages::DEFAULT
+                },
+            ),
+            (
+                "vsh2",
+                Voltages {
+                    vsh2: 0x60,
+                    ..Voltages::DEFAULT
+                },
+            ),
+            (
+                "vsl",
+                Voltages {
+                    vsl: 0x33,
+                    ..Voltages::DEFAULT
+                },
+            ),
+            (
+                "vsl",
+                Voltages {
+                    vsl: 0x3c,
+                    ..Voltages::DEFAULT
+                },
+            ),
+            (
+                "vcom",
+                Voltages {
+                    vcom: 0x00,
+                    ..Voltages::DEFAULT
+                },
+            ),
+        ] {
+            let bytes = Waveform::gray2().with_voltages(voltages).to_bytes();
+            assert_eq!(parse_error(&bytes), field, "{voltages:x?}");
+        }
+    }
+}
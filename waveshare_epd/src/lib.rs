@@ -1,2 +1,4 @@
 #[cfg(feature = "epd5in79")]
 pub mod epd5in79;
+#[cfg(feature = "linux")]
+pub mod linux;
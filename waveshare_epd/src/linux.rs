@@ -0,0 +1,146 @@
+//! Helpers for `spidev` and `gpio-cdev` on Linux.
+
+use std::{
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use embedded_hal::digital::{ErrorType, InputPin};
+use linux_embedded_hal::{
+    gpio_cdev::{self, Chip, EventRequestFlags, LineEventHandle, LineRequestFlags},
+    CdevPin, CdevPinError,
+};
+use nix::poll::{poll, PollFd, PollFlags};
+use waveshare_epd_core::spi_interface::WaitIdle;
+
+/// How to wait for the busy pin.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BusyWait {
+    /// Poll the busy pin, sleeping between reads.
+    #[default]
+    Poll,
+    /// Request the busy line with falling-edge events and block on the event fd.
+    ///
+    /// Falls back to [`BusyWait::Poll`] if the kernel or the GPIO chip does not support events.
+    Events,
+}
+
+/// Bias of an input line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Bias {
+    /// Leave the bias as configured by the kernel or the device tree.
+    AsIs,
+    PullUp,
+    #[default]
+    PullDown,
+    Disable,
+}
+
+impl Bias {
+    /// `GPIOHANDLE_REQUEST_BIAS_*` flags, which `gpio-cdev` does not name.
+    pub fn flags(self) -> LineRequestFlags {
+        match self {
+            Self::AsIs => LineRequestFlags::empty(),
+            Self::PullUp => LineRequestFlags::from_bits_retain(1 << 5),
+            Self::PullDown => LineRequestFlags::from_bits_retain(1 << 6),
+            Self::Disable => LineRequestFlags::from_bits_retain(1 << 7),
+        }
+    }
+}
+
+/// The maximum size of a single spidev transfer, if the kernel exposes it.
+pub fn spidev_bufsiz() -> Option<usize> {
+    std::fs::read_to_string("/sys/module/spidev/parameters/bufsiz")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// A busy pin on `gpio-cdev`, see [`BusyWait`].
+pub enum CdevBusyPin {
+    Poll(CdevPin),
+    Events(LineEventHandle),
+}
+
+impl Debug for CdevBusyPin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Poll(_) => f.debug_tuple("Poll").finish_non_exhaustive(),
+            Self::Events(events) => f.debug_tuple("Events").field(events).finish(),
+        }
+    }
+}
+
+impl CdevBusyPin {
+    /// Request `line` as input, with falling-edge events if `busy_wait` is [`BusyWait::Events`].
+    pub fn request(
+        chip: &mut Chip,
+        line: u32,
+        flags: LineRequestFlags,
+        consumer: &str,
+        busy_wait: BusyWait,
+    ) -> Result<Self, gpio_cdev::Error> {
+        let line = chip.get_line(line)?;
+        if busy_wait == BusyWait::Events {
+            if let Ok(events) =
+                line.events(flags.clone(), EventRequestFlags::FALLING_EDGE, consumer)
+            {
+                return Ok(Self::Events(events));
+            }
+        }
+        Ok(Self::Poll(CdevPin::new(line.request(flags, 0, consumer)?)?))
+    }
+
+    /// Whether falling-edge events are in use.
+    pub fn is_events(&self) -> bool {
+        matches!(self, Self::Events(_))
+    }
+}
+
+impl ErrorType for CdevBusyPin {
+    type Error = CdevPinError;
+}
+
+impl InputPin for CdevBusyPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        match self {
+            Self::Poll(pin) => pin.is_high(),
+            Self::Events(events) => Ok(events.get_value()? != 0),
+        }
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl WaitIdle for CdevBusyPin {
+    fn wait_idle(&mut self, timeout: Duration) -> Result<Option<bool>, Self::Error> {
+        let Self::Events(events) = self else {
+            return Ok(None);
+        };
+
+        let now = Instant::now();
+        loop {
+            // stale events of previous refreshes may still be queued,
+            // so the level is the source of truth.
+            if events.get_value()? == 0 {
+                return Ok(Some(true));
+            }
+            let remaining = timeout.saturating_sub(now.elapsed());
+            if remaining.is_zero() {
+                return Ok(Some(false));
+            }
+
+            let ready = {
+                let mut fds = [PollFd::new(&*events, PollFlags::POLLIN)];
+                let ms = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+                poll(&mut fds, ms).map_err(|e| gpio_cdev::Error::from(std::io::Error::from(e)))?
+            };
+            if ready > 0 {
+                events.get_event()?;
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Defined a common async SPI interface.
+//!
+//! This is the async counterpart of [`SpiInterface`](crate::spi_interface::SpiInterface),
+//! it uses [embedded-hal-async](https://docs.rs/embedded-hal-async/latest/embedded_hal_async/)
+//! and waits for the busy pin by interrupt ([`Wait`]) instead of polling.
+//!
+//! The same pin conventions apply.
+
+use std::{
+    fmt::Debug,
+    future::{poll_fn, Future},
+    pin::pin,
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{Error, TimeOutError},
+    spi_interface::{DelayStep, SpiInterfaceError},
+};
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::{delay::DelayNs, digital::Wait, spi::SpiDevice};
+
+/// A common async SPI interface uses [embedded-hal-async](https://docs.rs/embedded-hal-async/latest/embedded_hal_async/).
+pub struct AsyncSpiInterface<Spi, I, O, D> {
+    spi: Spi,
+    rst_pin: O,
+    dc_pin: O,
+    cs_pin: Option<O>,
+    busy_pin: I,
+    pwr_pin: O,
+
+    delay: D,
+}
+
+impl<Spi, I, O, D> Debug for AsyncSpiInterface<Spi, I, O, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncSpiInterface").finish_non_exhaustive()
+    }
+}
+
+impl<Spi, I, O, D> AsyncSpiInterface<Spi, I, O, D>
+where
+    Spi: SpiDevice,
+    I: Wait,
+    O: OutputPin,
+    D: DelayNs,
+{
+    pub fn new(
+        spi: Spi,
+        rst_pin: O,
+        dc_pin: O,
+        cs_pin: Option<O>,
+        busy_pin: I,
+        pwr_pin: O,
+
+        delay: D,
+    ) -> Self {
+        Self {
+            spi,
+            rst_pin,
+            dc_pin,
+            cs_pin,
+            busy_pin,
+            pwr_pin,
+            delay,
+        }
+    }
+
+    fn set_cs(&mut self, active: bool) -> Result<(), SpiInterfaceError<Spi, I, O>> {
+        if let Some(cs) = self.cs_pin.as_mut() {
+            if active {
+                cs.set_high().map_err(Error::Pin)?;
+            } else {
+                cs.set_low().map_err(Error::Pin)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_rst_pin(&mut self, active: bool) -> Result<(), SpiInterfaceError<Spi, I, O>> {
+        if active {
+            self.rst_pin.set_high().map_err(Error::Pin)?;
+        } else {
+            self.rst_pin.set_low().map_err(Error::Pin)?;
+        }
+        Ok(())
+    }
+
+    pub async fn delay(&mut self, delay: DelayStep) {
+        match delay {
+            DelayStep::Ms(ms) => self.delay.delay_ms(ms).await,
+            DelayStep::Us(us) => self.delay.delay_us(us).await,
+            DelayStep::Ns(ns) => self.delay.delay_ns(ns).await,
+        }
+    }
+
+    /// Wait for the falling edge of the busy pin, or fail after `timeout`.
+    pub async fn wait_busy_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Duration, SpiInterfaceError<Spi, I, O>> {
+        let now = Instant::now();
+        let mut idle = pin!(self.busy_pin.wait_for_low());
+        let mut timer = pin!(self
+            .delay
+            .delay_us(u32::try_from(timeout.as_micros()).unwrap_or(u32::MAX)));
+
+        let result = poll_fn(|cx| {
+            if let Poll::Ready(res) = idle.as_mut().poll(cx) {
+                return Poll::Ready(Some(res));
+            }
+            if timer.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+            Poll::Pending
+        })
+        .await;
+
+        match result {
+            Some(res) => {
+                res.map_err(Error::BusyPin)?;
+                Ok(now.elapsed())
+            }
+            None => Err(TimeOutError {
+                timeout,
+                elapsed: now.elapsed(),
+            }
+            .into()),
+        }
+    }
+
+    pub async fn command(&mut self, cmd: u8) -> Result<(), SpiInterfaceError<Spi, I, O>> {
+        self.set_cs(true)?;
+        self.dc_pin.set_low().map_err(Error::Pin)?;
+        self.spi.write(&[cmd]).await.map_err(Error::Spi)?;
+        self.set_cs(false)?;
+        Ok(())
+    }
+
+    pub async fn data(
+        &mut self,
+        data: impl AsRef<[u8]>,
+        chunk_size: usize,
+    ) -> Result<(), SpiInterfaceError<Spi, I, O>> {
+        let data = data.as_ref();
+        let chunk_size = chunk_size.max(1);
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.set_cs(true)?;
+        self.dc_pin.set_high().map_err(Error::Pin)?;
+        for chunk in data.chunks(chunk_size) {
+            self.spi.write(chunk).await.map_err(Error::Spi)?;
+        }
+        self.set_cs(false)?;
+        Ok(())
+    }
+
+    pub async fn command_data(
+        &mut self,
+        cmd: u8,
+        data: impl AsRef<[u8]>,
+        chunk_size: usize,
+    ) -> Result<(), SpiInterfaceError<Spi, I, O>> {
+        self.command(cmd).await?;
+        self.data(data, chunk_size).await?;
+        Ok(())
+    }
+
+    /// Send `cmd` and read `buf.len()` data bytes from the controller.
+    ///
+    /// The SPI device must be able to read the data line of the controller,
+    /// e.g. a 3-wire (half-duplex) bus, or MISO wired to it.
+    pub async fn command_read(
+        &mut self,
+        cmd: u8,
+        buf: &mut [u8],
+    ) -> Result<(), SpiInterfaceError<Spi, I, O>> {
+        self.command(cmd).await?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        self.set_cs(true)?;
+        self.dc_pin.set_high().map_err(Error::Pin)?;
+        self.spi.read(buf).await.map_err(Error::Spi)?;
+        self.set_cs(false)?;
+        Ok(())
+    }
+
+    pub fn set_power(&mut self, on: bool) -> Result<(), SpiInterfaceError<Spi, I, O>> {
+        if on {
+            self.pwr_pin.set_high().map_err(Error::Pin)?;
+        } else {
+            self.pwr_pin.set_low().map_err(Error::Pin)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+//! A packed bit-plane framebuffer.
+//!
+//! Pixels are packed MSB-first, 8 pixels per byte, each row is padded to whole bytes.
+//! Colors with more than one bit are split across planes, bit `i` of a pixel lives in plane `i`,
+//! so every plane can be sent to the controller as is.
+
+use std::{convert::Infallible, fmt::Debug, marker::PhantomData};
+
+use embedded_graphics_core::{
+    draw_target::DrawTarget,
+    geometry::{Dimensions, OriginDimensions, Point, Size},
+    image::GetPixel,
+    pixelcolor::{BinaryColor, Gray2, GrayColor, PixelColor},
+    primitives::{PointsIter, Rectangle},
+    Pixel,
+};
+
+/// Colors that can be stored in a [`BitPlaneBuffer`].
+pub trait PlaneColor: PixelColor {
+    /// Number of planes, one per bit.
+    const PLANES: usize;
+
+    /// Bit `i` is stored in plane `i`.
+    fn to_bits(self) -> u8;
+
+    fn from_bits(bits: u8) -> Self;
+}
+
+impl PlaneColor for BinaryColor {
+    const PLANES: usize = 1;
+
+    fn to_bits(self) -> u8 {
+        self.is_on() as u8
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        (bits & 1 != 0).into()
+    }
+}
+
+impl PlaneColor for Gray2 {
+    const PLANES: usize = 2;
+
+    fn to_bits(self) -> u8 {
+        self.luma()
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Gray2::new(bits & 0b11)
+    }
+}
+
+/// A framebuffer of `C::PLANES` packed bit planes.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BitPlaneBuffer<C> {
+    size: Size,
+    row_bytes: usize,
+    planes: Vec<Box<[u8]>>,
+    color: PhantomData<C>,
+}
+
+impl<C> Debug for BitPlaneBuffer<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BitPlaneBuffer")
+            .field("size", &self.size)
+            .field("planes", &self.planes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: PlaneColor> BitPlaneBuffer<C> {
+    /// Create a buffer filled with `color`.
+    pub fn new(size: Size, color: C) -> Self {
+        let row_bytes = (size.width as usize).div_ceil(8);
+        let bits = color.to_bits();
+        let planes = (0..C::PLANES)
+            .map(|i| vec![fill_byte(bits >> i & 1 != 0); row_bytes * size.height as usize].into())
+            .collect();
+        Self {
+            size,
+            row_bytes,
+            planes,
+            color: PhantomData,
+        }
+    }
+
+    /// Bytes per row, including padding.
+    pub fn row_bytes(&self) -> usize {
+        self.row_bytes
+    }
+
+    pub fn plane(&self, plane: usize) -> &[u8] {
+        &self.planes[plane]
+    }
+
+    pub fn plane_mut(&mut self, plane: usize) -> &mut [u8] {
+        &mut self.planes[plane]
+    }
+
+    fn contains(&self, Point { x, y }: Point) -> bool {
+        x >= 0 && y >= 0 && (x as u32) < self.size.width && (y as u32) < self.size.height
+    }
+
+    /// Byte index and bit mask of `point`, which must be inside the buffer.
+    fn locate(&self, Point { x, y }: Point) -> (usize, u8) {
+        let index = y as usize * self.row_bytes + x as usize / 8;
+        (index, 0x80 >> (x % 8))
+    }
+
+    pub fn get_bit(&self, plane: usize, point: Point) -> Option<bool> {
+        if !self.contains(point) {
+            return None;
+        }
+        let (index, mask) = self.locate(point);
+        Some(self.planes[plane][index] & mask != 0)
+    }
+
+    /// Set a single bit, out of bounds points are ignored.
+    pub fn set_bit(&mut self, plane: usize, point: Point, bit: bool) {
+        if !self.contains(point) {
+            return;
+        }
+        let (index, mask) = self.locate(point);
+        set_masked(&mut self.planes[plane][index], mask, bit);
+    }
+
+    pub fn get(&self, point: Point) -> Option<C> {
+        if !self.contains(point) {
+            return None;
+        }
+        let (index, mask) = self.locate(point);
+        let bits = self.planes.iter().enumerate().fold(0, |bits, (i, plane)| {
+            bits | (((plane[index] & mask != 0) as u8) << i)
+        });
+        Some(C::from_bits(bits))
+    }
+
+    /// Set a single pixel, out of bounds points are ignored.
+    pub fn set(&mut self, point: Point, color: C) {
+        if !self.contains(point) {
+            return;
+        }
+        let (index, mask) = self.locate(point);
+        let bits = color.to_bits();
+        for (i, plane) in self.planes.iter_mut().enumerate() {
+            set_masked(&mut plane[index], mask, bits >> i & 1 != 0);
+        }
+    }
+
+    /// The bytes of a single plane covering `area`, one slice per row.
+    ///
+    /// `area` is clipped to the buffer and widened to whole bytes.
+    pub fn rows(&self, plane: usize, area: &Rectangle) -> impl Iterator<Item = &[u8]> + '_ {
+        let area = area.intersection(&self.bounding_box());
+        let bytes = match area.bottom_right() {
+            Some(bottom_right) => area.top_left.x as usize / 8..bottom_right.x as usize / 8 + 1,
+            None => 0..0,
+        };
+        let row_bytes = self.row_bytes;
+        area.rows().map(move |y| {
+            let row = &self.planes[plane][y as usize * row_bytes..][..row_bytes];
+            &row[bytes.clone()]
+        })
+    }
+
+    /// Fill `area` of a single plane with `bit`, clipped to the buffer.
+    pub fn fill_plane(&mut self, plane: usize, area: &Rectangle, bit: bool) {
+        let area = area.intersection(&self.bounding_box());
+        let Some(bottom_right) = area.bottom_right() else {
+            return;
+        };
+        let (x0, x1) = (area.top_left.x as usize, bottom_right.x as usize);
+        let (first, last) = (x0 / 8, x1 / 8);
+        let first_mask = 0xff >> (x0 % 8);
+        let last_mask = 0xff << (7 - x1 % 8);
+        let row_bytes = self.row_bytes;
+
+        for y in area.rows() {
+            let row = &mut self.planes[plane][y as usize * row_bytes..][..row_bytes];
+            if first == last {
+                set_masked(&mut row[first], first_mask & last_mask, bit);
+                continue;
+            }
+            set_masked(&mut row[first], first_mask, bit);
+            row[first + 1..last].fill(fill_byte(bit));
+            set_masked(&mut row[last], last_mask, bit);
+        }
+    }
+}
+
+fn fill_byte(bit: bool) -> u8 {
+    if bit {
+        0xff
+    } else {
+        0x00
+    }
+}
+
+fn set_masked(value: &mut u8, mask: u8, bit: bool) {
+    if bit {
+        *value |= mask;
+    } else {
+        *value &= !mask;
+    }
+}
+
+impl<C> OriginDimensions for BitPlaneBuffer<C> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl<C: PlaneColor> GetPixel for BitPlaneBuffer<C> {
+    type Color = C;
+
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        self.get(p)
+    }
+}
+
+impl<C: PlaneColor> DrawTarget for BitPlaneBuffer<C> {
+    type Color = C;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            self.set(point, color);
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let clipped = area.intersection(&self.bounding_box());
+        if clipped == *area {
+            // no bounds checks needed
+            for (point, color) in area.points().zip(colors) {
+                let (index, mask) = self.locate(point);
+                let bits = color.to_bits();
+                for (i, plane) in self.planes.iter_mut().enumerate() {
+                    set_masked(&mut plane[index], mask, bits >> i & 1 != 0);
+                }
+            }
+            return Ok(());
+        }
+        self.draw_iter(
+            area.points()
+                .zip(colors)
+                .map(|(point, color)| Pixel(point, color)),
+        )
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let bits = color.to_bits();
+        for i in 0..C::PLANES {
+            self.fill_plane(i, area, bits >> i & 1 != 0);
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        let bits = color.to_bits();
+        for (i, plane) in self.planes.iter_mut().enumerate() {
+            plane.fill(fill_byte(bits >> i & 1 != 0));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_msb_first() {
+        // 10 pixels wide, padded to 2 bytes
+        let mut buf = BitPlaneBuffer::new(Size::new(10, 2), BinaryColor::Off);
+        assert_eq!(buf.row_bytes(), 2);
+        assert_eq!(buf.plane(0).len(), 4);
+
+        buf.set(Point::new(0, 0), BinaryColor::On);
+        buf.set(Point::new(9, 1), BinaryColor::On);
+        assert_eq!(buf.plane(0), [0x80, 0x00, 0x00, 0x40]);
+        assert_eq!(buf.get(Point::new(9, 1)), Some(BinaryColor::On));
+        assert_eq!(buf.get(Point::new(8, 1)), Some(BinaryColor::Off));
+        assert_eq!(buf.get_bit(0, Point::new(0, 0)), Some(true));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut buf = BitPlaneBuffer::new(Size::new(10, 2), BinaryColor::Off);
+        for point in [Point::new(-1, 0), Point::new(10, 0), Point::new(0, 2)] {
+            buf.set(point, BinaryColor::On);
+            buf.set_bit(0, point, true);
+            assert_eq!(buf.get(point), None);
+            assert_eq!(buf.get_bit(0, point), None);
+        }
+        // the padding bits stay untouched
+        assert!(buf.plane(0).iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn gray_planes() {
+        let mut buf = BitPlaneBuffer::new(Size::new(8, 1), Gray2::WHITE);
+        assert_eq!(buf.plane(0), [0xff]);
+        assert_eq!(buf.plane(1), [0xff]);
+
+        buf.set(Point::new(0, 0), Gray2::new(0b01));
+        buf.set(Point::new(1, 0), Gray2::new(0b10));
+        buf.set(Point::new(2, 0), Gray2::BLACK);
+        assert_eq!(buf.plane(0), [0b1001_1111]);
+        assert_eq!(buf.plane(1), [0b0101_1111]);
+        for (x, luma) in [(0, 0b01), (1, 0b10), (2, 0b00), (3, 0b11)] {
+            assert_eq!(buf.get(Point::new(x, 0)), Some(Gray2::new(luma)));
+        }
+    }
+
+    #[test]
+    fn fill_solid_partial_bytes() {
+        let mut buf = BitPlaneBuffer::new(Size::new(24, 2), BinaryColor::Off);
+        let area = Rectangle::new(Point::new(3, 1), Size::new(15, 1));
+        buf.fill_solid(&area, BinaryColor::On).unwrap();
+        assert_eq!(buf.plane(0), [0x00, 0x00, 0x00, 0x1f, 0xff, 0xc0]);
+
+        // within a single byte, clipped to the buffer
+        let mut buf = BitPlaneBuffer::new(Size::new(6, 1), BinaryColor::On);
+        let area = Rectangle::new(Point::new(2, -1), Size::new(10, 3));
+        buf.fill_solid(&area, BinaryColor::Off).unwrap();
+        assert_eq!(buf.plane(0), [0xc3]);
+    }
+
+    #[test]
+    fn fill_contiguous() {
+        let colors = [Gray2::BLACK, Gray2::new(1), Gray2::new(2), Gray2::WHITE];
+        let area = Rectangle::new(Point::new(6, 0), Size::new(2, 2));
+
+        let mut buf = BitPlaneBuffer::new(Size::new(16, 2), Gray2::BLACK);
+        buf.fill_contiguous(&area, colors).unwrap();
+        let mut expected = BitPlaneBuffer::new(Size::new(16, 2), Gray2::BLACK);
+        expected
+            .draw_iter(area.points().zip(colors).map(|(p, c)| Pixel(p, c)))
+            .unwrap();
+        assert_eq!(buf, expected);
+        assert_eq!(buf.get(Point::new(7, 1)), Some(Gray2::WHITE));
+
+        // partly outside, only the visible pixels are drawn in order
+        let mut buf = BitPlaneBuffer::new(Size::new(8, 1), Gray2::BLACK);
+        buf.fill_contiguous(&area, colors).unwrap();
+        assert_eq!(buf.get(Point::new(6, 0)), Some(Gray2::BLACK));
+        assert_eq!(buf.get(Point::new(7, 0)), Some(Gray2::new(1)));
+    }
+
+    #[test]
+    fn rows() {
+        let mut buf = BitPlaneBuffer::new(Size::new(24, 3), BinaryColor::Off);
+        buf.fill_solid(&buf.bounding_box(), BinaryColor::On)
+            .unwrap();
+        buf.set(Point::new(9, 1), BinaryColor::Off);
+        let area = Rectangle::new(Point::new(9, 1), Size::new(20, 5));
+        let rows: Vec<&[u8]> = buf.rows(0, &area).collect();
+        assert_eq!(rows, [&[0xbf, 0xff][..], &[0xff, 0xff]]);
+    }
+}
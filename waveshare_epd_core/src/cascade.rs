@@ -0,0 +1,388 @@
+//! Panels driven by several cascaded controllers.
+//!
+//! Large panels like the epd5in79 are made of controllers side by side, each with its own RAM
+//! and its own command set (usually the master commands with a prefix bit set).
+//! Neighbouring controllers may drive some columns twice, [`Cascade`] defines once how pixels
+//! and areas are mapped across such a seam:
+//! - writes go to every controller covering the column, so the RAMs never disagree,
+//! - reads come from the last controller covering the column.
+//!
+//! # Examples
+//! ```
+//! # use embedded_graphics_core::{geometry::{Point, Size}, primitives::Rectangle};
+//! # use waveshare_epd_core::cascade::{Cascade, ControllerRegion};
+//! const PANEL: Cascade = Cascade::new(
+//!     272,
+//!     &[
+//!         ControllerRegion::new(0, 400, 0x00),
+//!         ControllerRegion::new(392, 400, 0x80),
+//!     ],
+//! );
+//! assert_eq!(PANEL.size(), Size::new(792, 272));
+//!
+//! // the 8 columns in the middle are driven by both controllers
+//! let targets: Vec<_> = PANEL.map(Point::new(395, 0)).collect();
+//! assert_eq!(targets, [(0, Point::new(395, 0)), (1, Point::new(3, 0))]);
+//! assert_eq!(PANEL.locate(Point::new(395, 0)), Some((1, Point::new(3, 0))));
+//! assert_eq!(PANEL.locate(Point::new(391, 0)), Some((0, Point::new(391, 0))));
+//!
+//! // 0x24 of the slave is 0xa4
+//! assert_eq!(PANEL.command(1, 0x24), 0xa4);
+//!
+//! let areas: Vec<_> = PANEL.split(&Rectangle::new(Point::new(380, 0), Size::new(20, 1))).collect();
+//! assert_eq!(
+//!     areas,
+//!     [
+//!         (0, Rectangle::new(Point::new(380, 0), Size::new(20, 1))),
+//!         (1, Rectangle::new(Point::new(0, 0), Size::new(8, 1))),
+//!     ]
+//! );
+//! ```
+
+use std::convert::Infallible;
+
+use embedded_graphics_core::{
+    draw_target::DrawTarget,
+    geometry::{OriginDimensions, Point, Size},
+    image::GetPixel,
+    primitives::Rectangle,
+    Pixel,
+};
+
+use crate::bit_plane::{BitPlaneBuffer, PlaneColor};
+
+/// The columns driven by one controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControllerRegion {
+    /// The first panel column of the controller.
+    pub x_offset: i32,
+    /// Width of the controller RAM in pixels.
+    pub width: u32,
+    /// OR-ed into every command sent to this controller, `0x00` for the master.
+    pub command_prefix: u8,
+}
+
+impl ControllerRegion {
+    pub const fn new(x_offset: i32, width: u32, command_prefix: u8) -> Self {
+        Self {
+            x_offset,
+            width,
+            command_prefix,
+        }
+    }
+
+    fn covers(&self, x: i32) -> bool {
+        x >= self.x_offset && x < self.x_offset + self.width as i32
+    }
+
+    fn local(&self, point: Point) -> Point {
+        point - Point::new(self.x_offset, 0)
+    }
+}
+
+/// A panel made of controller regions side by side, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cascade {
+    height: u32,
+    regions: &'static [ControllerRegion],
+}
+
+impl Cascade {
+    /// `regions` must be ordered by `x_offset`.
+    pub const fn new(height: u32, regions: &'static [ControllerRegion]) -> Self {
+        Self { height, regions }
+    }
+
+    pub fn regions(&self) -> &'static [ControllerRegion] {
+        self.regions
+    }
+
+    /// Size of the whole panel.
+    pub fn size(&self) -> Size {
+        let width = self
+            .regions
+            .iter()
+            .map(|r| (r.x_offset + r.width as i32).max(0) as u32)
+            .max()
+            .unwrap_or(0);
+        Size::new(width, self.height)
+    }
+
+    /// The command `cmd` for controller `region`.
+    pub fn command(&self, region: usize, cmd: u8) -> u8 {
+        cmd | self.regions[region].command_prefix
+    }
+
+    fn contains_row(&self, y: i32) -> bool {
+        y >= 0 && (y as u32) < self.height
+    }
+
+    /// Every controller that drives `point`, with the point in controller coordinates.
+    pub fn map(&self, point: Point) -> impl Iterator<Item = (usize, Point)> + '_ {
+        let in_rows = self.contains_row(point.y);
+        self.regions
+            .iter()
+            .enumerate()
+            .filter(move |(_, r)| in_rows && r.covers(point.x))
+            .map(move |(i, r)| (i, r.local(point)))
+    }
+
+    /// The controller `point` is read from, with the point in controller coordinates.
+    pub fn locate(&self, point: Point) -> Option<(usize, Point)> {
+        self.map(point).last()
+    }
+
+    /// Split `area` into the parts driven by each controller, in controller coordinates.
+    ///
+    /// Empty parts are skipped.
+    pub fn split(&self, area: &Rectangle) -> impl Iterator<Item = (usize, Rectangle)> + '_ {
+        let area = *area;
+        self.regions.iter().enumerate().filter_map(move |(i, r)| {
+            let region = Rectangle::new(Point::new(r.x_offset, 0), Size::new(r.width, self.height));
+            let part = area.intersection(&region);
+            if part.is_zero_sized() {
+                return None;
+            }
+            Some((i, Rectangle::new(r.local(part.top_left), part.size)))
+        })
+    }
+}
+
+/// One [`BitPlaneBuffer`] per controller of a [`Cascade`], addressed in panel coordinates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CascadeBuffer<C> {
+    cascade: Cascade,
+    buffers: Vec<BitPlaneBuffer<C>>,
+}
+
+impl<C: PlaneColor> CascadeBuffer<C> {
+    /// Create the buffers filled with `color`.
+    pub fn new(cascade: Cascade, color: C) -> Self {
+        let buffers = cascade
+            .regions()
+            .iter()
+            .map(|r| BitPlaneBuffer::new(Size::new(r.width, cascade.height), color))
+            .collect();
+        Self { cascade, buffers }
+    }
+
+    pub fn cascade(&self) -> &Cascade {
+        &self.cascade
+    }
+
+    pub fn buffer(&self, region: usize) -> &BitPlaneBuffer<C> {
+        &self.buffers[region]
+    }
+
+    pub fn buffer_mut(&mut self, region: usize) -> &mut BitPlaneBuffer<C> {
+        &mut self.buffers[region]
+    }
+
+    pub fn buffers_mut(&mut self) -> impl Iterator<Item = &mut BitPlaneBuffer<C>> {
+        self.buffers.iter_mut()
+    }
+
+    pub fn get_bit(&self, plane: usize, point: Point) -> Option<bool> {
+        let (region, local) = self.cascade.locate(point)?;
+        self.buffers[region].get_bit(plane, local)
+    }
+
+    /// Set a single bit, out of bounds points are ignored.
+    pub fn set_bit(&mut self, plane: usize, point: Point, bit: bool) {
+        for (region, local) in self.cascade.map(point) {
+            self.buffers[region].set_bit(plane, local, bit);
+        }
+    }
+
+    pub fn get(&self, point: Point) -> Option<C> {
+        let (region, local) = self.cascade.locate(point)?;
+        self.buffers[region].get(local)
+    }
+
+    /// Set a single pixel, out of bounds points are ignored.
+    pub fn set(&mut self, point: Point, color: C) {
+        for (region, local) in self.cascade.map(point) {
+            self.buffers[region].set(local, color);
+        }
+    }
+
+    /// Fill `area` of a single plane with `bit`, clipped to the panel.
+    pub fn fill_plane(&mut self, plane: usize, area: &Rectangle, bit: bool) {
+        for (region, local) in self.cascade.split(area) {
+            self.buffers[region].fill_plane(plane, &local, bit);
+        }
+    }
+}
+
+impl<C> OriginDimensions for CascadeBuffer<C> {
+    fn size(&self) -> Size {
+        self.cascade.size()
+    }
+}
+
+impl<C: PlaneColor> GetPixel for CascadeBuffer<C> {
+    type Color = C;
+
+    fn pixel(&self, p: Point) -> Option<Self::Color> {
+        self.get(p)
+    }
+}
+
+impl<C: PlaneColor> DrawTarget for CascadeBuffer<C> {
+    type Color = C;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            self.set(point, color);
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        for (region, local) in self.cascade.split(area) {
+            self.buffers[region].fill_solid(&local, color)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        for buffer in &mut self.buffers {
+            buffer.clear(color)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics_core::pixelcolor::BinaryColor;
+
+    use super::*;
+
+    const PANEL: Cascade = Cascade::new(
+        4,
+        &[
+            ControllerRegion::new(0, 400, 0x00),
+            ControllerRegion::new(392, 400, 0x80),
+        ],
+    );
+
+    #[test]
+    fn region_bounds() {
+        let region = PANEL.regions()[1];
+        assert!(!region.covers(391));
+        assert!(region.covers(392));
+        assert!(region.covers(791));
+        assert!(!region.covers(792));
+        assert_eq!(region.local(Point::new(400, 2)), Point::new(8, 2));
+
+        assert_eq!(PANEL.map(Point::new(792, 0)).count(), 0);
+        assert_eq!(PANEL.map(Point::new(-1, 0)).count(), 0);
+        assert_eq!(PANEL.map(Point::new(0, 4)).count(), 0);
+        assert_eq!(PANEL.locate(Point::new(399, -1)), None);
+    }
+
+    #[test]
+    fn command_prefix() {
+        assert_eq!(PANEL.command(0, 0x24), 0x24);
+        assert_eq!(PANEL.command(1, 0x24), 0xa4);
+        assert_eq!(PANEL.command(1, 0x4e), 0xce);
+    }
+
+    #[test]
+    fn split_at_the_seam() {
+        let left = Rectangle::new(Point::new(0, 0), Size::new(392, 4));
+        assert_eq!(PANEL.split(&left).collect::<Vec<_>>(), [(0, left)]);
+
+        // only the seam
+        let seam = Rectangle::new(Point::new(392, 1), Size::new(8, 2));
+        assert_eq!(
+            PANEL.split(&seam).collect::<Vec<_>>(),
+            [
+                (0, seam),
+                (1, Rectangle::new(Point::new(0, 1), Size::new(8, 2)))
+            ]
+        );
+
+        // clipped to the panel
+        let right = Rectangle::new(Point::new(700, -2), Size::new(200, 10));
+        assert_eq!(
+            PANEL.split(&right).collect::<Vec<_>>(),
+            [(1, Rectangle::new(Point::new(308, 0), Size::new(92, 4)))]
+        );
+    }
+
+    #[test]
+    fn set_on_the_seam() {
+        let mut buf = CascadeBuffer::new(PANEL, BinaryColor::Off);
+        buf.set(Point::new(392, 0), BinaryColor::On);
+        buf.set(Point::new(399, 3), BinaryColor::On);
+
+        // both controllers get the seam columns
+        assert_eq!(buf.buffer(0).get(Point::new(392, 0)), Some(BinaryColor::On));
+        assert_eq!(buf.buffer(1).get(Point::new(0, 0)), Some(BinaryColor::On));
+        assert_eq!(buf.buffer(0).get(Point::new(399, 3)), Some(BinaryColor::On));
+        assert_eq!(buf.buffer(1).get(Point::new(7, 3)), Some(BinaryColor::On));
+        assert_eq!(buf.get(Point::new(392, 0)), Some(BinaryColor::On));
+        assert_eq!(buf.get(Point::new(399, 3)), Some(BinaryColor::On));
+        assert_eq!(buf.get(Point::new(400, 3)), Some(BinaryColor::Off));
+
+        // reads come from the slave
+        buf.buffer_mut(0).set(Point::new(395, 1), BinaryColor::On);
+        assert_eq!(buf.get(Point::new(395, 1)), Some(BinaryColor::Off));
+        buf.set_bit(0, Point::new(395, 1), true);
+        assert_eq!(buf.get_bit(0, Point::new(395, 1)), Some(true));
+    }
+
+    #[test]
+    fn set_across_the_seam() {
+        let mut buf = CascadeBuffer::new(PANEL, BinaryColor::Off);
+        for x in 388..404 {
+            buf.set(Point::new(x, 2), BinaryColor::On);
+        }
+        for x in 386..406 {
+            let expected = if (388..404).contains(&x) {
+                BinaryColor::On
+            } else {
+                BinaryColor::Off
+            };
+            assert_eq!(buf.get(Point::new(x, 2)), Some(expected), "x = {x}");
+        }
+        // master keeps 388..400, slave gets 392..404
+        assert_eq!(&buf.buffer(0).plane(0)[2 * 50 + 48..3 * 50], [0x0f, 0xff]);
+        assert_eq!(&buf.buffer(1).plane(0)[2 * 50..2 * 50 + 2], [0xff, 0xf0]);
+
+        buf.set(Point::new(792, 2), BinaryColor::On);
+        buf.set(Point::new(0, -1), BinaryColor::On);
+        assert_eq!(buf.get(Point::new(792, 2)), None);
+    }
+
+    #[test]
+    fn fill_across_the_seam() {
+        let mut buf = CascadeBuffer::new(PANEL, BinaryColor::Off);
+        let area = Rectangle::new(Point::new(390, 1), Size::new(20, 2));
+        buf.fill_solid(&area, BinaryColor::On).unwrap();
+        for y in 0..4 {
+            for x in 380..420 {
+                let point = Point::new(x, y);
+                let expected = if area.contains(point) {
+                    BinaryColor::On
+                } else {
+                    BinaryColor::Off
+                };
+                assert_eq!(buf.get(point), Some(expected), "{point:?}");
+                if (392..400).contains(&x) {
+                    assert_eq!(buf.buffer(0).get(point), Some(expected), "{point:?}");
+                }
+            }
+        }
+
+        buf.fill_plane(0, &area, false);
+        assert!(buf.buffer(0).plane(0).iter().all(|&byte| byte == 0));
+        assert!(buf.buffer(1).plane(0).iter().all(|&byte| byte == 0));
+    }
+}
@@ -0,0 +1,279 @@
+//! Reducing 8-bit luma to the few gray levels of a panel.
+//!
+//! # Examples
+//! ```
+//! # use waveshare_epd_core::dither::{dither, Dither, ErrorDiffusionKernel};
+//! // a 4x1 gradient to black and white
+//! let luma = [0, 85, 170, 255];
+//! let levels = dither(&luma, 4, 2, Dither::Threshold);
+//! assert_eq!(levels, [0, 0, 1, 1]);
+//!
+//! // mid gray becomes a pattern of black and white
+//! let levels = dither(&[128; 16], 4, 2, Dither::ErrorDiffusion(ErrorDiffusionKernel::FloydSteinberg));
+//! assert_eq!(levels.iter().filter(|&&l| l == 1).count(), 8);
+//! ```
+
+/// How to pick a level for each pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// The nearest level, flat areas stay flat, gradients band.
+    Threshold,
+    /// Spread the quantization error to the neighbouring pixels.
+    ErrorDiffusion(ErrorDiffusionKernel),
+    /// Ordered dithering with an 8x8 Bayer matrix, stable under small changes of the image.
+    Bayer,
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Self::ErrorDiffusion(ErrorDiffusionKernel::FloydSteinberg)
+    }
+}
+
+/// Error diffusion kernels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorDiffusionKernel {
+    FloydSteinberg,
+    /// Only diffuses 3/4 of the error, higher contrast, loses detail in shadows and highlights.
+    Atkinson,
+    /// Spreads the error further than Floyd–Steinberg, smoother and slower.
+    Stucki,
+}
+
+impl ErrorDiffusionKernel {
+    /// `(dx, dy, weight)` of the neighbours, and the divisor of the weights.
+    pub fn weights(&self) -> (&'static [(i32, i32, u8)], u8) {
+        match self {
+            Self::FloydSteinberg => (&[(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)], 16),
+            Self::Atkinson => (
+                &[
+                    (1, 0, 1),
+                    (2, 0, 1),
+                    (-1, 1, 1),
+                    (0, 1, 1),
+                    (1, 1, 1),
+                    (0, 2, 1),
+                ],
+                8,
+            ),
+            Self::Stucki => (
+                &[
+                    (1, 0, 8),
+                    (2, 0, 4),
+                    (-2, 1, 2),
+                    (-1, 1, 4),
+                    (0, 1, 8),
+                    (1, 1, 4),
+                    (2, 1, 2),
+                    (-2, 2, 1),
+                    (-1, 2, 2),
+                    (0, 2, 4),
+                    (1, 2, 2),
+                    (2, 2, 1),
+                ],
+                42,
+            ),
+        }
+    }
+}
+
+#[rustfmt::skip]
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Reduce `luma` (row-major, `width` pixels per row) to `levels` evenly spaced levels.
+///
+/// Returns one level in `0..levels` per pixel, `0` is black.
+///
+/// # Panics
+/// If `levels < 2`, or if `luma` is not empty and not made of whole rows of `width` pixels.
+pub fn dither(luma: &[u8], width: usize, levels: u8, dither: Dither) -> Vec<u8> {
+    assert!(levels >= 2, "at least 2 levels are needed");
+    if luma.is_empty() {
+        return Vec::new();
+    }
+    assert!(
+        width > 0 && luma.len() % width == 0,
+        "{} pixels are not whole rows of {} pixels",
+        luma.len(),
+        width
+    );
+    let step = 255.0 / (levels - 1) as f32;
+    let nearest = |value: f32| (value / step).round().clamp(0.0, (levels - 1) as f32) as u8;
+
+    match dither {
+        Dither::Threshold => luma.iter().map(|&v| nearest(v as f32)).collect(),
+        Dither::Bayer => luma
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let (x, y) = (i % width, i / width);
+                let offset = (BAYER_8X8[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5;
+                nearest(v as f32 + offset * step)
+            })
+            .collect(),
+        Dither::ErrorDiffusion(kernel) => {
+            let (weights, divisor) = kernel.weights();
+            let mut values: Vec<f32> = luma.iter().map(|&v| v as f32).collect();
+            let mut out = Vec::with_capacity(luma.len());
+            for i in 0..values.len() {
+                let level = nearest(values[i]);
+                let error = values[i] - level as f32 * step;
+                out.push(level);
+
+                let (x, y) = ((i % width) as i32, (i / width) as i32);
+                for &(dx, dy, weight) in weights {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || nx >= width as i32 {
+                        continue;
+                    }
+                    // rows below the image are out of bounds too
+                    if let Some(v) = values.get_mut(ny as usize * width + nx as usize) {
+                        *v += error * weight as f32 / divisor as f32;
+                    }
+                }
+            }
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KERNELS: [ErrorDiffusionKernel; 3] = [
+        ErrorDiffusionKernel::FloydSteinberg,
+        ErrorDiffusionKernel::Atkinson,
+        ErrorDiffusionKernel::Stucki,
+    ];
+
+    /// The mean level of `levels` scaled back to luma.
+    fn mean_luma(levels: &[u8], max: u8) -> f32 {
+        let sum: u32 = levels.iter().map(|&l| l as u32).sum();
+        sum as f32 / levels.len() as f32 * 255.0 / max as f32
+    }
+
+    #[test]
+    fn threshold() {
+        let luma = [0, 42, 43, 127, 128, 212, 213, 255];
+        assert_eq!(
+            dither(&luma, 8, 4, Dither::Threshold),
+            [0, 0, 1, 1, 2, 2, 3, 3]
+        );
+        assert_eq!(
+            dither(&luma, 4, 2, Dither::Threshold),
+            [0, 0, 0, 0, 1, 1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn weights_sum_to_divisor() {
+        for kernel in KERNELS {
+            let (weights, divisor) = kernel.weights();
+            let sum: u32 = weights.iter().map(|&(_, _, w)| w as u32).sum();
+            let expected = match kernel {
+                // only 3/4 of the error is diffused
+                ErrorDiffusionKernel::Atkinson => 6,
+                _ => divisor as u32,
+            };
+            assert_eq!(sum, expected, "{kernel:?}");
+            // the error only goes to pixels not visited yet
+            assert!(weights.iter().all(|&(dx, dy, _)| dy > 0 || dx > 0));
+        }
+    }
+
+    #[test]
+    fn error_diffusion_keeps_mean() {
+        for kernel in KERNELS {
+            for value in [32, 64, 128, 192, 224] {
+                let levels = dither(&[value; 32 * 32], 32, 2, Dither::ErrorDiffusion(kernel));
+                let mean = mean_luma(&levels, 1);
+                if kernel == ErrorDiffusionKernel::Atkinson && value != 128 {
+                    // the lost quarter of the error pushes shadows and highlights to the extremes
+                    let pushed = (mean - value as f32) * (value as f32 - 127.5) >= 0.0;
+                    assert!(pushed, "{kernel:?} {value}: {mean}");
+                } else {
+                    // some error is lost at the edges
+                    assert!(
+                        (mean - value as f32).abs() < 8.0,
+                        "{kernel:?} {value}: {mean}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn error_diffusion_keeps_extremes() {
+        for kernel in KERNELS {
+            let mode = Dither::ErrorDiffusion(kernel);
+            assert_eq!(dither(&[0; 16], 4, 4, mode), [0; 16]);
+            assert_eq!(dither(&[255; 16], 4, 4, mode), [3; 16]);
+            // exact levels have no error to spread
+            assert_eq!(dither(&[85; 16], 4, 4, mode), [1; 16]);
+        }
+    }
+
+    #[test]
+    fn error_stays_in_row() {
+        // a single row, the error at the right edge must not leak to the next row
+        let levels = dither(
+            &[128, 128, 255, 0],
+            2,
+            2,
+            Dither::ErrorDiffusion(KERNELS[0]),
+        );
+        assert_eq!(levels[..2], [1, 0]);
+    }
+
+    #[test]
+    fn bayer() {
+        let levels = dither(&[128; 64], 8, 2, Dither::Bayer);
+        // half of the matrix is above the threshold
+        assert_eq!(levels.iter().filter(|&&l| l == 1).count(), 32);
+        // the pattern repeats every 8 pixels
+        let wide = dither(&[128; 16 * 8], 16, 2, Dither::Bayer);
+        for (y, row) in wide.chunks(16).enumerate() {
+            assert_eq!(row[..8], row[8..]);
+            assert_eq!(row[..8], levels[y * 8..][..8]);
+        }
+        assert_eq!(dither(&[0; 64], 8, 4, Dither::Bayer), [0; 64]);
+        assert_eq!(dither(&[255; 64], 8, 4, Dither::Bayer), [3; 64]);
+    }
+
+    #[test]
+    fn bayer_keeps_mean() {
+        for value in [16, 64, 100, 160, 240] {
+            let levels = dither(&[value; 64], 8, 4, Dither::Bayer);
+            let mean = mean_luma(&levels, 3);
+            assert!((mean - value as f32).abs() < 4.0, "{value}: {mean}");
+        }
+    }
+
+    #[test]
+    fn empty() {
+        assert!(dither(&[], 0, 2, Dither::Bayer).is_empty());
+        assert!(dither(&[], 4, 2, Dither::default()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "whole rows")]
+    fn zero_width() {
+        dither(&[0; 4], 0, 2, Dither::Bayer);
+    }
+
+    #[test]
+    #[should_panic(expected = "whole rows")]
+    fn partial_row() {
+        dither(&[0; 5], 4, 2, Dither::Threshold);
+    }
+}
@@ -0,0 +1,61 @@
+//! A common trait for all panel drivers.
+//!
+//! Application code written against [`Epd`] runs on every panel that supports its color.
+
+use std::convert::Infallible;
+
+use embedded_graphics_core::{draw_target::DrawTarget, geometry::Size, image::GetPixel};
+
+/// How the panel updates the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RefreshMode {
+    /// Full refresh, the screen flashes, no ghosting.
+    Full,
+    /// Faster full refresh, the screen flashes.
+    Fast,
+    /// Only changed pixels are updated, no flashing, ghosting accumulates.
+    Partial,
+}
+
+/// A panel driving pixels of color `C`.
+///
+/// A driver which supports several colors implements this trait once per color.
+pub trait Epd<C> {
+    type Error;
+
+    /// The framebuffer, drawing on it does not update the panel until [`Epd::display`].
+    type Frame<'a>: DrawTarget<Color = C, Error = Infallible> + GetPixel<Color = C>
+    where
+        Self: 'a;
+
+    /// Size of the visible area in pixels.
+    fn size(&self) -> Size;
+
+    /// Refresh modes supported with color `C`.
+    fn refresh_modes(&self) -> &'static [RefreshMode];
+
+    fn supports(&self, mode: RefreshMode) -> bool {
+        self.refresh_modes().contains(&mode)
+    }
+
+    fn frame(&mut self) -> Self::Frame<'_>;
+
+    /// Fill the framebuffer with `color`, the panel is not updated.
+    fn clear(&mut self, color: C) {
+        match self.frame().clear(color) {
+            Ok(()) => {}
+            Err(e) => match e {},
+        }
+    }
+
+    /// Show the framebuffer on the panel.
+    fn display(&mut self, mode: RefreshMode) -> Result<(), Self::Error>;
+
+    /// Enter deep sleep mode.
+    fn sleep(&mut self) -> Result<(), Self::Error>;
+
+    /// Leave deep sleep mode, [`Epd::display`] also wakes the panel if needed.
+    fn wake(&mut self) -> Result<(), Self::Error>;
+
+    fn is_sleeping(&self) -> bool;
+}
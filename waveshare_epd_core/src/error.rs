@@ -1,9 +1,52 @@
 use std::time::Duration;
 
+use crate::epd::RefreshMode;
+
+/// The error type shared by all drivers.
+///
+/// `SpiE`, `PinE` and `BusyE` are the error types of the underlying SPI device,
+/// output pins and busy pin.
 #[derive(Debug, thiserror::Error)]
-pub enum Error {
+pub enum Error<SpiE, PinE, BusyE = PinE> {
+    /// The SPI device failed.
+    #[error("spi error")]
+    Spi(#[source] SpiE),
+    /// A GPIO pin failed.
+    #[error("gpio error")]
+    Pin(#[source] PinE),
+    /// The busy pin could not be read.
+    #[error("busy pin error")]
+    BusyPin(#[source] BusyE),
+    /// The busy pin did not go idle in time.
     #[error(transparent)]
     TimeOut(#[from] TimeOutError),
+    /// A blocking driver was given a bus that suspends, use the async driver instead.
+    #[error("the bus suspended a blocking operation")]
+    Suspended,
+    /// The panel must be woken up before this operation.
+    #[error("epd is in deep sleep mode")]
+    DeepSleep,
+    /// The SPI device or GPIO chip could not be opened or configured.
+    #[error("failed to open device")]
+    Open(#[source] Box<dyn std::error::Error + Send + Sync + 'static>),
+    /// The refresh mode is not supported with the current color.
+    #[error("unsupported refresh mode: {0:?}")]
+    UnsupportedMode(RefreshMode),
+    /// The driver configuration is invalid.
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// The state file could not be read or written.
+    #[error("state file error")]
+    StateFile(#[source] std::io::Error),
+    /// A temperature that cannot be real, usually a sensor reading on a bus that cannot read.
+    #[error("implausible temperature reading: {0} °C")]
+    ImplausibleTemperature(f32),
+}
+
+impl<SpiE, PinE, BusyE> Error<SpiE, PinE, BusyE> {
+    pub fn open(err: impl Into<Box<dyn std::error::Error + Send + Sync + 'static>>) -> Self {
+        Self::Open(err.into())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -12,3 +55,34 @@ pub struct TimeOutError {
     pub timeout: Duration,
     pub elapsed: Duration,
 }
+
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("invalid config `{}`: {}", self.field, self.reason)]
+pub struct ConfigError {
+    pub field: &'static str,
+    pub reason: String,
+}
+
+impl ConfigError {
+    pub fn new(field: &'static str, reason: impl Into<String>) -> Self {
+        Self {
+            field,
+            reason: reason.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, error::Error as _, io};
+
+    use super::*;
+
+    #[test]
+    fn busy_pin_source() {
+        let err: Error<Infallible, Infallible, io::Error> =
+            Error::BusyPin(io::Error::other("line gone"));
+        let source = err.source().expect("source of the busy pin error");
+        assert_eq!(source.to_string(), "line gone");
+    }
+}
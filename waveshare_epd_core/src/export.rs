@@ -0,0 +1,198 @@
+//! Writing a framebuffer to an image file, to inspect it without a panel.
+//!
+//! PBM and PGM need no dependencies, PNG needs the `png` feature.
+//!
+//! # Examples
+//! ```
+//! # use embedded_graphics_core::{geometry::Size, pixelcolor::BinaryColor};
+//! # use waveshare_epd_core::{bit_plane::BitPlaneBuffer, export::{encode, ExportFormat}};
+//! let buffer = BitPlaneBuffer::new(Size::new(8, 1), BinaryColor::Off);
+//! // PBM uses 1 for black
+//! assert_eq!(encode(&buffer, ExportFormat::Pbm).unwrap(), b"P4\n8 1\n\xff");
+//! ```
+
+use std::{fs, io, path::Path};
+
+use embedded_graphics_core::{
+    geometry::{OriginDimensions, Point, Size},
+    image::GetPixel,
+    primitives::{PointsIter, Rectangle},
+};
+
+use crate::bit_plane::PlaneColor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Binary PBM (`P4`), grays are thresholded.
+    Pbm,
+    /// Binary PGM (`P5`), 8 bits per pixel.
+    Pgm,
+    /// 8-bit grayscale PNG.
+    #[cfg(feature = "png")]
+    Png,
+}
+
+impl ExportFormat {
+    /// The usual file extension.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Pbm => "pbm",
+            Self::Pgm => "pgm",
+            #[cfg(feature = "png")]
+            Self::Png => "png",
+        }
+    }
+}
+
+/// Encode `image` in `format`.
+pub fn encode<I>(image: &I, format: ExportFormat) -> io::Result<Vec<u8>>
+where
+    I: GetPixel + OriginDimensions,
+    I::Color: PlaneColor,
+{
+    encode_luma(&luma(image), image.size(), format)
+}
+
+/// Encode 8-bit `luma` (row-major, `size.width` pixels per row) in `format`.
+pub fn encode_luma(luma: &[u8], size: Size, format: ExportFormat) -> io::Result<Vec<u8>> {
+    if luma.len() != size.width as usize * size.height as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "luma does not match the size",
+        ));
+    }
+    let out = match format {
+        ExportFormat::Pbm => {
+            let mut out = format!("P4\n{} {}\n", size.width, size.height).into_bytes();
+            for row in luma.chunks(size.width.max(1) as usize) {
+                for byte in row.chunks(8) {
+                    let bits = byte
+                        .iter()
+                        .enumerate()
+                        .fold(0u8, |bits, (i, &v)| bits | (((v < 0x80) as u8) << (7 - i)));
+                    out.push(bits);
+                }
+            }
+            out
+        }
+        ExportFormat::Pgm => {
+            let mut out = format!("P5\n{} {}\n255\n", size.width, size.height).into_bytes();
+            out.extend_from_slice(luma);
+            out
+        }
+        #[cfg(feature = "png")]
+        ExportFormat::Png => {
+            let mut out = Vec::new();
+            let mut encoder = png::Encoder::new(&mut out, size.width, size.height);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .write_header()
+                .and_then(|mut writer| writer.write_image_data(luma))
+                .map_err(io::Error::other)?;
+            out
+        }
+    };
+    Ok(out)
+}
+
+/// Encode `image` in `format` and write it to `path`.
+pub fn write<I>(image: &I, format: ExportFormat, path: impl AsRef<Path>) -> io::Result<()>
+where
+    I: GetPixel + OriginDimensions,
+    I::Color: PlaneColor,
+{
+    fs::write(path, encode(image, format)?)
+}
+
+/// 8-bit luma of every pixel, row-major.
+fn luma<I>(image: &I) -> Vec<u8>
+where
+    I: GetPixel + OriginDimensions,
+    I::Color: PlaneColor,
+{
+    let max = (1u32 << <I::Color as PlaneColor>::PLANES) - 1;
+    Rectangle::new(Point::zero(), image.size())
+        .points()
+        .map(|point| {
+            let level = image.pixel(point).map_or(0, |color| color.to_bits() as u32);
+            (level * 255 / max) as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics_core::pixelcolor::{BinaryColor, Gray2, GrayColor};
+
+    use super::*;
+    use crate::bit_plane::BitPlaneBuffer;
+
+    #[test]
+    fn pbm_padded_rows() {
+        // 10 pixels per row, every row is padded to 2 bytes
+        let mut buffer = BitPlaneBuffer::new(Size::new(10, 2), BinaryColor::On);
+        buffer.set(Point::new(0, 0), BinaryColor::Off);
+        buffer.set(Point::new(9, 1), BinaryColor::Off);
+        let mut expected = b"P4\n10 2\n".to_vec();
+        expected.extend_from_slice(&[0x80, 0x00, 0x00, 0x40]);
+        assert_eq!(encode(&buffer, ExportFormat::Pbm).unwrap(), expected);
+    }
+
+    #[test]
+    fn pbm_thresholds_grays() {
+        let luma = [0x00, 0x55, 0x7f, 0x80, 0xaa, 0xff, 0x00, 0x00];
+        let pbm = encode_luma(&luma, Size::new(8, 1), ExportFormat::Pbm).unwrap();
+        assert_eq!(pbm, b"P4\n8 1\n\xe3");
+    }
+
+    #[test]
+    fn pgm_levels() {
+        let mut buffer = BitPlaneBuffer::new(Size::new(2, 2), Gray2::WHITE);
+        buffer.set(Point::new(0, 0), Gray2::BLACK);
+        buffer.set(Point::new(1, 0), Gray2::new(1));
+        buffer.set(Point::new(0, 1), Gray2::new(2));
+        let mut expected = b"P5\n2 2\n255\n".to_vec();
+        expected.extend_from_slice(&[0x00, 0x55, 0xaa, 0xff]);
+        assert_eq!(encode(&buffer, ExportFormat::Pgm).unwrap(), expected);
+    }
+
+    #[test]
+    fn pgm_binary() {
+        let mut buffer = BitPlaneBuffer::new(Size::new(3, 1), BinaryColor::On);
+        buffer.set(Point::new(1, 0), BinaryColor::Off);
+        assert_eq!(
+            encode(&buffer, ExportFormat::Pgm).unwrap(),
+            b"P5\n3 1\n255\n\xff\x00\xff"
+        );
+    }
+
+    #[test]
+    fn luma_size_mismatch() {
+        let err = encode_luma(&[0; 3], Size::new(2, 2), ExportFormat::Pgm).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            encode_luma(&[], Size::zero(), ExportFormat::Pbm).unwrap(),
+            b"P4\n0 0\n"
+        );
+        assert_eq!(
+            encode_luma(&[], Size::new(0, 3), ExportFormat::Pgm).unwrap(),
+            b"P5\n0 3\n255\n"
+        );
+    }
+
+    #[test]
+    fn write_file() {
+        let path = std::env::temp_dir().join(format!("export-{}.pgm", std::process::id()));
+        let buffer = BitPlaneBuffer::new(Size::new(1, 1), Gray2::WHITE);
+        write(&buffer, ExportFormat::Pgm, &path).unwrap();
+        let written = fs::read(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(written.unwrap(), b"P5\n1 1\n255\n\xff");
+        assert_eq!(ExportFormat::Pgm.extension(), "pgm");
+    }
+}
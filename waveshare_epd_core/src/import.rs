@@ -0,0 +1,435 @@
+//! Drawing 8-bit RGB or luma images of any size on a panel.
+//!
+//! The image is converted to luma, scaled to the target by [`Fit`], gamma corrected and
+//! reduced to the levels of the color by [`Dither`].
+//!
+//! # Examples
+//! ```
+//! # use embedded_graphics_core::{geometry::Size, image::GetPixel, prelude::Point, pixelcolor::{Gray2, GrayColor}};
+//! # use waveshare_epd_core::{bit_plane::BitPlaneBuffer, dither::Dither, import::{draw_image, Fit, Image, ImportOptions}};
+//! // a horizontal gradient
+//! let data: Vec<u8> = (0..=255).collect();
+//! let image = Image::luma8(&data, 256, 1).unwrap();
+//!
+//! let mut target = BitPlaneBuffer::new(Size::new(4, 2), Gray2::WHITE);
+//! let options = ImportOptions {
+//!     fit: Fit::Stretch,
+//!     dither: Dither::Threshold,
+//!     ..ImportOptions::DEFAULT
+//! };
+//! draw_image(&mut target, &image, &options).unwrap();
+//! assert_eq!(target.pixel(Point::new(0, 1)), Some(Gray2::BLACK));
+//! assert_eq!(target.pixel(Point::new(3, 1)), Some(Gray2::WHITE));
+//!
+//! let dark = ImportOptions { gamma: 0.0, ..options };
+//! assert!(draw_image(&mut target, &image, &dark).is_err());
+//! ```
+
+use embedded_graphics_core::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    primitives::Rectangle,
+};
+
+use crate::{
+    bit_plane::PlaneColor,
+    dither::{dither, Dither},
+    error::ConfigError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// One byte per pixel.
+    Luma8,
+    /// Three bytes per pixel, red, green, blue.
+    Rgb8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Luma8 => 1,
+            Self::Rgb8 => 3,
+        }
+    }
+}
+
+/// A borrowed row-major image without padding.
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+}
+
+impl<'a> Image<'a> {
+    pub fn new(
+        data: &'a [u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<Self, ConfigError> {
+        let expected = width as usize * height as usize * format.bytes_per_pixel();
+        if data.len() != expected {
+            return Err(ConfigError::new(
+                "data",
+                format!("expected {} bytes, got {}", expected, data.len()),
+            ));
+        }
+        Ok(Self {
+            data,
+            width,
+            height,
+            format,
+        })
+    }
+
+    pub fn luma8(data: &'a [u8], width: u32, height: u32) -> Result<Self, ConfigError> {
+        Self::new(data, width, height, PixelFormat::Luma8)
+    }
+
+    pub fn rgb8(data: &'a [u8], width: u32, height: u32) -> Result<Self, ConfigError> {
+        Self::new(data, width, height, PixelFormat::Rgb8)
+    }
+
+    pub fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+
+    /// Luma of every pixel, ITU-R BT.601 weights for RGB.
+    fn luma(&self) -> Vec<u8> {
+        match self.format {
+            PixelFormat::Luma8 => self.data.to_vec(),
+            PixelFormat::Rgb8 => self
+                .data
+                .chunks_exact(3)
+                .map(|rgb| {
+                    let luma = 299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32;
+                    ((luma + 500) / 1000) as u8
+                })
+                .collect(),
+        }
+    }
+}
+
+/// How the image is scaled to the target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fit {
+    /// Scale to fit inside the target keeping the aspect ratio, centred.
+    #[default]
+    Contain,
+    /// Scale to cover the whole target keeping the aspect ratio, centred and cropped.
+    Cover,
+    /// Scale to the size of the target, ignoring the aspect ratio.
+    Stretch,
+    /// No scaling, centred and cropped.
+    Center,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImportOptions {
+    pub fit: Fit,
+    pub dither: Dither,
+    /// Applied to the normalized luma as `luma ^ gamma`, above `1.0` darkens mid tones.
+    pub gamma: f32,
+    /// Luma of the target area not covered by the image, `None` leaves it untouched.
+    pub background: Option<u8>,
+}
+
+impl ImportOptions {
+    pub const DEFAULT: Self = Self {
+        fit: Fit::Contain,
+        dither: Dither::ErrorDiffusion(crate::dither::ErrorDiffusionKernel::FloydSteinberg),
+        gamma: 1.0,
+        background: None,
+    };
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !self.gamma.is_finite() || self.gamma <= 0.0 {
+            return Err(ConfigError::new(
+                "gamma",
+                format!("{} is not a finite positive number", self.gamma),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The error of [`draw_image`], `E` is the error of the draw target.
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError<E> {
+    /// The [`ImportOptions`] are invalid.
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// The draw target failed.
+    #[error("failed to draw the image")]
+    Draw(E),
+}
+
+/// Draw `image` on `target`, see the [module docs](self).
+pub fn draw_image<D>(
+    target: &mut D,
+    image: &Image,
+    options: &ImportOptions,
+) -> Result<(), ImportError<D::Error>>
+where
+    D: DrawTarget,
+    D::Color: PlaneColor,
+{
+    options.validate()?;
+    let bounds = target.bounding_box();
+    let placed = place(image.size(), bounds, options.fit);
+    let area = match options.background {
+        Some(_) => bounds,
+        None => placed.intersection(&bounds),
+    };
+    if area.is_zero_sized() || image.size().width == 0 || image.size().height == 0 {
+        return Ok(());
+    }
+
+    let scaled = scale(
+        &image.luma(),
+        image.size(),
+        placed,
+        area,
+        options.background.unwrap_or(0xff),
+    );
+    let corrected: Vec<u8> = if options.gamma == 1.0 {
+        scaled
+    } else {
+        let table: Vec<u8> = (0..=255)
+            .map(|v| ((v as f32 / 255.0).powf(options.gamma) * 255.0).round() as u8)
+            .collect();
+        scaled.iter().map(|&v| table[v as usize]).collect()
+    };
+
+    let levels = 1 << <D::Color as PlaneColor>::PLANES;
+    let levels = dither(&corrected, area.size.width as usize, levels, options.dither);
+    target
+        .fill_contiguous(&area, levels.into_iter().map(D::Color::from_bits))
+        .map_err(ImportError::Draw)
+}
+
+/// Where the image lands in `bounds`, may exceed them.
+fn place(image: Size, bounds: Rectangle, fit: Fit) -> Rectangle {
+    let (iw, ih) = (image.width as f32, image.height as f32);
+    let (bw, bh) = (bounds.size.width as f32, bounds.size.height as f32);
+    let size = match fit {
+        Fit::Stretch => return bounds,
+        Fit::Center => image,
+        Fit::Contain | Fit::Cover => {
+            let (sx, sy) = (bw / iw, bh / ih);
+            let s = if fit == Fit::Contain {
+                sx.min(sy)
+            } else {
+                sx.max(sy)
+            };
+            Size::new((iw * s).round() as u32, (ih * s).round() as u32)
+        }
+    };
+    let offset = Point::new(
+        (bounds.size.width as i32 - size.width as i32) / 2,
+        (bounds.size.height as i32 - size.height as i32) / 2,
+    );
+    Rectangle::new(bounds.top_left + offset, size)
+}
+
+/// Sample `area` of the image `placed` on the target, every target pixel averages
+/// the source pixels it covers, pixels outside of `placed` are `background`.
+fn scale(luma: &[u8], image: Size, placed: Rectangle, area: Rectangle, background: u8) -> Vec<u8> {
+    // source range of every target column and row, at least one pixel wide
+    let ranges = |from: i32, len: u32, offset: i32, placed_len: u32, image_len: u32| {
+        (from..from + len as i32)
+            .map(|t| {
+                let i = t - offset;
+                if i < 0 || i >= placed_len as i32 {
+                    return None;
+                }
+                let start = i as u64 * image_len as u64 / placed_len as u64;
+                let end = ((i as u64 + 1) * image_len as u64).div_ceil(placed_len as u64);
+                Some(start as usize..(end as usize).max(start as usize + 1))
+            })
+            .collect::<Vec<_>>()
+    };
+    let columns = ranges(
+        area.top_left.x,
+        area.size.width,
+        placed.top_left.x,
+        placed.size.width,
+        image.width,
+    );
+    let rows = ranges(
+        area.top_left.y,
+        area.size.height,
+        placed.top_left.y,
+        placed.size.height,
+        image.height,
+    );
+
+    let width = image.width as usize;
+    let mut out = Vec::with_capacity(columns.len() * rows.len());
+    for row in &rows {
+        for column in &columns {
+            let (Some(row), Some(column)) = (row, column) else {
+                out.push(background);
+                continue;
+            };
+            let sum: u32 = row
+                .clone()
+                .flat_map(|y| luma[y * width..][column.clone()].iter())
+                .map(|&v| v as u32)
+                .sum();
+            let count = (row.len() * column.len()) as u32;
+            out.push(((sum + count / 2) / count) as u8);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics_core::{
+        image::GetPixel,
+        pixelcolor::{BinaryColor, Gray2, GrayColor},
+    };
+
+    use super::*;
+    use crate::bit_plane::BitPlaneBuffer;
+
+    const THRESHOLD: ImportOptions = ImportOptions {
+        fit: Fit::Stretch,
+        dither: Dither::Threshold,
+        gamma: 1.0,
+        background: None,
+    };
+
+    fn bounds(width: u32, height: u32) -> Rectangle {
+        Rectangle::new(Point::new(10, 20), Size::new(width, height))
+    }
+
+    #[test]
+    fn image_size() {
+        assert!(Image::luma8(&[0; 6], 3, 2).is_ok());
+        assert!(Image::luma8(&[0; 5], 3, 2).is_err());
+        assert!(Image::rgb8(&[0; 18], 3, 2).is_ok());
+        assert!(Image::rgb8(&[0; 6], 3, 2).is_err());
+    }
+
+    #[test]
+    fn rgb_luma() {
+        let data = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+        let image = Image::rgb8(&data, 4, 1).unwrap();
+        assert_eq!(image.luma(), [76, 150, 29, 255]);
+    }
+
+    #[test]
+    fn fits() {
+        // a 2:1 image in a square
+        let image = Size::new(20, 10);
+        let cases = [
+            (
+                Fit::Stretch,
+                Rectangle::new(Point::new(10, 20), Size::new(40, 40)),
+            ),
+            (
+                Fit::Contain,
+                Rectangle::new(Point::new(10, 30), Size::new(40, 20)),
+            ),
+            (
+                Fit::Cover,
+                Rectangle::new(Point::new(-10, 20), Size::new(80, 40)),
+            ),
+            (
+                Fit::Center,
+                Rectangle::new(Point::new(20, 35), Size::new(20, 10)),
+            ),
+        ];
+        for (fit, expected) in cases {
+            assert_eq!(place(image, bounds(40, 40), fit), expected, "{fit:?}");
+        }
+    }
+
+    #[test]
+    fn scale_averages() {
+        // 4x2 down to 2x1
+        let luma = [0, 100, 200, 200, 100, 200, 0, 0];
+        let placed = Rectangle::new(Point::zero(), Size::new(2, 1));
+        assert_eq!(scale(&luma, Size::new(4, 2), placed, placed, 0), [100, 100]);
+
+        // 2x1 up to 4x1, every source pixel is repeated
+        let placed = Rectangle::new(Point::zero(), Size::new(4, 1));
+        assert_eq!(
+            scale(&[10, 20], Size::new(2, 1), placed, placed, 0),
+            [10, 10, 20, 20]
+        );
+    }
+
+    #[test]
+    fn scale_background() {
+        let placed = Rectangle::new(Point::new(1, 0), Size::new(2, 1));
+        let area = Rectangle::new(Point::zero(), Size::new(4, 1));
+        assert_eq!(
+            scale(&[10, 20], Size::new(2, 1), placed, area, 0xff),
+            [0xff, 10, 20, 0xff]
+        );
+    }
+
+    #[test]
+    fn background() {
+        let image = Image::luma8(&[0; 4], 2, 2).unwrap();
+        let mut target = BitPlaneBuffer::new(Size::new(6, 2), BinaryColor::Off);
+        let options = ImportOptions {
+            fit: Fit::Center,
+            ..THRESHOLD
+        };
+        draw_image(&mut target, &image, &options).unwrap();
+        // the area around the image is untouched
+        assert_eq!(target.plane(0), [0x00, 0x00]);
+
+        target.clear(BinaryColor::Off).unwrap();
+        let white = ImportOptions {
+            background: Some(0xff),
+            ..options
+        };
+        draw_image(&mut target, &image, &white).unwrap();
+        assert_eq!(target.plane(0), [0b1100_1100, 0b1100_1100]);
+    }
+
+    #[test]
+    fn gamma() {
+        let image = Image::luma8(&[96], 1, 1).unwrap();
+        let mut target = BitPlaneBuffer::new(Size::new(1, 1), Gray2::WHITE);
+        draw_image(&mut target, &image, &THRESHOLD).unwrap();
+        assert_eq!(target.pixel(Point::zero()), Some(Gray2::new(1)));
+
+        let light = ImportOptions {
+            gamma: 0.5,
+            ..THRESHOLD
+        };
+        draw_image(&mut target, &image, &light).unwrap();
+        assert_eq!(target.pixel(Point::zero()), Some(Gray2::new(2)));
+
+        for gamma in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let options = ImportOptions { gamma, ..THRESHOLD };
+            assert!(matches!(
+                draw_image(&mut target, &image, &options),
+                Err(ImportError::Config(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn empty_image() {
+        let image = Image::luma8(&[], 0, 0).unwrap();
+        let mut target = BitPlaneBuffer::new(Size::new(8, 1), BinaryColor::On);
+        draw_image(&mut target, &image, &ImportOptions::DEFAULT).unwrap();
+        assert_eq!(target.plane(0), [0xff]);
+    }
+}
@@ -1,2 +1,14 @@
+#[cfg(feature = "async")]
+pub mod async_spi_interface;
+pub mod bit_plane;
+pub mod cascade;
+pub mod dither;
+pub mod epd;
 pub mod error;
+pub mod export;
+pub mod import;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod orientation;
+pub mod refresh;
 pub mod spi_interface;
@@ -0,0 +1,565 @@
+//! A scriptable mock HAL for testing drivers without hardware.
+//!
+//! All handles created from one [`MockHal`] share a single transcript, which records
+//! every SPI transfer (together with the level of the `dc_pin` at that moment),
+//! every output pin transition, every busy pin read and every delay.
+//!
+//! # Examples
+//! ```
+//! # use waveshare_epd_core::{mock::{MockHal, Pin}, spi_interface::SpiInterface};
+//! let hal = MockHal::new();
+//! let mut spi = SpiInterface::new(
+//!     hal.spi(),
+//!     hal.pin(Pin::Rst),
+//!     hal.pin(Pin::Dc),
+//!     None,
+//!     hal.busy(),
+//!     hal.pin(Pin::Pwr),
+//!     hal.delay(),
+//! );
+//!
+//! // The busy pin reports "busy" twice, then "idle".
+//! hal.script_busy([true, true]);
+//!
+//! spi.command_data(0x22, [0xc7], 4096).unwrap();
+//! spi.command(0x20).unwrap();
+//!
+//! hal.assert_command(0x22, [0xc7]);
+//! hal.assert_sequence([(0x22, &[0xc7][..]), (0x20, &[][..])]);
+//! ```
+
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    fmt::Debug,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use embedded_hal::{
+    delay::DelayNs,
+    digital::{ErrorType as DigitalErrorType, InputPin, OutputPin},
+    spi::{ErrorType as SpiErrorType, Operation, SpiDevice},
+};
+
+/// Output pins of [`SpiInterface`](crate::spi_interface::SpiInterface).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pin {
+    Rst,
+    Dc,
+    Cs,
+    Pwr,
+}
+
+/// A single recorded event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// An output pin was set, `true` for high level.
+    Pin(Pin, bool),
+    /// The busy pin was read, `true` for busy.
+    Busy(bool),
+    /// Bytes were written, `dc` is the level of the `dc_pin` during the write.
+    Write { dc: bool, data: Vec<u8> },
+    /// Bytes were read, `dc` is the level of the `dc_pin` during the read.
+    Read { dc: bool, len: usize },
+    /// A delay was requested.
+    Delay(Duration),
+}
+
+/// A command followed by its data, rebuilt from [`Event::Write`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub cmd: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    events: Vec<Event>,
+    dc: bool,
+    busy_script: VecDeque<bool>,
+    busy_idle: bool,
+}
+
+/// The shared transcript and the factory of all mock handles.
+#[derive(Debug, Clone, Default)]
+pub struct MockHal {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockHal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn spi(&self) -> MockSpi {
+        MockSpi { hal: self.clone() }
+    }
+
+    pub fn pin(&self, pin: Pin) -> MockOutputPin {
+        MockOutputPin {
+            hal: self.clone(),
+            pin,
+        }
+    }
+
+    pub fn busy(&self) -> MockBusyPin {
+        MockBusyPin { hal: self.clone() }
+    }
+
+    pub fn delay(&self) -> MockDelay {
+        MockDelay { hal: self.clone() }
+    }
+
+    /// Queue levels returned by the next reads of the busy pin, `true` for busy.
+    ///
+    /// Once the script is exhausted, the pin returns the idle level (see [`Self::set_busy_idle`]).
+    pub fn script_busy(&self, levels: impl IntoIterator<Item = bool>) {
+        self.lock().busy_script.extend(levels);
+    }
+
+    /// The level returned when the busy script is empty, defaults to `false` (not busy).
+    pub fn set_busy_idle(&self, busy: bool) {
+        self.lock().busy_idle = busy;
+    }
+
+    /// All recorded events.
+    pub fn events(&self) -> Vec<Event> {
+        self.lock().events.clone()
+    }
+
+    /// Forget all recorded events, the busy script is kept.
+    pub fn clear(&self) {
+        self.lock().events.clear();
+    }
+
+    /// All commands with their data, in the order they were sent.
+    ///
+    /// Data written before any command is ignored.
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands: Vec<Command> = Vec::new();
+        for event in self.lock().events.iter() {
+            let Event::Write { dc, data } = event else {
+                continue;
+            };
+            if *dc {
+                if let Some(last) = commands.last_mut() {
+                    last.data.extend_from_slice(data);
+                }
+            } else {
+                commands.extend(data.iter().map(|&cmd| Command {
+                    cmd,
+                    data: Vec::new(),
+                }));
+            }
+        }
+        commands
+    }
+
+    /// All output pin transitions of `pin`.
+    pub fn pin_levels(&self, pin: Pin) -> Vec<bool> {
+        self.lock()
+            .events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Pin(p, level) if *p == pin => Some(*level),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Sum of all recorded delays.
+    pub fn total_delay(&self) -> Duration {
+        self.lock()
+            .events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Delay(d) => Some(*d),
+                _ => None,
+            })
+            .sum()
+    }
+
+    /// Panics if `cmd` has never been sent with exactly `data`.
+    #[track_caller]
+    pub fn assert_command(&self, cmd: u8, data: impl AsRef<[u8]>) {
+        let data = data.as_ref();
+        let commands = self.commands();
+        if !commands.iter().any(|c| c.cmd == cmd && c.data == data) {
+            panic!(
+                "expected command {:#04x} with data {:#04x?}, sent: {:#04x?}",
+                cmd, data, commands
+            );
+        }
+    }
+
+    /// Panics if `cmd` has ever been sent.
+    #[track_caller]
+    pub fn assert_no_command(&self, cmd: u8) {
+        let commands = self.commands();
+        if let Some(c) = commands.iter().find(|c| c.cmd == cmd) {
+            panic!("unexpected command {:#04x} with data {:#04x?}", cmd, c.data);
+        }
+    }
+
+    /// Panics unless `expected` was sent in this order, other commands may be interleaved.
+    #[track_caller]
+    pub fn assert_sequence<'a>(&self, expected: impl IntoIterator<Item = (u8, &'a [u8])>) {
+        let commands = self.commands();
+        let mut sent = commands.iter();
+        for (cmd, data) in expected {
+            if !sent.any(|c| c.cmd == cmd && c.data == data) {
+                panic!(
+                    "expected command {:#04x} with data {:#04x?} in sequence, sent: {:#04x?}",
+                    cmd, data, commands
+                );
+            }
+        }
+    }
+}
+
+/// A recording [`SpiDevice`], reads return zeros.
+#[derive(Debug, Clone)]
+pub struct MockSpi {
+    hal: MockHal,
+}
+
+impl SpiErrorType for MockSpi {
+    type Error = Infallible;
+}
+
+impl SpiDevice for MockSpi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let mut state = self.hal.lock();
+        let dc = state.dc;
+        for op in operations {
+            match op {
+                Operation::Write(data) => state.events.push(Event::Write {
+                    dc,
+                    data: data.to_vec(),
+                }),
+                Operation::Read(buf) => {
+                    buf.fill(0);
+                    state.events.push(Event::Read { dc, len: buf.len() });
+                }
+                Operation::Transfer(read, write) => {
+                    read.fill(0);
+                    state.events.push(Event::Write {
+                        dc,
+                        data: write.to_vec(),
+                    });
+                    state.events.push(Event::Read {
+                        dc,
+                        len: read.len(),
+                    });
+                }
+                Operation::TransferInPlace(buf) => {
+                    state.events.push(Event::Write {
+                        dc,
+                        data: buf.to_vec(),
+                    });
+                    buf.fill(0);
+                    state.events.push(Event::Read { dc, len: buf.len() });
+                }
+                Operation::DelayNs(ns) => state
+                    .events
+                    .push(Event::Delay(Duration::from_nanos(*ns as u64))),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A recording [`OutputPin`].
+#[derive(Debug, Clone)]
+pub struct MockOutputPin {
+    hal: MockHal,
+    pin: Pin,
+}
+
+impl MockOutputPin {
+    fn set(&mut self, level: bool) {
+        let mut state = self.hal.lock();
+        if self.pin == Pin::Dc {
+            state.dc = level;
+        }
+        state.events.push(Event::Pin(self.pin, level));
+    }
+}
+
+impl DigitalErrorType for MockOutputPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for MockOutputPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set(true);
+        Ok(())
+    }
+}
+
+/// A scripted busy [`InputPin`], high level for busy.
+#[derive(Debug, Clone)]
+pub struct MockBusyPin {
+    hal: MockHal,
+}
+
+impl MockBusyPin {
+    fn read(&mut self) -> bool {
+        let mut state = self.hal.lock();
+        let busy = state.busy_script.pop_front().unwrap_or(state.busy_idle);
+        state.events.push(Event::Busy(busy));
+        busy
+    }
+}
+
+impl DigitalErrorType for MockBusyPin {
+    type Error = Infallible;
+}
+
+impl InputPin for MockBusyPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.read())
+    }
+}
+
+/// A recording [`DelayNs`], it never sleeps.
+#[derive(Debug, Clone)]
+pub struct MockDelay {
+    hal: MockHal,
+}
+
+impl MockDelay {
+    fn record(&mut self, delay: Duration) {
+        self.hal.lock().events.push(Event::Delay(delay));
+    }
+}
+
+impl DelayNs for MockDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.record(Duration::from_nanos(ns as u64));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.record(Duration::from_micros(us as u64));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.record(Duration::from_millis(ms as u64));
+    }
+}
+
+#[cfg(feature = "async")]
+mod asynch {
+    use embedded_hal_async::{
+        delay::DelayNs,
+        digital::Wait,
+        spi::{Operation, SpiDevice},
+    };
+
+    use super::{MockBusyPin, MockDelay, MockSpi};
+
+    impl SpiDevice for MockSpi {
+        async fn transaction(
+            &mut self,
+            operations: &mut [Operation<'_, u8>],
+        ) -> Result<(), Self::Error> {
+            embedded_hal::spi::SpiDevice::transaction(self, operations)
+        }
+    }
+
+    /// Waiting consumes the busy script like repeated reads,
+    /// waiting for idle while the idle level is busy never completes.
+    impl Wait for MockBusyPin {
+        async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+            self.wait_for(true).await;
+            Ok(())
+        }
+
+        async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+            self.wait_for(false).await;
+            Ok(())
+        }
+
+        async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+            self.wait_for(false).await;
+            self.wait_for(true).await;
+            Ok(())
+        }
+
+        async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+            self.wait_for(true).await;
+            self.wait_for(false).await;
+            Ok(())
+        }
+
+        async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+            let level = self.read();
+            self.wait_for(!level).await;
+            Ok(())
+        }
+    }
+
+    impl MockBusyPin {
+        async fn wait_for(&mut self, busy: bool) {
+            loop {
+                if self.read() == busy {
+                    return;
+                }
+                let stuck = {
+                    let state = self.hal.lock();
+                    state.busy_script.is_empty() && state.busy_idle != busy
+                };
+                if stuck {
+                    // The level will never change.
+                    std::future::pending::<()>().await;
+                }
+            }
+        }
+    }
+
+    impl DelayNs for MockDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            embedded_hal::delay::DelayNs::delay_ns(self, ns);
+        }
+
+        async fn delay_us(&mut self, us: u32) {
+            embedded_hal::delay::DelayNs::delay_us(self, us);
+        }
+
+        async fn delay_ms(&mut self, ms: u32) {
+            embedded_hal::delay::DelayNs::delay_ms(self, ms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spi_interface::{DelayStep, SpiInterface};
+
+    use super::*;
+
+    type MockInterface = SpiInterface<MockSpi, MockBusyPin, MockOutputPin, MockDelay>;
+
+    fn interface(hal: &MockHal) -> MockInterface {
+        SpiInterface::new(
+            hal.spi(),
+            hal.pin(Pin::Rst),
+            hal.pin(Pin::Dc),
+            Some(hal.pin(Pin::Cs)),
+            hal.busy(),
+            hal.pin(Pin::Pwr),
+            hal.delay(),
+        )
+    }
+
+    #[test]
+    fn commands() {
+        let hal = MockHal::new();
+        let mut spi = interface(&hal);
+        spi.data([0xff], 1).unwrap();
+        spi.command_data(0x24, [1, 2, 3, 4, 5], 2).unwrap();
+        spi.command(0x20).unwrap();
+
+        // the data before the first command is dropped, chunks are joined
+        let commands = hal.commands();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].cmd, 0x24);
+        assert_eq!(commands[0].data, [1, 2, 3, 4, 5]);
+        assert_eq!(commands[1].cmd, 0x20);
+        assert!(commands[1].data.is_empty());
+        assert!(hal.events().contains(&Event::Write {
+            dc: true,
+            data: vec![1, 2]
+        }));
+
+        hal.clear();
+        assert!(hal.commands().is_empty());
+    }
+
+    #[test]
+    fn assert_sequence() {
+        let hal = MockHal::new();
+        let mut spi = interface(&hal);
+        spi.command_data(0x11, [0x01], 4096).unwrap();
+        spi.command_data(0x22, [0xc7], 4096).unwrap();
+        spi.command(0x20).unwrap();
+
+        hal.assert_command(0x22, [0xc7]);
+        hal.assert_no_command(0x10);
+        // other commands may be interleaved
+        hal.assert_sequence([(0x11, &[0x01][..]), (0x20, &[])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "in sequence")]
+    fn assert_sequence_order() {
+        let hal = MockHal::new();
+        let mut spi = interface(&hal);
+        spi.command_data(0x22, [0xc7], 4096).unwrap();
+        spi.command(0x20).unwrap();
+        hal.assert_sequence([(0x20, &[][..]), (0x22, &[0xc7])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected command 0x22")]
+    fn assert_command_data() {
+        let hal = MockHal::new();
+        interface(&hal).command_data(0x22, [0xc7], 4096).unwrap();
+        hal.assert_command(0x22, [0xf7]);
+    }
+
+    #[test]
+    fn script_busy() {
+        let hal = MockHal::new();
+        let mut spi = interface(&hal);
+        hal.script_busy([true, true]);
+        spi.wait_busy_timeout(DelayStep::Ms(1), Duration::from_secs(1))
+            .unwrap();
+
+        let reads: Vec<_> = hal
+            .events()
+            .into_iter()
+            .filter(|e| matches!(e, Event::Busy(_)))
+            .collect();
+        assert_eq!(
+            reads,
+            [Event::Busy(true), Event::Busy(true), Event::Busy(false)]
+        );
+        // polled twice
+        assert_eq!(hal.total_delay(), Duration::from_millis(2));
+
+        hal.set_busy_idle(true);
+        assert!(spi
+            .wait_busy_timeout(DelayStep::Us(1), Duration::from_millis(1))
+            .is_err());
+    }
+
+    #[test]
+    fn pin_levels() {
+        let hal = MockHal::new();
+        let mut spi = interface(&hal);
+        spi.set_power(true).unwrap();
+        spi.set_rst_pin(false).unwrap();
+        spi.set_rst_pin(true).unwrap();
+        spi.command(0x12).unwrap();
+
+        assert_eq!(hal.pin_levels(Pin::Pwr), [true]);
+        assert_eq!(hal.pin_levels(Pin::Rst), [false, true]);
+        assert_eq!(hal.pin_levels(Pin::Dc), [false]);
+        assert_eq!(hal.pin_levels(Pin::Cs), [true, false]);
+    }
+}
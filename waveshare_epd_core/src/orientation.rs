@@ -0,0 +1,242 @@
+//! Drawing on a panel that is mounted rotated or mirrored.
+//!
+//! An [`Orientation`] maps the logical coordinates a program draws in to the native
+//! coordinates of the panel. The logical image is first mirrored, then rotated clockwise.
+//!
+//! # Examples
+//! ```
+//! # use embedded_graphics_core::{geometry::{Point, Size}, primitives::Rectangle};
+//! # use waveshare_epd_core::orientation::{Orientation, Rotation};
+//! let native = Size::new(792, 272);
+//! let portrait = Orientation {
+//!     rotation: Rotation::Deg90,
+//!     ..Orientation::DEFAULT
+//! };
+//! assert_eq!(portrait.size(native), Size::new(272, 792));
+//! // the top left corner of the image is the top right corner of the panel
+//! assert_eq!(portrait.to_native(Point::zero(), native), Point::new(791, 0));
+//! assert_eq!(portrait.from_native(Point::new(791, 0), native), Point::zero());
+//!
+//! let area = Rectangle::new(Point::new(0, 0), Size::new(10, 20));
+//! assert_eq!(
+//!     portrait.rect_to_native(&area, native),
+//!     Rectangle::new(Point::new(772, 0), Size::new(20, 10))
+//! );
+//! ```
+
+use embedded_graphics_core::{
+    geometry::{Point, Size},
+    primitives::Rectangle,
+};
+
+/// Clockwise rotation of the image on the panel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    /// Whether width and height are swapped.
+    pub fn is_portrait(self) -> bool {
+        matches!(self, Self::Deg90 | Self::Deg270)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    /// Mirror the logical image left to right.
+    pub mirror_x: bool,
+    /// Mirror the logical image top to bottom.
+    pub mirror_y: bool,
+}
+
+impl Orientation {
+    /// The native orientation of the panel.
+    pub const DEFAULT: Self = Self {
+        rotation: Rotation::Deg0,
+        mirror_x: false,
+        mirror_y: false,
+    };
+
+    /// Whether logical and native coordinates are the same.
+    pub fn is_native(self) -> bool {
+        self == Self::DEFAULT
+    }
+
+    /// The logical size of a panel of `native` size.
+    pub fn size(self, native: Size) -> Size {
+        if self.rotation.is_portrait() {
+            Size::new(native.height, native.width)
+        } else {
+            native
+        }
+    }
+
+    /// The native point of the logical `point`.
+    ///
+    /// Points outside of the screen stay outside.
+    pub fn to_native(self, point: Point, native: Size) -> Point {
+        let (w, h) = (native.width as i32, native.height as i32);
+        let logical = self.size(native);
+        let mut p = point;
+        if self.mirror_x {
+            p.x = logical.width as i32 - 1 - p.x;
+        }
+        if self.mirror_y {
+            p.y = logical.height as i32 - 1 - p.y;
+        }
+        match self.rotation {
+            Rotation::Deg0 => p,
+            Rotation::Deg90 => Point::new(w - 1 - p.y, p.x),
+            Rotation::Deg180 => Point::new(w - 1 - p.x, h - 1 - p.y),
+            Rotation::Deg270 => Point::new(p.y, h - 1 - p.x),
+        }
+    }
+
+    /// The logical point of the native `point`, the inverse of [`to_native`](Self::to_native).
+    pub fn from_native(self, point: Point, native: Size) -> Point {
+        let (w, h) = (native.width as i32, native.height as i32);
+        let logical = self.size(native);
+        let mut p = match self.rotation {
+            Rotation::Deg0 => point,
+            Rotation::Deg90 => Point::new(point.y, w - 1 - point.x),
+            Rotation::Deg180 => Point::new(w - 1 - point.x, h - 1 - point.y),
+            Rotation::Deg270 => Point::new(h - 1 - point.y, point.x),
+        };
+        if self.mirror_x {
+            p.x = logical.width as i32 - 1 - p.x;
+        }
+        if self.mirror_y {
+            p.y = logical.height as i32 - 1 - p.y;
+        }
+        p
+    }
+
+    /// The native area covered by the logical `area`.
+    pub fn rect_to_native(self, area: &Rectangle, native: Size) -> Rectangle {
+        Self::map_rect(area, |p| self.to_native(p, native))
+    }
+
+    /// The logical area covered by the native `area`.
+    pub fn rect_from_native(self, area: &Rectangle, native: Size) -> Rectangle {
+        Self::map_rect(area, |p| self.from_native(p, native))
+    }
+
+    fn map_rect(area: &Rectangle, f: impl Fn(Point) -> Point) -> Rectangle {
+        match area.bottom_right() {
+            Some(bottom_right) => Rectangle::with_corners(f(area.top_left), f(bottom_right)),
+            None => Rectangle::new(f(area.top_left), Size::zero()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics_core::primitives::PointsIter;
+
+    use super::*;
+
+    const NATIVE: Size = Size::new(5, 3);
+    const ROTATIONS: [Rotation; 4] = [
+        Rotation::Deg0,
+        Rotation::Deg90,
+        Rotation::Deg180,
+        Rotation::Deg270,
+    ];
+
+    fn all() -> impl Iterator<Item = Orientation> {
+        ROTATIONS.into_iter().flat_map(|rotation| {
+            [(false, false), (true, false), (false, true), (true, true)].map(
+                |(mirror_x, mirror_y)| Orientation {
+                    rotation,
+                    mirror_x,
+                    mirror_y,
+                },
+            )
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        for orientation in all() {
+            let logical = Rectangle::new(Point::zero(), orientation.size(NATIVE));
+            let mut native: Vec<_> = logical
+                .points()
+                .map(|p| orientation.to_native(p, NATIVE))
+                .collect();
+            for (p, n) in logical.points().zip(&native) {
+                assert_eq!(orientation.from_native(*n, NATIVE), p, "{orientation:?}");
+            }
+            // every native pixel is covered once
+            native.sort_by_key(|p| (p.y, p.x));
+            let expected: Vec<_> = Rectangle::new(Point::zero(), NATIVE).points().collect();
+            assert_eq!(native, expected, "{orientation:?}");
+        }
+    }
+
+    #[test]
+    fn top_left_corner() {
+        let corner = |rotation, mirror_x, mirror_y| {
+            let orientation = Orientation {
+                rotation,
+                mirror_x,
+                mirror_y,
+            };
+            orientation.to_native(Point::zero(), NATIVE)
+        };
+        assert_eq!(corner(Rotation::Deg0, false, false), Point::new(0, 0));
+        assert_eq!(corner(Rotation::Deg90, false, false), Point::new(4, 0));
+        assert_eq!(corner(Rotation::Deg180, false, false), Point::new(4, 2));
+        assert_eq!(corner(Rotation::Deg270, false, false), Point::new(0, 2));
+        assert_eq!(corner(Rotation::Deg0, true, false), Point::new(4, 0));
+        assert_eq!(corner(Rotation::Deg0, false, true), Point::new(0, 2));
+        // mirrored first, then rotated
+        assert_eq!(corner(Rotation::Deg90, true, false), Point::new(4, 2));
+        assert_eq!(corner(Rotation::Deg90, false, true), Point::new(0, 0));
+    }
+
+    #[test]
+    fn outside_stays_outside() {
+        let screen = Rectangle::new(Point::zero(), NATIVE);
+        for orientation in all() {
+            for p in [Point::new(-1, 0), Point::new(0, -1), Point::new(7, 7)] {
+                assert!(!screen.contains(orientation.to_native(p, NATIVE)));
+            }
+        }
+    }
+
+    #[test]
+    fn rects() {
+        for orientation in all() {
+            let logical = Rectangle::new(Point::new(1, 0), Size::new(2, 3));
+            let native = orientation.rect_to_native(&logical, NATIVE);
+            assert_eq!(native.size.width * native.size.height, 6);
+            assert!(logical
+                .points()
+                .all(|p| native.contains(orientation.to_native(p, NATIVE))));
+            assert_eq!(orientation.rect_from_native(&native, NATIVE), logical);
+
+            let empty = Rectangle::new(Point::new(1, 1), Size::zero());
+            assert!(orientation.rect_to_native(&empty, NATIVE).is_zero_sized());
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        for orientation in all() {
+            let size = orientation.size(NATIVE);
+            assert_eq!(size == NATIVE, !orientation.rotation.is_portrait());
+        }
+        assert!(Orientation::DEFAULT.is_native());
+        assert!(!Orientation {
+            mirror_y: true,
+            ..Orientation::DEFAULT
+        }
+        .is_native());
+    }
+}
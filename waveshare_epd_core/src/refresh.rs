@@ -0,0 +1,204 @@
+//! Choosing the refresh mode automatically.
+//!
+//! Partial refreshes do not flash but leave ghosting behind, which only a full refresh removes.
+//! A [`RefreshPolicy`] decides from the [`RefreshStats`] of a driver when that is due.
+//!
+//! # Examples
+//! ```
+//! # use std::time::Duration;
+//! # use waveshare_epd_core::{epd::RefreshMode, refresh::{RefreshPolicy, RefreshStats}};
+//! let policy = RefreshPolicy {
+//!     full_every: Some(2),
+//!     full_after: Some(Duration::from_secs(600)),
+//!     fast_above: Some(0.5),
+//! };
+//! let mut stats = RefreshStats::new();
+//! // the first refresh is always full
+//! assert_eq!(policy.choose(&stats, 0.1), RefreshMode::Full);
+//! stats.record(RefreshMode::Full);
+//! assert_eq!(policy.choose(&stats, 0.1), RefreshMode::Partial);
+//! assert_eq!(policy.choose(&stats, 0.8), RefreshMode::Fast);
+//! stats.record(RefreshMode::Partial);
+//! stats.record(RefreshMode::Partial);
+//! assert_eq!(policy.choose(&stats, 0.1), RefreshMode::Full);
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::epd::RefreshMode;
+
+/// Rules for picking a [`RefreshMode`], a rule set to `None` is disabled.
+///
+/// A full refresh is chosen if the panel has not had one yet or if `full_every` or `full_after`
+/// is reached, otherwise a fast refresh if more than `fast_above` of the screen changed,
+/// otherwise a partial refresh.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RefreshPolicy {
+    /// Full refresh after this many partial refreshes since the last full or fast refresh.
+    pub full_every: Option<u32>,
+    /// Full refresh if the last one is older than this.
+    pub full_after: Option<Duration>,
+    /// Fast refresh if the changed area exceeds this fraction of the screen, in `0.0..=1.0`.
+    pub fast_above: Option<f32>,
+}
+
+impl RefreshPolicy {
+    /// Full every 10 partials or every hour, fast above half of the screen.
+    pub const DEFAULT: Self = Self {
+        full_every: Some(10),
+        full_after: Some(Duration::from_secs(60 * 60)),
+        fast_above: Some(0.5),
+    };
+
+    /// Always use full refresh.
+    pub const ALWAYS_FULL: Self = Self {
+        full_every: Some(0),
+        full_after: None,
+        fast_above: None,
+    };
+
+    /// `changed` is the fraction of the screen that changed since the last refresh.
+    pub fn choose(&self, stats: &RefreshStats, changed: f32) -> RefreshMode {
+        let Some(last_full) = stats.last_full else {
+            return RefreshMode::Full;
+        };
+        if self
+            .full_every
+            .is_some_and(|n| stats.partials_since_full >= n)
+        {
+            return RefreshMode::Full;
+        }
+        if self.full_after.is_some_and(|t| last_full.elapsed() >= t) {
+            return RefreshMode::Full;
+        }
+        if self.fast_above.is_some_and(|ratio| changed > ratio) {
+            return RefreshMode::Fast;
+        }
+        RefreshMode::Partial
+    }
+}
+
+impl Default for RefreshPolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// What a driver has refreshed so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RefreshStats {
+    /// Partial refreshes since the last full or fast refresh.
+    pub partials_since_full: u32,
+    /// The last full refresh.
+    pub last_full: Option<Instant>,
+    /// Refreshes of any kind.
+    pub total: u64,
+}
+
+impl RefreshStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, mode: RefreshMode) {
+        match mode {
+            RefreshMode::Full => {
+                self.partials_since_full = 0;
+                self.last_full = Some(Instant::now());
+            }
+            RefreshMode::Fast => self.partials_since_full = 0,
+            RefreshMode::Partial => self.partials_since_full += 1,
+        }
+        self.total += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after_full(partials: u32, ago: Duration) -> RefreshStats {
+        RefreshStats {
+            partials_since_full: partials,
+            last_full: Instant::now().checked_sub(ago),
+            total: 1 + partials as u64,
+        }
+    }
+
+    #[test]
+    fn first_refresh_is_full() {
+        let stats = RefreshStats::new();
+        assert_eq!(
+            RefreshPolicy::DEFAULT.choose(&stats, 0.0),
+            RefreshMode::Full
+        );
+        let never = RefreshPolicy {
+            full_every: None,
+            full_after: None,
+            fast_above: None,
+        };
+        assert_eq!(never.choose(&stats, 1.0), RefreshMode::Full);
+    }
+
+    #[test]
+    fn full_every() {
+        let policy = RefreshPolicy::DEFAULT;
+        let stats = after_full(9, Duration::ZERO);
+        assert_eq!(policy.choose(&stats, 0.1), RefreshMode::Partial);
+        let stats = after_full(10, Duration::ZERO);
+        assert_eq!(policy.choose(&stats, 0.1), RefreshMode::Full);
+    }
+
+    #[test]
+    fn full_after() {
+        let policy = RefreshPolicy {
+            full_after: Some(Duration::from_secs(10)),
+            ..RefreshPolicy::DEFAULT
+        };
+        let stats = after_full(0, Duration::ZERO);
+        assert_eq!(policy.choose(&stats, 0.1), RefreshMode::Partial);
+        let stats = after_full(0, Duration::from_secs(10));
+        assert_eq!(policy.choose(&stats, 0.1), RefreshMode::Full);
+    }
+
+    #[test]
+    fn fast_above() {
+        let policy = RefreshPolicy::DEFAULT;
+        let stats = after_full(0, Duration::ZERO);
+        assert_eq!(policy.choose(&stats, 0.5), RefreshMode::Partial);
+        assert_eq!(policy.choose(&stats, 0.51), RefreshMode::Fast);
+        // full takes precedence
+        let stats = after_full(10, Duration::ZERO);
+        assert_eq!(policy.choose(&stats, 1.0), RefreshMode::Full);
+    }
+
+    #[test]
+    fn disabled_rules() {
+        let policy = RefreshPolicy {
+            full_every: None,
+            full_after: None,
+            fast_above: None,
+        };
+        let stats = after_full(1000, Duration::from_secs(10));
+        assert_eq!(policy.choose(&stats, 1.0), RefreshMode::Partial);
+        assert_eq!(
+            RefreshPolicy::ALWAYS_FULL.choose(&stats, 0.0),
+            RefreshMode::Full
+        );
+    }
+
+    #[test]
+    fn record() {
+        let mut stats = RefreshStats::new();
+        stats.record(RefreshMode::Partial);
+        stats.record(RefreshMode::Partial);
+        assert_eq!(stats.partials_since_full, 2);
+        assert_eq!(stats.last_full, None);
+        stats.record(RefreshMode::Fast);
+        assert_eq!(stats.partials_since_full, 0);
+        assert_eq!(stats.last_full, None);
+        stats.record(RefreshMode::Full);
+        assert!(stats.last_full.is_some());
+        assert_eq!(stats.total, 4);
+    }
+}
@@ -10,19 +10,39 @@
 
 use std::{
     fmt::Debug,
-    marker::PhantomData,
     time::{Duration, Instant},
 };
 
-use crate::error::TimeOutError;
+use crate::error::{Error, TimeOutError};
 use embedded_hal::{
     delay::DelayNs,
-    digital::{InputPin, OutputPin},
-    spi::SpiDevice,
+    digital::{self, InputPin, OutputPin},
+    spi::{self, SpiDevice},
 };
 
+/// The [`Error`] returned by [`SpiInterface`].
+pub type SpiInterfaceError<Spi, I, O> = Error<
+    <Spi as spi::ErrorType>::Error,
+    <O as digital::ErrorType>::Error,
+    <I as digital::ErrorType>::Error,
+>;
+
+/// Busy pins that can block until the busy line goes idle, instead of being polled.
+pub trait WaitIdle: InputPin {
+    /// Block until the busy line is idle (low level), at most `timeout`.
+    ///
+    /// Returns `Ok(Some(false))` on timeout, and `Ok(None)` if blocking is not available,
+    /// in which case the caller falls back to polling.
+    fn wait_idle(&mut self, timeout: Duration) -> Result<Option<bool>, Self::Error>;
+}
+
+type WaitIdleFn<I> = fn(&mut I, Duration) -> Result<Option<bool>, <I as digital::ErrorType>::Error>;
+
 /// A common SPI interface uses [embedded-hal](https://docs.rs/embedded-hal/latest/embedded_hal/).
-pub struct SpiInterface<Spi, I, O, D, E> {
+pub struct SpiInterface<Spi, I, O, D>
+where
+    I: digital::ErrorType,
+{
     spi: Spi,
     rst_pin: O,
     dc_pin: O,
@@ -32,22 +52,24 @@ pub struct SpiInterface<Spi, I, O, D, E> {
 
     delay: D,
 
-    marker: PhantomData<E>,
+    wait_idle: Option<WaitIdleFn<I>>,
 }
 
-impl<Spi, I, O, D, E> Debug for SpiInterface<Spi, I, O, D, E> {
+impl<Spi, I, O, D> Debug for SpiInterface<Spi, I, O, D>
+where
+    I: digital::ErrorType,
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SpiInterface").finish_non_exhaustive()
     }
 }
 
-impl<Spi, I, O, D, E> SpiInterface<Spi, I, O, D, E>
+impl<Spi, I, O, D> SpiInterface<Spi, I, O, D>
 where
     Spi: SpiDevice,
     I: InputPin,
     O: OutputPin,
     D: DelayNs,
-    E: From<Spi::Error> + From<I::Error> + From<O::Error>,
 {
     pub fn new(
         spi: Spi,
@@ -67,30 +89,38 @@ where
             busy_pin,
             pwr_pin,
             delay,
-            marker: PhantomData,
+            wait_idle: None,
         }
     }
 
-    fn set_cs(&mut self, active: bool) -> Result<(), E> {
+    /// Wait for the busy pin with [`WaitIdle`] instead of polling it.
+    pub fn enable_wait_idle(&mut self)
+    where
+        I: WaitIdle,
+    {
+        self.wait_idle = Some(I::wait_idle);
+    }
+
+    fn set_cs(&mut self, active: bool) -> Result<(), SpiInterfaceError<Spi, I, O>> {
         if let Some(cs) = self.cs_pin.as_mut() {
             if active {
-                cs.set_high()?;
+                cs.set_high().map_err(Error::Pin)?;
             } else {
-                cs.set_low()?;
+                cs.set_low().map_err(Error::Pin)?;
             }
         }
         Ok(())
     }
 
-    pub fn is_busy(&mut self) -> Result<bool, E> {
-        Ok(self.busy_pin.is_high()?)
+    pub fn is_busy(&mut self) -> Result<bool, SpiInterfaceError<Spi, I, O>> {
+        self.busy_pin.is_high().map_err(Error::BusyPin)
     }
 
-    pub fn set_rst_pin(&mut self, active: bool) -> Result<(), E> {
+    pub fn set_rst_pin(&mut self, active: bool) -> Result<(), SpiInterfaceError<Spi, I, O>> {
         if active {
-            self.rst_pin.set_high()?;
+            self.rst_pin.set_high().map_err(Error::Pin)?;
         } else {
-            self.rst_pin.set_low()?;
+            self.rst_pin.set_low().map_err(Error::Pin)?;
         }
         Ok(())
     }
@@ -103,15 +133,33 @@ where
         }
     }
 
-    pub fn wait_busy_timeout(&mut self, delay: DelayStep, timeout: Duration) -> Result<Duration, E>
-    where
-        E: From<TimeOutError>,
-    {
+    pub fn wait_busy_timeout(
+        &mut self,
+        delay: DelayStep,
+        timeout: Duration,
+    ) -> Result<Duration, SpiInterfaceError<Spi, I, O>> {
         let now = Instant::now();
         if !self.is_busy()? {
             return Ok(now.elapsed());
         }
 
+        if let Some(wait_idle) = self.wait_idle {
+            let idle = wait_idle(&mut self.busy_pin, timeout.saturating_sub(now.elapsed()))
+                .map_err(Error::BusyPin)?;
+            match idle {
+                Some(true) => return Ok(now.elapsed()),
+                Some(false) => {
+                    return Err(TimeOutError {
+                        timeout,
+                        elapsed: now.elapsed(),
+                    }
+                    .into())
+                }
+                // not available, poll instead
+                None => {}
+            }
+        }
+
         let delay = delay.max_one();
         while now.elapsed() < timeout {
             self.delay(delay);
@@ -127,24 +175,28 @@ where
         .into())
     }
 
-    pub fn command(&mut self, cmd: u8) -> Result<(), E> {
+    pub fn command(&mut self, cmd: u8) -> Result<(), SpiInterfaceError<Spi, I, O>> {
         self.set_cs(true)?;
-        self.dc_pin.set_low()?;
-        self.spi.write(&[cmd])?;
+        self.dc_pin.set_low().map_err(Error::Pin)?;
+        self.spi.write(&[cmd]).map_err(Error::Spi)?;
         self.set_cs(false)?;
         Ok(())
     }
 
-    pub fn data(&mut self, data: impl AsRef<[u8]>, chunk_size: usize) -> Result<(), E> {
+    pub fn data(
+        &mut self,
+        data: impl AsRef<[u8]>,
+        chunk_size: usize,
+    ) -> Result<(), SpiInterfaceError<Spi, I, O>> {
         let data = data.as_ref();
         let chunk_size = chunk_size.max(1);
         if data.is_empty() {
             return Ok(());
         }
         self.set_cs(true)?;
-        self.dc_pin.set_high()?;
+        self.dc_pin.set_high().map_err(Error::Pin)?;
         for chunk in data.chunks(chunk_size) {
-            self.spi.write(chunk)?;
+            self.spi.write(chunk).map_err(Error::Spi)?;
         }
         self.set_cs(false)?;
         Ok(())
@@ -155,23 +207,43 @@ where
         cmd: u8,
         data: impl AsRef<[u8]>,
         chunk_size: usize,
-    ) -> Result<(), E> {
+    ) -> Result<(), SpiInterfaceError<Spi, I, O>> {
         self.command(cmd)?;
         self.data(data, chunk_size)?;
         Ok(())
     }
 
-    pub fn set_power(&mut self, on: bool) -> Result<(), E> {
+    /// Send `cmd` and read `buf.len()` data bytes from the controller.
+    ///
+    /// The SPI device must be able to read the data line of the controller,
+    /// e.g. a 3-wire (half-duplex) bus, or MISO wired to it.
+    pub fn command_read(
+        &mut self,
+        cmd: u8,
+        buf: &mut [u8],
+    ) -> Result<(), SpiInterfaceError<Spi, I, O>> {
+        self.command(cmd)?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        self.set_cs(true)?;
+        self.dc_pin.set_high().map_err(Error::Pin)?;
+        self.spi.read(buf).map_err(Error::Spi)?;
+        self.set_cs(false)?;
+        Ok(())
+    }
+
+    pub fn set_power(&mut self, on: bool) -> Result<(), SpiInterfaceError<Spi, I, O>> {
         if on {
-            self.pwr_pin.set_high()?;
+            self.pwr_pin.set_high().map_err(Error::Pin)?;
         } else {
-            self.pwr_pin.set_low()?;
+            self.pwr_pin.set_low().map_err(Error::Pin)?;
         }
         Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct PinDefinition {
     pub rst_pin: u32,
     pub dc_pin: u32,
@@ -217,6 +289,14 @@ pub enum DelayStep {
 }
 
 impl DelayStep {
+    pub fn as_duration(self) -> Duration {
+        match self {
+            Self::Ns(ns) => Duration::from_nanos(ns as u64),
+            Self::Us(us) => Duration::from_micros(us as u64),
+            Self::Ms(ms) => Duration::from_millis(ms as u64),
+        }
+    }
+
     fn max_one(self) -> Self {
         match self {
             Self::Ns(ns) => Self::Ns(ns.max(1)),